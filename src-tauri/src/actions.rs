@@ -0,0 +1,70 @@
+use crate::config::TunnelConfig;
+use log::{info, warn};
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_shell::ShellExt;
+
+fn expand_placeholders(value: &str, tunnel: &TunnelConfig) -> String {
+    value
+        .replace("{localPort}", &tunnel.local_port.to_string())
+        .replace("{remoteHost}", &tunnel.remote_host)
+        .replace("{remotePort}", &tunnel.remote_port.to_string())
+        .replace("{host}", &tunnel.host)
+}
+
+fn is_url(target: &str) -> bool {
+    target.contains("://")
+}
+
+/// Launches the tunnel's `onConnect` action, if any. Runs each time the
+/// tunnel comes up, reconnects included.
+///
+/// URLs are handed to the system opener; anything else is spawned as a
+/// program with the configured arguments. Failures are only logged.
+pub fn run_connect_action<R: Runtime>(app_handle: &AppHandle<R>, tunnel: &TunnelConfig) {
+    let action = match &tunnel.on_connect {
+        Some(a) if !a.target.trim().is_empty() => a,
+        _ => return,
+    };
+
+    let target = expand_placeholders(action.target.trim(), tunnel);
+    let args: Vec<String> = action
+        .args
+        .iter()
+        .map(|a| expand_placeholders(a, tunnel))
+        .collect();
+
+    let (program, args) = if is_url(&target) {
+        opener_command(target)
+    } else {
+        (target, args)
+    };
+
+    info!(
+        "Running on-connect action for '{}': {} {}",
+        tunnel.name,
+        program,
+        args.join(" ")
+    );
+
+    if let Err(e) = app_handle.shell().command(&program).args(&args).spawn() {
+        warn!("On-connect action for '{}' failed: {}", tunnel.name, e);
+    }
+}
+
+#[cfg(windows)]
+fn opener_command(url: String) -> (String, Vec<String>) {
+    (
+        "rundll32".to_string(),
+        vec!["url.dll,FileProtocolHandler".to_string(), url],
+    )
+}
+
+#[cfg(target_os = "macos")]
+fn opener_command(url: String) -> (String, Vec<String>) {
+    ("open".to_string(), vec![url])
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn opener_command(url: String) -> (String, Vec<String>) {
+    ("xdg-open".to_string(), vec![url])
+}
@@ -0,0 +1,1165 @@
+use crate::config::{
+    self, AuthMethod, BastionTarget, CloudflaredTarget, DockerTarget, JumpHost, KubeTarget,
+    Provider, SsmTarget, Transport, TunnelConfig, TunnelType,
+};
+use crate::mux;
+use crate::orphans;
+use crate::pageant;
+use crate::password;
+use crate::process::{self, ProcessTree};
+use crate::proxy;
+use async_trait::async_trait;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+/// How a line of client output should be treated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputKind {
+    Info,
+    Error,
+    /// The client is connected but couldn't set up the forward, e.g. the
+    /// port is taken.
+    ForwardFailed,
+}
+
+impl OutputKind {
+    pub fn level(self) -> &'static str {
+        match self {
+            OutputKind::Info => "info",
+            OutputKind::Error | OutputKind::ForwardFailed => "error",
+        }
+    }
+}
+
+/// A host key the client refused, as told by its output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostKeyNotice {
+    /// The tunnel's server has a key that isn't trusted yet; holds its
+    /// fingerprint.
+    Unknown(String),
+    /// The server's key doesn't match the one trusted for it, which may be
+    /// an attack; never offered for acceptance.
+    Changed(String),
+    /// A key of another host on the way, e.g. a jump host, which can't be
+    /// pinned on this tunnel; holds that host.
+    OtherHost(String),
+}
+
+/// A way of running a tunnel. The manager and monitor only talk to this
+/// trait, so new clients don't touch the lifecycle code.
+#[async_trait]
+pub trait TunnelBackend: Send + Sync {
+    /// Program and arguments that run the tunnel.
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>);
+
+    /// Program and arguments that run `remote` on the tunnel's SSH host,
+    /// for backends that can.
+    fn exec(&self, _tunnel: &TunnelConfig, _remote: &str) -> Option<(String, Vec<String>)> {
+        None
+    }
+
+    /// Refuses settings this backend can't honour; checked when the tunnel
+    /// is saved and again before each start, as the client may have been
+    /// changed in between.
+    fn validate(&self, _tunnel: &TunnelConfig) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Written to the client's stdin once it's started, which is then
+    /// closed; for what shouldn't show on its command line.
+    fn input(&self, _tunnel: &TunnelConfig) -> Option<String> {
+        None
+    }
+
+    /// Runs before the client is spawned, e.g. to look up where to forward
+    /// to.
+    async fn prepare(&self, _tunnel: &TunnelConfig) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Starts the client process with piped output, in its own process
+    /// group.
+    fn spawn(&self, tunnel: &TunnelConfig) -> Result<Child, String> {
+        let (cmd, args) = self.command(tunnel);
+        let mut command = Command::new(&cmd);
+        command
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Closed on stop, which clients that read it take as a request
+            // to quit
+            .stdin(Stdio::piped())
+            .env(orphans::MARKER_ENV, &tunnel.id)
+            .kill_on_drop(true);
+        password::apply_env(&mut command, tunnel);
+        proxy::apply_env(&mut command, tunnel);
+        process::new_group(&mut command);
+        command
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {}. Is it in PATH?", cmd, e))
+    }
+
+    /// Stops the client process and anything it started, giving them the
+    /// configured grace period to exit on their own.
+    async fn stop(&self, child: &mut Child, tree: &ProcessTree) {
+        process::stop(child, tree, stop_grace().await).await;
+    }
+
+    /// Classifies one line of client output.
+    fn parse_output(&self, _line: &str) -> OutputKind {
+        OutputKind::Info
+    }
+
+    /// Turns a line of client output into a log message, or `None` to drop
+    /// it.
+    fn log_line(&self, _tunnel: &TunnelConfig, line: &str) -> Option<(OutputKind, String)> {
+        Some((self.parse_output(line), line.to_string()))
+    }
+
+    /// The host key the client refused, if `line` (following the stream's
+    /// `recent` lines, oldest first) shows one.
+    fn host_key_notice(
+        &self,
+        _tunnel: &TunnelConfig,
+        _recent: &[String],
+        _line: &str,
+    ) -> Option<HostKeyNotice> {
+        None
+    }
+
+    /// Checks a running tunnel beyond its process being alive; `Err` holds
+    /// the reason it's considered broken.
+    async fn health(&self, _tunnel: &TunnelConfig) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Command-line flavour of the configured SSH client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SshClient {
+    Plink,
+    OpenSsh,
+    #[cfg(feature = "native-ssh")]
+    Native,
+}
+
+impl SshClient {
+    /// Guesses the flavour from the executable name (`ssh`/`ssh.exe` is
+    /// OpenSSH, `builtin` the native client, anything else is treated as
+    /// plink).
+    pub fn detect(path: &str) -> Self {
+        let stem = Path::new(path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        #[cfg(feature = "native-ssh")]
+        if stem == crate::native::CLIENT_NAME {
+            return SshClient::Native;
+        }
+        if stem == "ssh" {
+            SshClient::OpenSsh
+        } else {
+            SshClient::Plink
+        }
+    }
+}
+
+/// Grace period for stopping clients, from settings.
+async fn stop_grace() -> Duration {
+    Duration::from_secs(config::load_config_async().await.settings.stop_grace_sec)
+}
+
+/// Picks the backend for `tunnel` given the configured client path; the
+/// tunnel's own `ssh_client` takes precedence.
+pub fn select(tunnel: &TunnelConfig, client_path: &str) -> Arc<dyn TunnelBackend> {
+    let client_path = tunnel
+        .ssh_client
+        .as_deref()
+        .filter(|c| !c.is_empty())
+        .unwrap_or(client_path);
+    match &tunnel.provider {
+        Some(Provider::Ssm(target)) => Arc::new(SsmBackend {
+            target: target.clone(),
+        }),
+        Some(Provider::Bastion(target)) => Arc::new(BastionBackend {
+            target: target.clone(),
+        }),
+        Some(Provider::Kubernetes(target)) => Arc::new(KubeBackend {
+            target: target.clone(),
+            broken: Mutex::new(None),
+        }),
+        Some(Provider::Docker(target)) => Arc::new(DockerBackend {
+            ssh: ssh_backend(client_path),
+            target: target.clone(),
+            address: Mutex::new(None),
+        }),
+        Some(Provider::Cloudflared(target)) => Arc::new(CloudflaredBackend {
+            target: target.clone(),
+        }),
+        None => match resolve_client(client_path) {
+            // Windows' ssh.exe has no ControlMaster support
+            (SshClient::OpenSsh, path) if tunnel.share_connection && cfg!(unix) => {
+                Arc::new(SharedSshBackend {
+                    ssh: OpenSshBackend { path },
+                    client: Mutex::new(None),
+                })
+            }
+            _ => ssh_backend(client_path),
+        },
+    }
+}
+
+/// The client flavour and path to run for the configured client path.
+fn resolve_client(client_path: &str) -> (SshClient, String) {
+    // A Windows default carried over to another OS would never resolve
+    let client_path = if !cfg!(windows) && client_path.eq_ignore_ascii_case("plink.exe") {
+        config::detect_ssh_client()
+    } else {
+        client_path.to_string()
+    };
+    (SshClient::detect(&client_path), client_path)
+}
+
+/// The plink or OpenSSH backend for the configured client path.
+fn ssh_backend(client_path: &str) -> Arc<dyn TunnelBackend> {
+    let (client, client_path) = resolve_client(client_path);
+    match client {
+        SshClient::Plink => Arc::new(PlinkBackend { path: client_path }),
+        SshClient::OpenSsh => Arc::new(OpenSshBackend { path: client_path }),
+        #[cfg(feature = "native-ssh")]
+        SshClient::Native => Arc::new(NativeBackend),
+    }
+}
+
+/// Refuses remote and dynamic forwards for backends that only forward a
+/// local port.
+fn local_only(tunnel: &TunnelConfig, backend: &str) -> Result<(), String> {
+    if tunnel.tunnel_type != TunnelType::Local {
+        return Err(format!("{} only forwards local ports", backend));
+    }
+    Ok(())
+}
+
+/// `-L`/`-R`/`-D` flag and spec; both clients share the same syntax.
+fn forward_spec(tunnel: &TunnelConfig) -> (&'static str, String) {
+    let (flag, spec) = forward_target(tunnel);
+    match tunnel.bind_address.as_deref().filter(|a| !a.is_empty()) {
+        Some(bind) => (flag, format!("{}:{}", config::bracket_host(bind), spec)),
+        None => (flag, spec),
+    }
+}
+
+fn forward_target(tunnel: &TunnelConfig) -> (&'static str, String) {
+    match tunnel.tunnel_type {
+        TunnelType::Local => (
+            "-L",
+            format!(
+                "{}:{}:{}",
+                tunnel.local_port,
+                config::bracket_host(&tunnel.remote_host),
+                tunnel.remote_port
+            ),
+        ),
+        TunnelType::Remote => (
+            "-R",
+            format!(
+                "{}:{}:{}",
+                tunnel.remote_port,
+                config::bracket_host(&tunnel.remote_host),
+                tunnel.local_port
+            ),
+        ),
+        TunnelType::Dynamic => ("-D", tunnel.local_port.to_string()),
+    }
+}
+
+/// Command the SSH client runs to reach the server when a transport wrapper
+/// is configured. It runs as the client's child, so it lives and dies with
+/// the tunnel's process tree.
+fn proxy_command(tunnel: &TunnelConfig) -> Option<String> {
+    match tunnel.transport.as_ref()? {
+        Transport::WebSocket(ws) => {
+            let client = ws.client_path.as_deref().unwrap_or("wstunnel");
+            let client = if client.contains(' ') {
+                format!("\"{}\"", client)
+            } else {
+                client.to_string()
+            };
+            let target = ws
+                .target
+                .clone()
+                .unwrap_or_else(|| format!("{}:{}", tunnel.host, tunnel.port));
+            Some(format!(
+                "{} client -L stdio://{} {}",
+                client, target, ws.url
+            ))
+        }
+        Transport::Proxy(p) => Some(
+            proxy::command_args(p, tunnel)
+                .iter()
+                .map(|arg| quote_arg(arg))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+    }
+}
+
+/// Quotes a proxy command argument if it needs it, escaping embedded
+/// quotes so commands can nest.
+fn quote_arg(arg: &str) -> String {
+    if !arg.contains([' ', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            // Backslashes before a quote are escapes themselves
+            '"' => quoted.push_str(&"\\".repeat(backslashes * 2 + 1)),
+            _ => quoted.push_str(&"\\".repeat(backslashes)),
+        }
+        backslashes = 0;
+        quoted.push(c);
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// PuTTY's command-line client.
+pub struct PlinkBackend {
+    path: String,
+}
+
+impl PlinkBackend {
+    /// Saved session, batch mode, proxy, compression, host key, port, key
+    /// and extra arguments.
+    fn login_args(&self, tunnel: &TunnelConfig, args: &mut Vec<String>) {
+        // The saved session first, so our options override it
+        if let Some(session) = tunnel.putty_session.as_deref().filter(|s| !s.is_empty()) {
+            args.push("-load".to_string());
+            args.push(session.to_string());
+        }
+        args.push("-batch".to_string()); // non-interactive
+        args.push("-ssh".to_string()); // force SSH
+
+        if let Some(proxy) = self.jump_command(tunnel).or_else(|| proxy_command(tunnel)) {
+            args.push("-proxycmd".to_string());
+            args.push(proxy);
+        }
+
+        if tunnel.compression {
+            args.push("-C".to_string());
+        }
+
+        if let Some(ref fingerprint) = tunnel.host_key_fingerprint {
+            args.push("-hostkey".to_string());
+            args.push(fingerprint.clone());
+        }
+
+        // Port
+        if tunnel.port != 22 {
+            args.push("-P".to_string());
+            args.push(tunnel.port.to_string());
+        }
+
+        // Auth
+        match &tunnel.auth_method {
+            AuthMethod::Key => {
+                if let Some(ref key) = tunnel.key_path {
+                    args.push("-i".to_string());
+                    args.push(key.clone());
+                }
+            }
+            AuthMethod::Agent => {
+                // Pageant, even if a saved default session turned it off
+                args.push("-agent".to_string());
+            }
+            AuthMethod::Password => {
+                if password::needed(tunnel) {
+                    args.push("-pwfile".to_string());
+                    args.push(
+                        password::file_path(&tunnel.id)
+                            .to_string_lossy()
+                            .to_string(),
+                    );
+                }
+            }
+        }
+
+        args.extend(tunnel.extra_args.iter().cloned());
+    }
+
+    /// `-proxycmd` reaching the server through `jump_hosts`: a `plink -nc`
+    /// per hop, each connecting through the one before it. Hops log in
+    /// with the tunnel's key.
+    fn jump_command(&self, tunnel: &TunnelConfig) -> Option<String> {
+        let hops: Vec<JumpHost> = tunnel
+            .jump_hosts
+            .iter()
+            .filter_map(|hop| JumpHost::parse(hop, &tunnel.username).ok())
+            .collect();
+        let mut proxy: Option<String> = None;
+        for (i, hop) in hops.iter().enumerate() {
+            // plink fills in %host/%port for the last hop
+            let target = match hops.get(i + 1) {
+                Some(next) => format!("{}:{}", config::bracket_host(&next.host), next.port),
+                None => "%host:%port".to_string(),
+            };
+            let mut command = vec![
+                quote_arg(&self.path),
+                "-batch".to_string(),
+                "-ssh".to_string(),
+                "-P".to_string(),
+                hop.port.to_string(),
+            ];
+            if let (AuthMethod::Key, Some(key)) = (&tunnel.auth_method, &tunnel.key_path) {
+                command.push("-i".to_string());
+                command.push(quote_arg(key));
+            }
+            if let Some(inner) = proxy {
+                command.push("-proxycmd".to_string());
+                command.push(quote_arg(&inner));
+            }
+            command.push("-nc".to_string());
+            command.push(target);
+            command.push(format!("{}@{}", hop.username, hop.host));
+            proxy = Some(command.join(" "));
+        }
+        proxy
+    }
+}
+
+#[async_trait]
+impl TunnelBackend for PlinkBackend {
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        let mut args = vec![
+            "-N".to_string(), // no shell
+        ];
+        if tunnel.access_log {
+            args.push("-v".to_string()); // reports forwarded connections
+        }
+        self.login_args(tunnel, &mut args);
+
+        // Tunnel forwarding
+        let (flag, spec) = forward_spec(tunnel);
+        args.push(flag.to_string());
+        args.push(spec);
+
+        // user@host
+        args.push(format!("{}@{}", tunnel.username, tunnel.host));
+
+        (self.path.clone(), args)
+    }
+
+    fn exec(&self, tunnel: &TunnelConfig, remote: &str) -> Option<(String, Vec<String>)> {
+        let mut args = Vec::new();
+        self.login_args(tunnel, &mut args);
+        args.push(format!("{}@{}", tunnel.username, tunnel.host));
+        args.push(remote.to_string());
+        Some((self.path.clone(), args))
+    }
+
+    async fn prepare(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        match (&tunnel.auth_method, &tunnel.key_path) {
+            (AuthMethod::Key, Some(key)) if tunnel.use_pageant => {
+                pageant::load_key(&self.path, key).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        // "Local port 8080 forwarding to db:5432 failed: Network error:
+        // Address already in use", "Remote port forwarding from
+        // 0.0.0.0:8080 refused"; plink stays connected after either
+        let local_failed = line.contains(" forwarding to ") && line.contains(" failed");
+        let remote_refused = line.starts_with("Remote port forwarding") && line.contains("refused");
+        if local_failed || remote_refused || line.contains("Address already in use") {
+            return OutputKind::ForwardFailed;
+        }
+        // An unknown or changed host key ends with "Connection abandoned"
+        // in batch mode
+        if line.starts_with("FATAL ERROR")
+            || line.contains("Access denied")
+            || line.contains("Connection abandoned")
+        {
+            OutputKind::Error
+        } else {
+            OutputKind::Info
+        }
+    }
+
+    fn log_line(&self, tunnel: &TunnelConfig, line: &str) -> Option<(OutputKind, String)> {
+        // "Received remote port 0.0.0.0:20001 open request from 203.0.113.5:54321"
+        if tunnel.access_log && line.starts_with("Received remote port") {
+            if let Some((_, peer)) = line.split_once(" open request from ") {
+                return Some((OutputKind::Info, format!("Connection from {}", peer)));
+            }
+        }
+        Some((self.parse_output(line), line.to_string()))
+    }
+
+    // "The host key is not cached for this server:"
+    // "  example.com (port 22)"
+    // ...
+    // "The server's ssh-ed25519 key fingerprint is:"
+    // "  ssh-ed25519 255 SHA256:..."
+    //
+    // A changed key starts with "WARNING - POTENTIAL SECURITY BREACH!"
+    // instead, and hops of a jump chain print theirs in the same stream.
+    fn host_key_notice(
+        &self,
+        tunnel: &TunnelConfig,
+        recent: &[String],
+        line: &str,
+    ) -> Option<HostKeyNotice> {
+        if !recent.last()?.trim_end().ends_with("key fingerprint is:") {
+            return None;
+        }
+        let fingerprint = line.split_whitespace().last()?.to_string();
+        let start = recent.iter().rposition(|l| {
+            l.contains("POTENTIAL SECURITY BREACH")
+                || l.contains("does not match")
+                || l.contains("not cached")
+        });
+        let block = &recent[start.unwrap_or(0)..];
+        if block
+            .iter()
+            .any(|l| l.contains("POTENTIAL SECURITY BREACH") || l.contains("does not match"))
+        {
+            return Some(HostKeyNotice::Changed(fingerprint));
+        }
+        // "  example.com (port 22)"; older clients don't say
+        let server = block.iter().find_map(|l| {
+            let (host, port) = l.trim().strip_suffix(')')?.rsplit_once(" (port ")?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            Some((host.to_string(), port.parse::<u16>().ok()?))
+        });
+        match server {
+            Some((host, port))
+                if !host.eq_ignore_ascii_case(&tunnel.host) || port != tunnel.port =>
+            {
+                Some(HostKeyNotice::OtherHost(format!(
+                    "{}:{}",
+                    config::bracket_host(&host),
+                    port
+                )))
+            }
+            None if !tunnel.jump_hosts.is_empty() => {
+                Some(HostKeyNotice::OtherHost("a jump host".to_string()))
+            }
+            _ => Some(HostKeyNotice::Unknown(fingerprint)),
+        }
+    }
+}
+
+/// Unanswered OpenSSH keepalives before the client gives up.
+const SERVER_ALIVE_COUNT_MAX: u32 = 3;
+
+/// OpenSSH keepalive options, so a dead connection is noticed instead of
+/// hung on.
+fn keepalive_args(tunnel: &TunnelConfig) -> Vec<String> {
+    match tunnel
+        .keepalive_sec
+        .unwrap_or(config::DEFAULT_KEEPALIVE_SEC)
+    {
+        0 => Vec::new(),
+        interval => vec![
+            "-o".to_string(),
+            format!("ServerAliveInterval={}", interval),
+            "-o".to_string(),
+            format!("ServerAliveCountMax={}", SERVER_ALIVE_COUNT_MAX),
+        ],
+    }
+}
+
+/// The OpenSSH client (`ssh`, or `ssh.exe` on Windows 10+). Host aliases and
+/// options from `~/.ssh/config` apply as usual.
+pub struct OpenSshBackend {
+    path: String,
+}
+
+impl OpenSshBackend {
+    /// Batch mode, proxy, compression, port, key and extra arguments.
+    fn login_args(&self, tunnel: &TunnelConfig, args: &mut Vec<String>) {
+        args.push("-o".to_string());
+        if password::needed(tunnel) {
+            // Batch mode would skip SSH_ASKPASS, which answers the password
+            // prompt and refuses any other
+            args.push("BatchMode=no".to_string());
+            args.push("-o".to_string());
+            args.push("NumberOfPasswordPrompts=1".to_string());
+        } else {
+            args.push("BatchMode=yes".to_string()); // non-interactive
+        }
+
+        if let Some(proxy) = proxy_command(tunnel) {
+            args.push("-o".to_string());
+            args.push(format!("ProxyCommand={}", proxy));
+        }
+
+        if !tunnel.jump_hosts.is_empty() {
+            let hops: Vec<String> = tunnel
+                .jump_hosts
+                .iter()
+                .filter_map(|hop| JumpHost::parse(hop, &tunnel.username).ok())
+                .map(|hop| {
+                    format!(
+                        "{}@{}:{}",
+                        hop.username,
+                        config::bracket_host(&hop.host),
+                        hop.port
+                    )
+                })
+                .collect();
+            args.push("-J".to_string());
+            args.push(hops.join(","));
+        }
+
+        if tunnel.compression {
+            args.push("-C".to_string());
+        }
+
+        // Port
+        if tunnel.port != 22 {
+            args.push("-p".to_string());
+            args.push(tunnel.port.to_string());
+        }
+
+        // Auth; agent keys need nothing, ssh asks the agent (the Windows
+        // ssh-agent service, or SSH_AUTH_SOCK) by default
+        if tunnel.auth_method == AuthMethod::Key {
+            if let Some(ref key) = tunnel.key_path {
+                args.push("-i".to_string());
+                args.push(key.clone());
+            }
+        }
+
+        args.extend(tunnel.extra_args.iter().cloned());
+    }
+}
+
+#[async_trait]
+impl TunnelBackend for OpenSshBackend {
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        let mut args = vec![
+            "-N".to_string(), // no shell
+            "-o".to_string(),
+            "ExitOnForwardFailure=yes".to_string(),
+        ];
+        args.extend(keepalive_args(tunnel));
+        if tunnel.access_log {
+            args.push("-v".to_string()); // reports forwarded connections
+        }
+        self.login_args(tunnel, &mut args);
+
+        // Tunnel forwarding
+        let (flag, spec) = forward_spec(tunnel);
+        args.push(flag.to_string());
+        args.push(spec);
+
+        // user@host
+        args.push(format!("{}@{}", tunnel.username, tunnel.host));
+
+        (self.path.clone(), args)
+    }
+
+    fn exec(&self, tunnel: &TunnelConfig, remote: &str) -> Option<(String, Vec<String>)> {
+        let mut args = Vec::new();
+        self.login_args(tunnel, &mut args);
+        args.push(format!("{}@{}", tunnel.username, tunnel.host));
+        args.push(remote.to_string());
+        Some((self.path.clone(), args))
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        // "bind [127.0.0.1]:8080: Address already in use", "Could not
+        // request local forwarding.", "Error: remote port forwarding failed
+        // for listen port 8080"
+        const FORWARD_ERRORS: [&str; 4] = [
+            "Address already in use",
+            "cannot listen to port",
+            "Could not request local forwarding",
+            "forwarding failed",
+        ];
+        const ERRORS: [&str; 3] = [
+            "Permission denied",
+            "Could not resolve",
+            "Connection refused",
+        ];
+        if FORWARD_ERRORS.iter().any(|e| line.contains(e)) {
+            OutputKind::ForwardFailed
+        } else if ERRORS.iter().any(|e| line.contains(e)) {
+            OutputKind::Error
+        } else {
+            OutputKind::Info
+        }
+    }
+
+    fn log_line(&self, tunnel: &TunnelConfig, line: &str) -> Option<(OutputKind, String)> {
+        if tunnel.access_log {
+            // "debug1: client_request_forwarded_tcpip: listen 0.0.0.0 port
+            // 20001, originator 203.0.113.5 port 54321"
+            if let Some((_, originator)) = line.split_once("originator ") {
+                let peer = originator.replacen(" port ", ":", 1);
+                return Some((OutputKind::Info, format!("Connection from {}", peer)));
+            }
+            // The rest of -v output is noise
+            if line.starts_with("debug1:") {
+                return None;
+            }
+        }
+        Some((self.parse_output(line), line.to_string()))
+    }
+}
+
+/// OpenSSH attached to a master connection shared with other tunnels to the
+/// same server (see `mux`).
+pub struct SharedSshBackend {
+    ssh: OpenSshBackend,
+    /// The config the client was started with, to cancel its forward.
+    client: Mutex<Option<TunnelConfig>>,
+}
+
+impl SharedSshBackend {
+    fn login(&self, tunnel: &TunnelConfig) -> Vec<String> {
+        let mut args = Vec::new();
+        self.ssh.login_args(tunnel, &mut args);
+        args
+    }
+
+    /// Arguments reaching the tunnel's master: control socket, login
+    /// options and destination.
+    fn common(&self, tunnel: &TunnelConfig) -> Vec<String> {
+        let mut args = vec!["-S".to_string(), mux::control_path(tunnel)];
+        args.extend(self.login(tunnel));
+        args.push(format!("{}@{}", tunnel.username, tunnel.host));
+        args
+    }
+
+    /// Sends a control command (`cancel`, ...) for the tunnel's forward to
+    /// the master.
+    async fn control(&self, tunnel: &TunnelConfig, operation: &str) -> bool {
+        let (flag, spec) = forward_spec(tunnel);
+        Command::new(&self.ssh.path)
+            .args(["-O", operation, flag, &spec])
+            .args(self.common(tunnel))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .map(|s| s.success())
+            .unwrap_or(false)
+    }
+}
+
+#[async_trait]
+impl TunnelBackend for SharedSshBackend {
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        // The forward is the master's; the helper only adds it and watches
+        // the master, without a session of its own
+        let (flag, spec) = forward_spec(tunnel);
+        mux::forward_command(&self.ssh.path, flag, &spec, &self.common(tunnel))
+    }
+
+    async fn prepare(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        let mut login = keepalive_args(tunnel);
+        login.extend(self.login(tunnel));
+        mux::acquire(tunnel, &self.ssh.path, &login).await?;
+        // Forwards outlive their client in the master; one left by a client
+        // that died would block this one
+        self.control(tunnel, "cancel").await;
+        *self.client.lock().unwrap() = Some(tunnel.clone());
+        Ok(())
+    }
+
+    fn spawn(&self, tunnel: &TunnelConfig) -> Result<Child, String> {
+        let (cmd, args) = self.command(tunnel);
+        let mut command = Command::new(&cmd);
+        command
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::null())
+            .env(orphans::MARKER_ENV, &tunnel.id)
+            .kill_on_drop(true);
+        process::new_group(&mut command);
+        command
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {}. Is it in PATH?", cmd, e))
+    }
+
+    async fn stop(&self, child: &mut Child, tree: &ProcessTree) {
+        process::stop(child, tree, stop_grace().await).await;
+        let client = self.client.lock().unwrap().clone();
+        if let Some(tunnel) = client {
+            self.control(&tunnel, "cancel").await;
+        }
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        self.ssh.parse_output(line)
+    }
+
+    fn log_line(&self, tunnel: &TunnelConfig, line: &str) -> Option<(OutputKind, String)> {
+        self.ssh.log_line(tunnel, line)
+    }
+}
+
+/// The built-in russh client, run as a helper process of this executable.
+#[cfg(feature = "native-ssh")]
+pub struct NativeBackend;
+
+#[cfg(feature = "native-ssh")]
+#[async_trait]
+impl TunnelBackend for NativeBackend {
+    fn command(&self, _tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        let exe = std::env::current_exe()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| "opentunnel".to_string());
+        (exe, vec![crate::native::HELPER_FLAG.to_string()])
+    }
+
+    fn validate(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        crate::native::validate(tunnel)
+    }
+
+    /// The tunnel, on stdin so other users can't read it from the process
+    /// list. Passwords come from their file and environment instead.
+    fn input(&self, tunnel: &TunnelConfig) -> Option<String> {
+        let mut tunnel = TunnelConfig {
+            password: None,
+            ..tunnel.clone()
+        };
+        if let Some(Transport::Proxy(ref mut proxy)) = tunnel.transport {
+            proxy.password = None;
+        }
+        serde_json::to_string(&tunnel).ok()
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        if line.starts_with("FATAL ERROR") {
+            OutputKind::Error
+        } else {
+            OutputKind::Info
+        }
+    }
+}
+
+/// AWS Session Manager through the `aws` CLI (needs the
+/// session-manager-plugin). Only local forwarding is supported.
+pub struct SsmBackend {
+    target: SsmTarget,
+}
+
+#[async_trait]
+impl TunnelBackend for SsmBackend {
+    fn validate(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        local_only(tunnel, "Session Manager")
+    }
+
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        // The instance itself, or a host reachable from it
+        let on_instance = matches!(tunnel.remote_host.as_str(), "" | "localhost" | "127.0.0.1");
+        let (document, parameters) = if on_instance {
+            (
+                "AWS-StartPortForwardingSession",
+                format!(
+                    "portNumber={},localPortNumber={}",
+                    tunnel.remote_port, tunnel.local_port
+                ),
+            )
+        } else {
+            (
+                "AWS-StartPortForwardingSessionToRemoteHost",
+                format!(
+                    "host={},portNumber={},localPortNumber={}",
+                    tunnel.remote_host, tunnel.remote_port, tunnel.local_port
+                ),
+            )
+        };
+
+        let mut args = vec![
+            "ssm".to_string(),
+            "start-session".to_string(),
+            "--target".to_string(),
+            self.target.instance_id.clone(),
+            "--document-name".to_string(),
+            document.to_string(),
+            "--parameters".to_string(),
+            parameters,
+        ];
+        if let Some(ref profile) = self.target.profile {
+            args.push("--profile".to_string());
+            args.push(profile.clone());
+        }
+        if let Some(ref region) = self.target.region {
+            args.push("--region".to_string());
+            args.push(region.clone());
+        }
+
+        ("aws".to_string(), args)
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        const ERRORS: [&str; 4] = [
+            "An error occurred",
+            "SessionManagerPlugin is not found",
+            "Unable to locate credentials",
+            "TargetNotConnected",
+        ];
+        if ERRORS.iter().any(|e| line.contains(e)) {
+            OutputKind::Error
+        } else {
+            OutputKind::Info
+        }
+    }
+}
+
+/// Azure Bastion through `az network bastion tunnel` (needs the `bastion`
+/// CLI extension and a Standard SKU Bastion). Forwards a local port to a
+/// port on the VM itself.
+pub struct BastionBackend {
+    target: BastionTarget,
+}
+
+#[async_trait]
+impl TunnelBackend for BastionBackend {
+    fn validate(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        local_only(tunnel, "Azure Bastion")
+    }
+
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        let args = vec![
+            "network".to_string(),
+            "bastion".to_string(),
+            "tunnel".to_string(),
+            "--subscription".to_string(),
+            self.target.subscription.clone(),
+            "--resource-group".to_string(),
+            self.target.resource_group.clone(),
+            "--name".to_string(),
+            self.target.bastion.clone(),
+            "--target-resource-id".to_string(),
+            self.target.vm_id(),
+            "--resource-port".to_string(),
+            tunnel.remote_port.to_string(),
+            "--port".to_string(),
+            tunnel.local_port.to_string(),
+        ];
+
+        // az is a batch script on Windows, which Command won't find by name
+        let program = if cfg!(windows) { "az.cmd" } else { "az" };
+        (program.to_string(), args)
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        const ERRORS: [&str; 4] = ["ERROR:", "az login", "AuthorizationFailed", "not found"];
+        if ERRORS.iter().any(|e| line.contains(e)) {
+            OutputKind::Error
+        } else {
+            OutputKind::Info
+        }
+    }
+}
+
+/// `kubectl port-forward`. Only local forwarding is supported.
+pub struct KubeBackend {
+    target: KubeTarget,
+    /// First fatal forwarding error seen. kubectl keeps its listener open
+    /// after losing the pod, so this is what the health check reports.
+    broken: Mutex<Option<String>>,
+}
+
+#[async_trait]
+impl TunnelBackend for KubeBackend {
+    fn validate(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        local_only(tunnel, "kubectl port-forward")
+    }
+
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        let mut args = vec!["port-forward".to_string()];
+        if let Some(ref context) = self.target.context {
+            args.push("--context".to_string());
+            args.push(context.clone());
+        }
+        if let Some(ref namespace) = self.target.namespace {
+            args.push("--namespace".to_string());
+            args.push(namespace.clone());
+        }
+        args.push("--address".to_string());
+        args.push("127.0.0.1".to_string());
+        args.push(self.target.resource.clone());
+        args.push(format!("{}:{}", tunnel.local_port, tunnel.remote_port));
+
+        ("kubectl".to_string(), args)
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        // Logged while the process carries on with a dead pod
+        const FATAL: [&str; 3] = [
+            "an error occurred forwarding",
+            "lost connection to pod",
+            "not found",
+        ];
+        if FATAL.iter().any(|e| line.contains(e)) {
+            self.broken
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| line.to_string());
+            return OutputKind::Error;
+        }
+        if line.contains("error:") || line.contains("Unable to connect to the server") {
+            OutputKind::Error
+        } else {
+            OutputKind::Info
+        }
+    }
+
+    async fn health(&self, _tunnel: &TunnelConfig) -> Result<(), String> {
+        match self.broken.lock().unwrap().clone() {
+            Some(line) => Err(line),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Forwards to a container on the SSH host: its address is looked up over
+/// SSH before each start, then the SSH client forwards to it.
+pub struct DockerBackend {
+    ssh: Arc<dyn TunnelBackend>,
+    target: DockerTarget,
+    /// Container IP found by `prepare`.
+    address: Mutex<Option<String>>,
+}
+
+impl DockerBackend {
+    /// `tunnel` as a local forward to the container.
+    fn forwarded(&self, tunnel: &TunnelConfig) -> TunnelConfig {
+        let mut forwarded = tunnel.clone();
+        forwarded.tunnel_type = TunnelType::Local;
+        forwarded.remote_host = self
+            .address
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| self.target.container.clone());
+        forwarded
+    }
+}
+
+#[async_trait]
+impl TunnelBackend for DockerBackend {
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        self.ssh.command(&self.forwarded(tunnel))
+    }
+
+    async fn prepare(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        let container = &self.target.container;
+        let valid = !container.is_empty()
+            && container
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+        if !valid {
+            return Err(format!("Invalid container name '{}'", container));
+        }
+
+        let inspect = format!(
+            "docker inspect -f '{{{{range .NetworkSettings.Networks}}}}{{{{.IPAddress}}}} {{{{end}}}}' {}",
+            container
+        );
+        let (cmd, args) = self
+            .ssh
+            .exec(tunnel, &inspect)
+            .ok_or("The SSH client can't run remote commands")?;
+        let output = tokio::time::timeout(
+            Duration::from_secs(30),
+            Command::new(&cmd)
+                .args(&args)
+                .stdin(Stdio::null())
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await
+        .map_err(|_| format!("Timed out looking up container '{}'", container))?
+        .map_err(|e| format!("Failed to start {}: {}", cmd, e))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to inspect container '{}': {}",
+                container,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let address = stdout.split_whitespace().next().ok_or_else(|| {
+            format!(
+                "Container '{}' has no IP address (is it running?)",
+                container
+            )
+        })?;
+        *self.address.lock().unwrap() = Some(address.to_string());
+        Ok(())
+    }
+
+    async fn stop(&self, child: &mut Child, tree: &ProcessTree) {
+        self.ssh.stop(child, tree).await;
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        self.ssh.parse_output(line)
+    }
+
+    fn log_line(&self, tunnel: &TunnelConfig, line: &str) -> Option<(OutputKind, String)> {
+        self.ssh.log_line(tunnel, line)
+    }
+
+    async fn health(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        self.ssh.health(&self.forwarded(tunnel)).await
+    }
+}
+
+/// `cloudflared access tcp`, listening on the local port.
+pub struct CloudflaredBackend {
+    target: CloudflaredTarget,
+}
+
+#[async_trait]
+impl TunnelBackend for CloudflaredBackend {
+    fn validate(&self, tunnel: &TunnelConfig) -> Result<(), String> {
+        local_only(tunnel, "cloudflared access")
+    }
+
+    fn command(&self, tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        let args = vec![
+            "access".to_string(),
+            "tcp".to_string(),
+            "--hostname".to_string(),
+            self.target.hostname.clone(),
+            "--url".to_string(),
+            format!("127.0.0.1:{}", tunnel.local_port),
+        ];
+        ("cloudflared".to_string(), args)
+    }
+
+    fn parse_output(&self, line: &str) -> OutputKind {
+        // zerolog console format: "2024-01-01T00:00:00Z ERR ..."
+        if line.contains(" ERR ") || line.contains(" FTL ") {
+            OutputKind::Error
+        } else {
+            OutputKind::Info
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
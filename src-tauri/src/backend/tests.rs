@@ -0,0 +1,145 @@
+use super::*;
+
+fn plink_tunnel(host: &str, port: u16) -> TunnelConfig {
+    let mut tunnel = TunnelConfig::new("t".to_string(), host.to_string(), "user".to_string());
+    tunnel.port = port;
+    tunnel
+}
+
+/// Feeds `output` to the plink backend line by line, as the output loop
+/// does, and returns the notices it raised.
+fn notices(tunnel: &TunnelConfig, output: &str) -> Vec<HostKeyNotice> {
+    let plink = PlinkBackend {
+        path: "plink".to_string(),
+    };
+    let mut recent = Vec::new();
+    let mut found = Vec::new();
+    for line in output.lines() {
+        found.extend(plink.host_key_notice(tunnel, &recent, line));
+        recent.push(line.to_string());
+    }
+    found
+}
+
+const UNKNOWN: &str = "The host key is not cached for this server:
+  example.com (port 22)
+You have no guarantee that the server is the computer
+you think it is.
+The server's ssh-ed25519 key fingerprint is:
+  ssh-ed25519 255 SHA256:abcdef
+If you trust this host, enter \"y\" to add the key to
+PuTTY's cache and carry on connecting.
+Connection abandoned.";
+
+const CHANGED: &str = "WARNING - POTENTIAL SECURITY BREACH!
+The host key does not match the one PuTTY has cached
+for this server:
+  example.com (port 22)
+This means that either the server administrator has
+changed the host key, or you have actually connected
+to another computer pretending to be the server.
+The new ssh-ed25519 key fingerprint is:
+  ssh-ed25519 255 SHA256:evil
+Connection abandoned.";
+
+#[test]
+fn plink_unknown_host_key_is_offered() {
+    assert_eq!(
+        notices(&plink_tunnel("example.com", 22), UNKNOWN),
+        vec![HostKeyNotice::Unknown("SHA256:abcdef".to_string())]
+    );
+}
+
+#[test]
+fn plink_changed_host_key_is_never_offered() {
+    assert_eq!(
+        notices(&plink_tunnel("example.com", 22), CHANGED),
+        vec![HostKeyNotice::Changed("SHA256:evil".to_string())]
+    );
+}
+
+#[test]
+fn plink_jump_host_key_is_not_pinned_on_the_target() {
+    let mut tunnel = plink_tunnel("internal.example.com", 22);
+    tunnel.jump_hosts = vec!["user@example.com".to_string()];
+    assert_eq!(
+        notices(&tunnel, UNKNOWN),
+        vec![HostKeyNotice::OtherHost("example.com:22".to_string())]
+    );
+    assert_eq!(
+        notices(&plink_tunnel("example.com", 2222), UNKNOWN),
+        vec![HostKeyNotice::OtherHost("example.com:22".to_string())]
+    );
+    // Old clients don't name the host, so a chain can't be told apart
+    let old = UNKNOWN.replace("  example.com (port 22)\n", "");
+    assert_eq!(
+        notices(&tunnel, &old),
+        vec![HostKeyNotice::OtherHost("a jump host".to_string())]
+    );
+}
+
+#[test]
+fn changed_key_after_a_trusted_hop_is_still_refused() {
+    let output = format!("{}\n{}", UNKNOWN, CHANGED);
+    assert_eq!(
+        notices(&plink_tunnel("example.com", 22), &output),
+        vec![
+            HostKeyNotice::Unknown("SHA256:abcdef".to_string()),
+            HostKeyNotice::Changed("SHA256:evil".to_string()),
+        ]
+    );
+}
+
+fn kube_tunnel() -> TunnelConfig {
+    let mut tunnel = plink_tunnel("cluster.invalid", 22);
+    tunnel.provider = Some(Provider::Kubernetes(KubeTarget {
+        context: None,
+        namespace: None,
+        resource: "svc/postgres".to_string(),
+    }));
+    tunnel
+}
+
+#[tokio::test]
+async fn port_forward_errors_fail_health_check() {
+    let tunnel = kube_tunnel();
+    let backend = select(&tunnel, "plink");
+    assert!(backend.health(&tunnel).await.is_ok());
+
+    let line = "E0101 portforward.go:413] an error occurred forwarding 5432 -> 5432";
+    assert_eq!(backend.parse_output(line), OutputKind::Error);
+    assert_eq!(backend.health(&tunnel).await, Err(line.to_string()));
+}
+
+#[test]
+fn local_only_backends_refuse_other_forwards() {
+    let mut tunnel = kube_tunnel();
+    assert!(select(&tunnel, "plink").validate(&tunnel).is_ok());
+    tunnel.tunnel_type = TunnelType::Dynamic;
+    assert!(select(&tunnel, "plink").validate(&tunnel).is_err());
+
+    tunnel.provider = Some(Provider::Ssm(SsmTarget {
+        instance_id: "i-0123456789abcdef0".to_string(),
+        profile: None,
+        region: None,
+    }));
+    tunnel.tunnel_type = TunnelType::Remote;
+    assert!(select(&tunnel, "plink").validate(&tunnel).is_err());
+
+    tunnel.provider = Some(Provider::Bastion(BastionTarget {
+        subscription: "sub".to_string(),
+        resource_group: "rg".to_string(),
+        bastion: "bastion".to_string(),
+        vm: "vm".to_string(),
+    }));
+    assert!(select(&tunnel, "plink").validate(&tunnel).is_err());
+    tunnel.tunnel_type = TunnelType::Local;
+    assert!(select(&tunnel, "plink").validate(&tunnel).is_ok());
+
+    tunnel.provider = Some(Provider::Cloudflared(CloudflaredTarget {
+        hostname: "ssh.example.invalid".to_string(),
+    }));
+    assert!(select(&tunnel, "plink").validate(&tunnel).is_ok());
+    tunnel.tunnel_type = TunnelType::Dynamic;
+    assert!(select(&tunnel, "plink").validate(&tunnel).is_err());
+}
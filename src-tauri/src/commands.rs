@@ -1,46 +1,159 @@
-use crate::config::{self, AppConfig, TunnelConfig};
-use crate::tunnel::{self, TunnelManager, TunnelState};
+use crate::backend;
+use crate::config::{self, AppConfig, TunnelConfig, TunnelFilter, TunnelGroup};
+use crate::diagnostics::{self, AgentDiagnostics};
+use crate::expose::{self, Exposure};
+use crate::health::{self, AppHealth, HealthSample};
+use crate::instances::{self, ForeignClaim};
+use crate::jumplist;
+use crate::logs::{self, LogBus};
+use crate::monitor::{self, Monitor};
+use crate::profiles::{self, Profile};
+use crate::tunnel::{self, TunnelManager, TunnelState, TunnelStatus};
 use log::info;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use uuid::Uuid;
 
 #[cfg(windows)]
 use crate::putty_import;
+use crate::secrets;
+use crate::templates::{self, TunnelTemplate};
 
 // ── Tunnel CRUD ──
 
 #[tauri::command]
 pub async fn get_config() -> Result<AppConfig, String> {
-    Ok(config::load_config())
+    Ok(config::load_config_async().await)
 }
 
+/// Tunnels matching `filter` (all of them without one), in config order.
 #[tauri::command]
-pub async fn save_settings(settings: config::Settings) -> Result<(), String> {
-    let mut cfg = config::load_config();
-    cfg.settings = settings;
-    config::save_config(&cfg)
+pub async fn list_tunnels(
+    filter: Option<TunnelFilter>,
+    manager: tauri::State<'_, TunnelManager>,
+) -> Result<Vec<TunnelConfig>, String> {
+    let filter = filter.unwrap_or_default();
+    let states = manager.states();
+    let cfg = config::load_config_async().await;
+    Ok(cfg
+        .tunnels
+        .into_iter()
+        .filter(|t| {
+            let status = states
+                .iter()
+                .find(|s| s.id == t.id)
+                .map_or(TunnelStatus::Stopped, |s| s.status);
+            filter.matches(t, status)
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn save_settings(
+    settings: config::Settings,
+    log_bus: tauri::State<'_, LogBus>,
+) -> Result<(), String> {
+    if let Some(ref condition) = settings.auto_connect_on {
+        condition.validate()?;
+    }
+    log_bus.set_capacity(settings.log_buffer_lines);
+    config::update_config(move |cfg| {
+        cfg.settings = settings;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// `tunnel`'s own checks, then those of the client it would run with.
+async fn validate(tunnel: &TunnelConfig) -> Result<(), String> {
+    tunnel.validate()?;
+    let client_path = config::load_config_async().await.settings.plink_path;
+    backend::select(tunnel, &client_path).validate(tunnel)
 }
 
 #[tauri::command]
 pub async fn add_tunnel(mut tunnel: TunnelConfig) -> Result<TunnelConfig, String> {
+    validate(&tunnel).await?;
     if tunnel.id.is_empty() {
         tunnel.id = Uuid::new_v4().to_string();
     }
-    let mut cfg = config::load_config();
-    cfg.tunnels.push(tunnel.clone());
-    config::save_config(&cfg)?;
+    let added = tunnel.clone();
+    let cfg = config::update_config(move |cfg| {
+        config::check_depends_on(&cfg.tunnels, &added)?;
+        cfg.tunnels.push(added);
+        Ok(())
+    })
+    .await?;
+    jumplist::refresh(&cfg);
     Ok(tunnel)
 }
 
 #[tauri::command]
 pub async fn update_tunnel(tunnel: TunnelConfig) -> Result<(), String> {
-    let mut cfg = config::load_config();
-    if let Some(existing) = cfg.tunnels.iter_mut().find(|t| t.id == tunnel.id) {
+    validate(&tunnel).await?;
+    let cfg = config::update_config(move |cfg| {
+        config::check_depends_on(&cfg.tunnels, &tunnel)?;
+        let existing = cfg
+            .tunnels
+            .iter_mut()
+            .find(|t| t.id == tunnel.id)
+            .ok_or("Tunnel not found")?;
         *existing = tunnel;
-        config::save_config(&cfg)?;
         Ok(())
-    } else {
-        Err("Tunnel not found".to_string())
+    })
+    .await?;
+    jumplist::refresh(&cfg);
+    Ok(())
+}
+
+/// Saves the settings of a group, by its path.
+#[tauri::command]
+pub async fn save_group(group: TunnelGroup) -> Result<(), String> {
+    config::update_config(move |cfg| {
+        match cfg.groups.iter_mut().find(|g| g.path == group.path) {
+            Some(existing) => *existing = group,
+            None => cfg.groups.push(group),
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Moves a group, with its subfolders and their tunnels, to `to`.
+#[tauri::command]
+pub async fn rename_group(from: String, to: String) -> Result<(), String> {
+    let to = to.trim().trim_matches('/').to_string();
+    if to.split('/').any(|part| part.trim().is_empty()) {
+        return Err(format!("Invalid group '{}'", to));
     }
+    config::update_config(move |cfg| {
+        let moved = |path: &str| format!("{}{}", to, &path[from.len()..]);
+        for tunnel in &mut cfg.tunnels {
+            if let Some(group) = tunnel.group.as_mut().filter(|g| config::in_group(g, &from)) {
+                *group = moved(group);
+            }
+        }
+        for group in &mut cfg.groups {
+            if config::in_group(&group.path, &from) {
+                group.path = moved(&group.path);
+            }
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Saves the order tunnels are listed in (e.g. after dragging one).
+#[tauri::command]
+pub async fn reorder_tunnels(ids: Vec<String>) -> Result<(), String> {
+    let cfg = config::update_config(move |cfg| config::reorder(&mut cfg.tunnels, &ids)).await?;
+    jumplist::refresh(&cfg);
+    Ok(())
 }
 
 #[tauri::command]
@@ -51,28 +164,146 @@ pub async fn delete_tunnel(
 ) -> Result<(), String> {
     // Stop if running
     tunnel::stop_tunnel(&manager, &id, &app_handle).await?;
+    health::forget(&id);
+
+    let cfg = config::update_config(move |cfg| {
+        if let Some(tunnel) = cfg.tunnels.iter().find(|t| t.id == id) {
+            secrets::forget(tunnel);
+        }
+        cfg.tunnels.retain(|t| t.id != id);
+        Ok(())
+    })
+    .await?;
+    jumplist::refresh(&cfg);
+    Ok(())
+}
+
+// ── Templates ──
+
+/// The built-in presets, then the user's templates.
+#[tauri::command]
+pub async fn list_templates() -> Result<Vec<TunnelTemplate>, String> {
+    Ok(templates::all(&config::load_config_async().await))
+}
 
-    let mut cfg = config::load_config();
-    cfg.tunnels.retain(|t| t.id != id);
-    config::save_config(&cfg)
+/// Adds or updates one of the user's templates.
+#[tauri::command]
+pub async fn save_template(mut template: TunnelTemplate) -> Result<TunnelTemplate, String> {
+    template.validate()?;
+    if templates::is_preset(&template.id) {
+        return Err(format!("'{}' is a built-in template", template.name));
+    }
+    if template.id.is_empty() {
+        template.id = Uuid::new_v4().to_string();
+    }
+    template.builtin = false;
+    let saved = template.clone();
+    config::update_config(move |cfg| {
+        match cfg.templates.iter_mut().find(|t| t.id == saved.id) {
+            Some(existing) => *existing = saved,
+            None => cfg.templates.push(saved),
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(template)
+}
+
+#[tauri::command]
+pub async fn delete_template(id: String) -> Result<(), String> {
+    config::update_config(move |cfg| {
+        cfg.templates.retain(|t| t.id != id);
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// A new tunnel, not added yet, with the ports and name of template
+/// `template_id`, logging in like tunnel `server_id` if given.
+#[tauri::command]
+pub async fn create_from_template(
+    template_id: String,
+    server_id: Option<String>,
+) -> Result<TunnelConfig, String> {
+    let cfg = config::load_config_async().await;
+    let template = templates::all(&cfg)
+        .into_iter()
+        .find(|t| t.id == template_id)
+        .ok_or("Template not found")?;
+    let server = server_id
+        .map(|id| {
+            cfg.tunnels
+                .iter()
+                .find(|t| t.id == id)
+                .ok_or("Tunnel not found")
+        })
+        .transpose()?;
+    Ok(templates::instantiate(&template, server))
 }
 
 // ── Tunnel Control ──
 
+/// Outcome of one tunnel in a bulk start.
+#[derive(Debug, Clone, Serialize)]
+pub struct StartResult {
+    #[serde(rename = "tunnelId")]
+    pub tunnel_id: String,
+    #[serde(rename = "tunnelName")]
+    pub tunnel_name: String,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
 #[tauri::command]
 pub async fn start_tunnel_cmd(
     id: String,
     manager: tauri::State<'_, TunnelManager>,
     app_handle: tauri::AppHandle,
 ) -> Result<(), String> {
-    let cfg = config::load_config();
+    let cfg = config::load_config_async().await;
     let tunnel_cfg = cfg
         .tunnels
         .iter()
         .find(|t| t.id == id)
         .ok_or("Tunnel not found")?;
 
-    tunnel::start_tunnel(&manager, tunnel_cfg, &cfg.settings.plink_path, app_handle).await
+    tunnel::start_tunnel(
+        &manager,
+        tunnel_cfg,
+        &cfg.settings.plink_path,
+        app_handle.clone(),
+    )
+    .await?;
+    jumplist::record_start(&cfg, &id);
+    jumplist::refresh(&cfg);
+    Ok(())
+}
+
+/// Trusts `fingerprint` as the tunnel's host key by pinning it, then
+/// restarts the tunnel with it.
+#[tauri::command]
+pub async fn accept_host_key(
+    id: String,
+    fingerprint: String,
+    manager: tauri::State<'_, TunnelManager>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    let pinned = id.clone();
+    config::update_config(move |cfg| {
+        let tunnel = cfg
+            .tunnels
+            .iter_mut()
+            .find(|t| t.id == pinned)
+            .ok_or("Tunnel not found")?;
+        tunnel.host_key_fingerprint = Some(fingerprint);
+        Ok(())
+    })
+    .await?;
+    info!("Accepted host key for tunnel {}", id);
+
+    tunnel::stop_tunnel(&manager, &id, &app_handle).await?;
+    start_tunnel_cmd(id, manager, app_handle).await
 }
 
 #[tauri::command]
@@ -84,24 +315,74 @@ pub async fn stop_tunnel_cmd(
     tunnel::stop_tunnel(&manager, &id, &app_handle).await
 }
 
+/// Publishes a local port on the server `server_id` logs into and saves the
+/// resulting Remote tunnel, so it can be stopped and restarted like any
+/// other.
+#[tauri::command]
+pub async fn expose_port(
+    server_id: String,
+    local_port: u16,
+    manager: tauri::State<'_, TunnelManager>,
+    app_handle: tauri::AppHandle,
+) -> Result<Exposure, String> {
+    let cfg = config::load_config_async().await;
+    let server = cfg
+        .tunnels
+        .iter()
+        .find(|t| t.id == server_id)
+        .ok_or("Tunnel not found")?;
+
+    let exposure = expose::expose(
+        &manager,
+        server,
+        local_port,
+        &cfg.settings.plink_path,
+        &app_handle,
+    )
+    .await?;
+
+    let added = exposure.tunnel.clone();
+    let cfg = config::update_config(move |cfg| {
+        cfg.tunnels.push(added);
+        Ok(())
+    })
+    .await?;
+    jumplist::refresh(&cfg);
+    Ok(exposure)
+}
+
 #[tauri::command]
 pub async fn start_all_tunnels(
     manager: tauri::State<'_, TunnelManager>,
     app_handle: tauri::AppHandle,
-) -> Result<(), String> {
-    let cfg = config::load_config();
-    for tunnel_cfg in &cfg.tunnels {
-        if tunnel_cfg.enabled {
-            let _ = tunnel::start_tunnel(
-                &manager,
-                tunnel_cfg,
-                &cfg.settings.plink_path,
-                app_handle.clone(),
-            )
-            .await;
-        }
+) -> Result<Vec<StartResult>, String> {
+    let cfg = config::load_config_async().await;
+    let limit = Arc::new(Semaphore::new(cfg.settings.start_concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for tunnel_cfg in cfg.tunnels.into_iter().filter(|t| t.enabled) {
+        let manager = manager.inner().clone();
+        let app_handle = app_handle.clone();
+        let plink_path = cfg.settings.plink_path.clone();
+        let limit = limit.clone();
+        tasks.spawn(async move {
+            let _permit = limit.acquire_owned().await;
+            let result =
+                tunnel::start_tunnel(&manager, &tunnel_cfg, &plink_path, app_handle.clone()).await;
+            StartResult {
+                tunnel_id: tunnel_cfg.id,
+                tunnel_name: tunnel_cfg.name,
+                ok: result.is_ok(),
+                error: result.err(),
+            }
+        });
     }
-    Ok(())
+
+    let mut results = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        results.push(joined.map_err(|e| format!("Start task failed: {}", e))?);
+    }
+    Ok(results)
 }
 
 #[tauri::command]
@@ -109,21 +390,171 @@ pub async fn stop_all_tunnels(
     manager: tauri::State<'_, TunnelManager>,
     app_handle: tauri::AppHandle,
 ) -> Result<(), String> {
-    let ids: Vec<String> = {
-        let mgr = manager.lock().await;
-        mgr.keys().cloned().collect()
-    };
-    for id in ids {
+    for id in manager.ids() {
         tunnel::stop_tunnel(&manager, &id, &app_handle).await?;
     }
     Ok(())
 }
 
+// ── Profiles ──
+
+#[tauri::command]
+pub async fn list_profiles() -> Result<Vec<Profile>, String> {
+    Ok(profiles::list())
+}
+
+/// Stops every tunnel and makes `name` the active profile (creating it if
+/// needed), then, given `connect`, starts its auto-connect tunnels. A
+/// tunnel that fails to stop doesn't keep the others running; the switch
+/// is refused once they've all been tried.
+#[tauri::command]
+pub async fn switch_profile(
+    name: String,
+    connect: bool,
+    manager: tauri::State<'_, TunnelManager>,
+    monitor: tauri::State<'_, Monitor>,
+    app_handle: tauri::AppHandle,
+) -> Result<AppConfig, String> {
+    let mut failed = Vec::new();
+    for id in manager.ids() {
+        if let Err(e) = tunnel::stop_tunnel(&manager, &id, &app_handle).await {
+            failed.push(e);
+        }
+    }
+    if !failed.is_empty() {
+        return Err(format!("Not switching profiles: {}", failed.join("; ")));
+    }
+    let profile = name.clone();
+    let cfg = tokio::task::spawn_blocking(move || config::switch_profile(&profile))
+        .await
+        .map_err(|e| format!("Profile switch failed: {}", e))??;
+    info!("Switched to profile '{}'", name);
+    jumplist::refresh(&cfg);
+
+    if connect {
+        monitor::auto_connect(&manager, &monitor, cfg.clone(), &app_handle).await;
+    }
+    Ok(cfg)
+}
+
 #[tauri::command]
 pub async fn get_tunnel_states(
     manager: tauri::State<'_, TunnelManager>,
 ) -> Result<Vec<TunnelState>, String> {
-    Ok(tunnel::get_all_states(&manager).await)
+    Ok(tunnel::get_all_states(&manager))
+}
+
+/// Overall health, as last emitted in `app-health`.
+#[tauri::command]
+pub async fn get_app_health(manager: tauri::State<'_, TunnelManager>) -> Result<AppHealth, String> {
+    Ok(health::current(&manager))
+}
+
+/// A tunnel's recent health checks, oldest first.
+#[tauri::command]
+pub async fn get_health_history(id: String) -> Result<Vec<HealthSample>, String> {
+    Ok(health::history(&id))
+}
+
+/// Tunnels and ports held by other OpenTunnel instances.
+#[tauri::command]
+pub async fn get_foreign_claims() -> Result<Vec<ForeignClaim>, String> {
+    let cfg = config::load_config_async().await;
+    Ok(instances::foreign_claims(&cfg.tunnels).await)
+}
+
+// ── Monitor ──
+
+/// Suspends auto-reconnect until `resume_monitor`.
+#[tauri::command]
+pub async fn pause_monitor(
+    monitor: tauri::State<'_, Monitor>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    monitor::set_paused(&monitor, true, &app_handle).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn resume_monitor(
+    monitor: tauri::State<'_, Monitor>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    monitor::set_paused(&monitor, false, &app_handle).await;
+    Ok(())
+}
+
+/// Re-arms auto-reconnect for `id`, or every tunnel, after the user fixed
+/// what kept failing.
+#[tauri::command]
+pub async fn reset_reconnect_attempts(
+    id: Option<String>,
+    manager: tauri::State<'_, TunnelManager>,
+    monitor: tauri::State<'_, Monitor>,
+) -> Result<(), String> {
+    monitor::reset_attempts(&manager, &monitor, id.as_deref()).await;
+    Ok(())
+}
+
+/// Reconnects `id` now instead of at the end of its backoff.
+#[tauri::command]
+pub async fn reconnect_now(id: String, monitor: tauri::State<'_, Monitor>) -> Result<(), String> {
+    monitor::reconnect_now(&monitor, &id).await
+}
+
+#[tauri::command]
+pub async fn is_monitor_paused(monitor: tauri::State<'_, Monitor>) -> Result<bool, String> {
+    Ok(monitor.lock().await.paused)
+}
+
+/// Uptime, reconnects and failures per tunnel, as in `monitor-summary`.
+#[tauri::command]
+pub async fn get_monitor_summary(
+    monitor: tauri::State<'_, Monitor>,
+) -> Result<monitor::MonitorSummary, String> {
+    Ok(monitor::summary(&monitor).await)
+}
+
+// ── Logs ──
+
+/// Streams the log file to `path`, or to a dated file in the Downloads
+/// folder. Returns where it was written.
+#[tauri::command]
+pub async fn export_logs(
+    path: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    let dest = match path.filter(|p| !p.is_empty()) {
+        Some(p) => std::path::PathBuf::from(p),
+        None => dirs::download_dir()
+            .unwrap_or_else(config::config_dir)
+            .join(format!(
+                "opentunnel-logs-{}.txt",
+                chrono::Local::now().format("%Y%m%d-%H%M%S")
+            )),
+    };
+    logs::export(&app_handle, &dest).await?;
+    Ok(dest.to_string_lossy().to_string())
+}
+
+// ── Diagnostics ──
+
+#[tauri::command]
+pub async fn diagnose_agents(id: Option<String>) -> Result<AgentDiagnostics, String> {
+    let cfg = config::load_config_async().await;
+    let tunnel_cfg = match id {
+        Some(id) => Some(
+            cfg.tunnels
+                .into_iter()
+                .find(|t| t.id == id)
+                .ok_or("Tunnel not found")?,
+        ),
+        None => None,
+    };
+
+    tokio::task::spawn_blocking(move || diagnostics::check_agents(tunnel_cfg.as_ref()))
+        .await
+        .map_err(|e| format!("Diagnostics failed: {}", e))
 }
 
 // ── PuTTY Import ──
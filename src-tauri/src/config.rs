@@ -1,12 +1,37 @@
+use crate::dpapi;
+use crate::profiles;
+use crate::schedule::Schedule;
+use crate::secrets;
+use crate::state::TunnelStatus;
+use crate::templates::TunnelTemplate;
+use chrono::Local;
+use log::{error, info};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TunnelConfig {
     pub id: String,
     pub name: String,
+    /// Folder the tunnel is listed in, nested with `/` (e.g.
+    /// "Acme/Production").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Free-form labels (e.g. "prod", "db") to filter the list by.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Free-text documentation ("prod DB, ask Bob before touching").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Label color of the card, `#rrggbb`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
     pub host: String,
     pub port: u16,
     pub username: String,
@@ -14,6 +39,21 @@ pub struct TunnelConfig {
     pub auth_method: AuthMethod,
     #[serde(rename = "keyPath", skip_serializing_if = "Option::is_none")]
     pub key_path: Option<String>,
+    /// Password for `AuthMethod::Password`, handed to the client through
+    /// `password`'s protected file. Only in memory: on disk it's in the
+    /// credential store, under `password_ref`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(
+        rename = "passwordRef",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub password_ref: Option<String>,
+    /// Load the key into Pageant (starting it if needed) before plink
+    /// connects, so a passphrase-protected key works unattended.
+    #[serde(rename = "usePageant", default)]
+    pub use_pageant: bool,
     #[serde(rename = "type")]
     pub tunnel_type: TunnelType,
     #[serde(rename = "localPort")]
@@ -24,19 +64,426 @@ pub struct TunnelConfig {
     pub remote_port: u16,
     #[serde(rename = "autoConnect", default)]
     pub auto_connect: bool,
+    /// Network the tunnel is only auto-connected on.
+    #[serde(
+        rename = "autoConnectOn",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub auto_connect_on: Option<NetworkCondition>,
     #[serde(default = "default_true")]
     pub enabled: bool,
+    #[serde(rename = "onConnect", default, skip_serializing_if = "Option::is_none")]
+    pub on_connect: Option<ConnectAction>,
+    /// Hostname mapped to 127.0.0.1 in the hosts file while the tunnel runs.
+    #[serde(
+        rename = "hostsAlias",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub hosts_alias: Option<String>,
+    #[serde(rename = "wakeOnLan", default, skip_serializing_if = "Option::is_none")]
+    pub wake_on_lan: Option<WakeOnLan>,
+    /// How the tunnel reaches its target when it isn't a plain SSH
+    /// connection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+    /// SSH client for this tunnel (`plink.exe`, `ssh`, ...), overriding the
+    /// one in settings.
+    #[serde(rename = "sshClient", default, skip_serializing_if = "Option::is_none")]
+    pub ssh_client: Option<String>,
+    /// Saved PuTTY session plink loads (`-load`) before our options, to
+    /// inherit its proxy, host keys, ciphers and so on.
+    #[serde(
+        rename = "puttySession",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub putty_session: Option<String>,
+    /// Server host key the client must see (`SHA256:...` or an MD5
+    /// fingerprint), so batch-mode connections to a host not yet trusted
+    /// work and a different key is refused.
+    #[serde(
+        rename = "hostKeyFingerprint",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub host_key_fingerprint: Option<String>,
+    /// Bastions to go through, first hop first, each `[user@]host[:port]`.
+    #[serde(rename = "jumpHosts", default, skip_serializing_if = "Vec::is_empty")]
+    pub jump_hosts: Vec<String>,
+    /// Wrapper the SSH connection is carried over, for networks that block
+    /// port 22.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<Transport>,
+    /// Keep the local port bound while the tunnel reconnects, holding new
+    /// connections until it's back.
+    #[serde(rename = "persistentListener", default)]
+    pub persistent_listener: bool,
+    /// Address the forwarded port listens on, e.g. `0.0.0.0` to make a
+    /// remote forward public (needs `GatewayPorts` on the server).
+    #[serde(
+        rename = "bindAddress",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bind_address: Option<String>,
+    /// Extra client arguments (`-4`, `-noagent`, cipher selection, ...),
+    /// passed before the destination.
+    #[serde(rename = "extraArgs", default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_args: Vec<String>,
+    /// Compress the SSH connection (`-C`), for slow links.
+    #[serde(default)]
+    pub compression: bool,
+    /// Attach to one SSH connection shared with other tunnels to the same
+    /// user@host:port (OpenSSH on Linux/macOS).
+    #[serde(rename = "shareConnection", default)]
+    pub share_connection: bool,
+    /// Seconds between keepalives on an idle connection, overriding the
+    /// setting; 0 disables them.
+    #[serde(
+        rename = "keepaliveSec",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub keepalive_sec: Option<u64>,
+    /// Log each connection made through a remote forward.
+    #[serde(rename = "accessLog", default)]
+    pub access_log: bool,
+    /// How hard and how often the running tunnel is checked; by default
+    /// end to end every 10s, restarted after 3 failures in a row.
+    #[serde(
+        rename = "healthCheck",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub health_check: Option<HealthCheck>,
+    /// Check of the service behind a local forward, run with the health
+    /// checks.
+    #[serde(
+        rename = "healthProbe",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub health_probe: Option<HealthProbe>,
+    /// Notify when the probe's average round trip goes over this many ms.
+    #[serde(
+        rename = "latencyAlertMs",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub latency_alert_ms: Option<u64>,
+    /// Stop the tunnel once nothing has been connected to its local port
+    /// for this many minutes. Local and dynamic tunnels only.
+    #[serde(
+        rename = "idleDisconnectMin",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub idle_disconnect_min: Option<u64>,
+    /// Restart the tunnel after this many hours of continuous uptime, to
+    /// force a fresh session (rekeying, bastion session limits).
+    #[serde(
+        rename = "maxSessionHours",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_session_hours: Option<u64>,
+    /// Id of a tunnel this one goes through (e.g. its SSH server is reached
+    /// through that tunnel's forward), reconnected first.
+    #[serde(rename = "dependsOn", default, skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<String>,
+    /// `host:port` a dynamic tunnel's health check connects to through the
+    /// proxy; without it, only the SOCKS handshake is checked.
+    #[serde(
+        rename = "socksProbe",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub socks_probe: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealthCheck {
+    #[serde(default)]
+    pub kind: HealthCheckKind,
+    #[serde(
+        rename = "intervalSec",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub interval_sec: Option<u64>,
+    /// Failed checks in a row after which the client is restarted.
+    #[serde(
+        rename = "failureThreshold",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub failure_threshold: Option<u32>,
+}
+
+/// What a running tunnel's health checks cover, each level including the
+/// ones before it.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "kebab-case")]
+pub enum HealthCheckKind {
+    /// Only that the client is still running.
+    Process,
+    /// The local port accepts connections, and the backend's own check.
+    Tcp,
+    /// A dynamic tunnel's port speaks SOCKS.
+    Socks,
+    /// The health probe gets through to the service.
+    #[default]
+    EndToEnd,
+}
+
+/// End-to-end checks through a local forward, tagged by `kind`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum HealthProbe {
+    /// Connecting through the forward isn't hung up on right away.
+    Tcp,
+    /// A GET through the forward answers with the expected status.
+    Http(HttpProbe),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpProbe {
+    #[serde(default = "default_probe_path")]
+    pub path: String,
+    #[serde(rename = "expectStatus", default = "default_expect_status")]
+    pub expect_status: u16,
+}
+
+fn default_probe_path() -> String {
+    "/".to_string()
+}
+
+fn default_expect_status() -> u16 {
+    200
+}
+
+/// SSH transport wrappers, tagged by `kind`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Transport {
+    /// SSH over WebSocket/HTTPS through a wstunnel server.
+    WebSocket(WebSocketTransport),
+    /// SSH through an HTTP CONNECT or SOCKS5 proxy.
+    Proxy(ProxyTransport),
+}
+
+/// Proxy the SSH connection goes through, reached by the `proxy` helper.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyTransport {
+    pub protocol: ProxyProtocol,
+    pub host: String,
+    pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Credential store entry of `password` on disk.
+    #[serde(
+        rename = "passwordRef",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub password_ref: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocol {
+    Http,
+    Socks5,
+}
+
+impl ProxyProtocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProxyProtocol::Http => "http",
+            ProxyProtocol::Socks5 => "socks5",
+        }
+    }
+}
+
+/// wstunnel client run as the SSH client's proxy command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebSocketTransport {
+    /// wstunnel server, e.g. `wss://tunnel.example.com:443`.
+    pub url: String,
+    /// SSH server as seen from the wstunnel server; defaults to the
+    /// tunnel's host and port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// wstunnel executable; `wstunnel` from PATH when unset.
+    #[serde(
+        rename = "clientPath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub client_path: Option<String>,
+}
+
+/// Non-SSH tunnel providers, tagged by `kind`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Provider {
+    /// AWS Systems Manager Session Manager port forwarding.
+    Ssm(SsmTarget),
+    /// Azure Bastion native-client tunnel.
+    Bastion(BastionTarget),
+    /// `kubectl port-forward` into a cluster.
+    Kubernetes(KubeTarget),
+    /// A container on the SSH host, forwarded to over the SSH connection.
+    Docker(DockerTarget),
+    /// Cloudflare Access TCP application through `cloudflared`.
+    Cloudflared(CloudflaredTarget),
+}
+
+/// EC2 (or hybrid) instance reached through Session Manager.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SsmTarget {
+    #[serde(rename = "instanceId")]
+    pub instance_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+/// Azure VM reached through a Bastion host.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BastionTarget {
+    pub subscription: String,
+    #[serde(rename = "resourceGroup")]
+    pub resource_group: String,
+    /// Name of the Bastion resource, in `resource_group`.
+    pub bastion: String,
+    /// Name of the target VM, also in `resource_group`.
+    pub vm: String,
+}
+
+/// Pod, service or deployment to port-forward to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KubeTarget {
+    /// kubeconfig context; the current one when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// `kubectl` resource, e.g. `svc/postgres` or `pod/api-0`.
+    pub resource: String,
+}
+
+/// Container on the tunnel's SSH host; `remotePort` is the port inside it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DockerTarget {
+    pub container: String,
+}
+
+/// Cloudflare Zero Trust application; the origin port is configured on the
+/// Cloudflare side, so `remotePort` is unused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloudflaredTarget {
+    pub hostname: String,
+}
+
+impl BastionTarget {
+    /// ARM ID of the target VM.
+    pub fn vm_id(&self) -> String {
+        format!(
+            "/subscriptions/{}/resourceGroups/{}/providers/Microsoft.Compute/virtualMachines/{}",
+            self.subscription, self.resource_group, self.vm
+        )
+    }
+}
+
+/// Program or URL launched once a tunnel is up.
+///
+/// `target` and `args` may use the `{localPort}`, `{remoteHost}`,
+/// `{remotePort}` and `{host}` placeholders, e.g. `mstsc` with
+/// `["/v:localhost:{localPort}"]` or `http://localhost:{localPort}/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectAction {
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+/// Wake-on-LAN target woken before connecting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WakeOnLan {
+    pub mac: String,
+    #[serde(default = "default_wol_broadcast")]
+    pub broadcast: String,
+    #[serde(default = "default_wol_port")]
+    pub port: u16,
+    /// How long to wait for the host's SSH port to come up.
+    #[serde(rename = "waitSec", default = "default_wol_wait")]
+    pub wait_sec: u64,
+}
+
+fn default_wol_broadcast() -> String {
+    "255.255.255.255".to_string()
+}
+
+fn default_wol_port() -> u16 {
+    9
+}
+
+fn default_wol_wait() -> u64 {
+    60
 }
 
 fn default_true() -> bool {
     true
 }
 
+/// A network to auto-connect on (or, with `except`, not to): one with
+/// everything set here.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NetworkCondition {
+    /// Wi-Fi network name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssid: Option<String>,
+    /// DNS domain of the connection, e.g. "corp.example.com".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// Subnet our address is in, e.g. "10.1.0.0/16".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subnet: Option<String>,
+    /// Auto-connect everywhere but on this network, e.g. for an office
+    /// bypass that's pointless on the office LAN.
+    #[serde(default)]
+    pub except: bool,
+}
+
+impl NetworkCondition {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ssid.is_none() && self.domain.is_none() && self.subnet.is_none() {
+            return Err("A network condition needs an SSID, domain or subnet".to_string());
+        }
+        if let Some(ref subnet) = self.subnet {
+            crate::netwatch::parse_subnet(subnet)
+                .ok_or_else(|| format!("Invalid subnet '{}', expected e.g. 10.1.0.0/16", subnet))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum AuthMethod {
     Password,
     Key,
+    /// Keys held by an SSH agent: Pageant for plink, the Windows ssh-agent
+    /// service or `SSH_AUTH_SOCK` for OpenSSH and the built-in client.
+    Agent,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -65,16 +512,165 @@ pub struct Settings {
     pub notify_on_disconnect: bool,
     #[serde(rename = "notifyOnReconnect", default = "default_true")]
     pub notify_on_reconnect: bool,
+    /// Consecutive failed reconnect attempts before a tunnel's failures are
+    /// notified, so blips that recover on a retry go unannounced.
+    #[serde(
+        rename = "notifyAfterFailures",
+        default = "default_notify_after_failures"
+    )]
+    pub notify_after_failures: u32,
+    /// macOS only: run from the menu bar without a Dock icon.
+    #[serde(rename = "hideDockIcon", default)]
+    pub hide_dock_icon: bool,
+    /// How many tunnels "Start all" launches at once.
+    #[serde(rename = "startConcurrency", default = "default_start_concurrency")]
+    pub start_concurrency: usize,
+    /// Log lines kept in the log panel.
+    #[serde(rename = "maxLogLines", default = "default_max_log_lines")]
+    pub max_log_lines: usize,
+    /// Log lines buffered between flushes to the frontend before the oldest
+    /// are dropped.
+    #[serde(rename = "logBufferLines", default = "default_log_buffer_lines")]
+    pub log_buffer_lines: usize,
+    /// Seconds a stopping client gets to close its session before it's
+    /// killed.
+    #[serde(rename = "stopGraceSec", default = "default_stop_grace")]
+    pub stop_grace_sec: u64,
+    /// Seconds between keepalives on idle connections, so NAT gateways
+    /// don't drop them and dead ones are noticed; 0 disables them.
+    #[serde(rename = "keepaliveSec", default = "default_keepalive")]
+    pub keepalive_sec: u64,
+    /// Seconds between the monitor's checks for dead tunnels; fractions
+    /// allowed, kept within `MONITOR_INTERVAL_BOUNDS`.
+    #[serde(rename = "monitorIntervalSec", default = "default_monitor_interval")]
+    pub monitor_interval_sec: f64,
+    /// A tunnel dropping more than this many times within
+    /// `flap_window_min` is held down for `flap_hold_min` instead of being
+    /// reconnected right away; 0 disables it.
+    #[serde(rename = "flapThreshold", default = "default_flap_threshold")]
+    pub flap_threshold: usize,
+    #[serde(rename = "flapWindowMin", default = "default_flap_window")]
+    pub flap_window_min: u64,
+    #[serde(rename = "flapHoldMin", default = "default_flap_hold")]
+    pub flap_hold_min: u64,
+    /// Seconds during which further notifications about a tunnel are
+    /// held back and summed up in one; 0 sends each right away.
+    #[serde(rename = "notifyThrottleSec", default = "default_notify_throttle")]
+    pub notify_throttle_sec: u64,
+    /// Seconds after a tunnel is started during which the monitor leaves
+    /// it alone, e.g. while it's still authenticating at boot.
+    #[serde(rename = "startupGraceSec", default = "default_startup_grace")]
+    pub startup_grace_sec: u64,
+    /// Previous versions of the config kept in `backups`; 0 keeps none.
+    #[serde(rename = "configBackups", default = "default_config_backups")]
+    pub config_backups: usize,
+    /// Keep the config file encrypted with the user's DPAPI key (Windows).
+    #[serde(rename = "encryptConfig", default)]
+    pub encrypt_config: bool,
+    /// Network this profile's tunnels are only auto-connected on, on top
+    /// of their own conditions.
+    #[serde(
+        rename = "autoConnectOn",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub auto_connect_on: Option<NetworkCondition>,
+}
+
+/// Shortest and longest monitor poll, in seconds.
+pub const MONITOR_INTERVAL_BOUNDS: (f64, f64) = (0.5, 60.0);
+
+impl Settings {
+    pub fn monitor_interval(&self) -> Duration {
+        let (min, max) = MONITOR_INTERVAL_BOUNDS;
+        Duration::from_secs_f64(self.monitor_interval_sec.clamp(min, max))
+    }
 }
 
 fn default_plink_path() -> String {
-    "plink.exe".to_string()
+    detect_ssh_client()
+}
+
+/// Picks the SSH client used when none is configured: plink on Windows
+/// (or the built-in OpenSSH if PuTTY isn't installed), `ssh` elsewhere.
+pub fn detect_ssh_client() -> String {
+    if !cfg!(windows) {
+        return "ssh".to_string();
+    }
+    if find_in_path("plink.exe").is_none() && find_in_path("ssh.exe").is_some() {
+        "ssh.exe".to_string()
+    } else if cfg!(feature = "native-ssh") && find_in_path("plink.exe").is_none() {
+        "builtin".to_string()
+    } else {
+        "plink.exe".to_string()
+    }
+}
+
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(program))
+        .find(|p| p.is_file())
 }
 
 fn default_reconnect_delay() -> u64 {
     5
 }
 
+fn default_start_concurrency() -> usize {
+    4
+}
+
+fn default_max_log_lines() -> usize {
+    1000
+}
+
+fn default_log_buffer_lines() -> usize {
+    2000
+}
+
+fn default_monitor_interval() -> f64 {
+    3.0
+}
+
+fn default_flap_threshold() -> usize {
+    5
+}
+
+fn default_flap_window() -> u64 {
+    10
+}
+
+fn default_flap_hold() -> u64 {
+    15
+}
+
+fn default_notify_after_failures() -> u32 {
+    1
+}
+
+fn default_notify_throttle() -> u64 {
+    60
+}
+
+fn default_startup_grace() -> u64 {
+    20
+}
+
+fn default_config_backups() -> usize {
+    10
+}
+
+fn default_stop_grace() -> u64 {
+    3
+}
+
+pub const DEFAULT_KEEPALIVE_SEC: u64 = 15;
+
+fn default_keepalive() -> u64 {
+    DEFAULT_KEEPALIVE_SEC
+}
+
 fn default_theme() -> String {
     "dark".to_string()
 }
@@ -90,71 +686,822 @@ impl Default for Settings {
             theme: default_theme(),
             notify_on_disconnect: true,
             notify_on_reconnect: true,
+            notify_after_failures: default_notify_after_failures(),
+            hide_dock_icon: false,
+            start_concurrency: default_start_concurrency(),
+            max_log_lines: default_max_log_lines(),
+            log_buffer_lines: default_log_buffer_lines(),
+            stop_grace_sec: default_stop_grace(),
+            keepalive_sec: default_keepalive(),
+            monitor_interval_sec: default_monitor_interval(),
+            flap_threshold: default_flap_threshold(),
+            flap_window_min: default_flap_window(),
+            flap_hold_min: default_flap_hold(),
+            notify_throttle_sec: default_notify_throttle(),
+            startup_grace_sec: default_startup_grace(),
+            config_backups: default_config_backups(),
+            encrypt_config: false,
+            auto_connect_on: None,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
+    /// Format of the file, `CONFIG_VERSION` once migrated; files from
+    /// before versioning have none (0).
+    #[serde(default)]
+    pub version: u32,
     pub tunnels: Vec<TunnelConfig>,
+    /// Settings of the groups tunnels are in; groups without any are
+    /// listed with the defaults.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<TunnelGroup>,
+    /// The user's tunnel templates, next to the built-in presets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<TunnelTemplate>,
     pub settings: Settings,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelGroup {
+    /// The `group` of its tunnels.
+    pub path: String,
+    /// Shown folded in the tunnel list.
+    #[serde(default)]
+    pub collapsed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// What `list_tunnels` looks for; a tunnel has to match everything set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TunnelFilter {
+    /// One of the tunnel's tags, ignoring case.
+    pub tag: Option<String>,
+    /// Part of the tunnel's host, ignoring case.
+    pub host: Option<String>,
+    pub status: Option<TunnelStatus>,
+}
+
+impl TunnelFilter {
+    /// Whether `tunnel`, currently `status`, matches.
+    pub fn matches(&self, tunnel: &TunnelConfig, status: TunnelStatus) -> bool {
+        self.tag
+            .as_ref()
+            .is_none_or(|tag| tunnel.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+            && self
+                .host
+                .as_ref()
+                .is_none_or(|host| tunnel.host.to_lowercase().contains(&host.to_lowercase()))
+            && self.status.is_none_or(|s| s == status)
+    }
+}
+
+/// Whether `group` is `path` or one of its subfolders.
+pub fn in_group(group: &str, path: &str) -> bool {
+    group == path
+        || group
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             tunnels: Vec::new(),
+            groups: Vec::new(),
+            templates: Vec::new(),
             settings: Settings::default(),
         }
     }
 }
 
+/// Current config format.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrades a config's JSON by one version, so fields that have changed
+/// shape still parse.
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// Migration from the version of each index to the next.
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [
+    // 0 → 1: versioning itself; nothing else changed
+    |_| Ok(()),
+];
+
+/// Parses a config file, migrating it to `CONFIG_VERSION`. Also returns the
+/// version it was written as.
+pub fn parse_config(content: &str) -> Result<(AppConfig, u32), String> {
+    let mut value: Value =
+        serde_json::from_str(content).map_err(|e| format!("Invalid config: {}", e))?;
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "Config version {} is newer than this OpenTunnel supports ({})",
+            version, CONFIG_VERSION
+        ));
+    }
+    for migrate in &MIGRATIONS[version as usize..] {
+        migrate(&mut value)?;
+    }
+    let mut config: AppConfig =
+        serde_json::from_value(value).map_err(|e| format!("Invalid config: {}", e))?;
+    config.version = CONFIG_VERSION;
+    Ok((config, version))
+}
+
 impl TunnelConfig {
-    #[allow(dead_code)]
     pub fn new(name: String, host: String, username: String) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             name,
+            group: None,
+            tags: Vec::new(),
+            notes: None,
+            color: None,
             host,
             port: 22,
             username,
             auth_method: AuthMethod::Key,
             key_path: None,
+            password: None,
+            password_ref: None,
+            use_pageant: false,
             tunnel_type: TunnelType::Local,
             local_port: 0,
             remote_host: "127.0.0.1".to_string(),
             remote_port: 0,
             auto_connect: false,
+            auto_connect_on: None,
             enabled: true,
+            on_connect: None,
+            hosts_alias: None,
+            wake_on_lan: None,
+            provider: None,
+            ssh_client: None,
+            putty_session: None,
+            host_key_fingerprint: None,
+            jump_hosts: Vec::new(),
+            transport: None,
+            persistent_listener: false,
+            bind_address: None,
+            extra_args: Vec::new(),
+            compression: false,
+            share_connection: false,
+            keepalive_sec: None,
+            access_log: false,
+            health_check: None,
+            health_probe: None,
+            latency_alert_ms: None,
+            idle_disconnect_min: None,
+            max_session_hours: None,
+            depends_on: None,
+            socks_probe: None,
+            schedule: None,
+        }
+    }
+
+    /// A new tunnel named `name` reaching `server`'s server the same way it
+    /// does: login, client and everything on the way there, but none of
+    /// its forward or lifecycle settings.
+    pub fn on_server_of(name: String, server: &TunnelConfig) -> Self {
+        Self {
+            port: server.port,
+            auth_method: server.auth_method.clone(),
+            key_path: server.key_path.clone(),
+            password: server.password.clone(),
+            use_pageant: server.use_pageant,
+            wake_on_lan: server.wake_on_lan.clone(),
+            provider: server.provider.clone(),
+            ssh_client: server.ssh_client.clone(),
+            putty_session: server.putty_session.clone(),
+            host_key_fingerprint: server.host_key_fingerprint.clone(),
+            jump_hosts: server.jump_hosts.clone(),
+            transport: server.transport.clone(),
+            extra_args: server.extra_args.clone(),
+            compression: server.compression,
+            share_connection: server.share_connection,
+            keepalive_sec: server.keepalive_sec,
+            ..Self::new(name, server.host.clone(), server.username.clone())
         }
     }
+
+    /// Checks the settings that can't be checked by deserializing alone.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ref group) = self.group {
+            if group.split('/').any(|part| part.trim().is_empty()) {
+                return Err(format!("Invalid group '{}'", group));
+            }
+        }
+        if self.tags.iter().any(|tag| tag.trim().is_empty()) {
+            return Err("Tags can't be empty".to_string());
+        }
+        if let Some(ref color) = self.color {
+            let hex = color.strip_prefix('#').unwrap_or("");
+            if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("Invalid color '{}', expected #rrggbb", color));
+            }
+        }
+        if let Some(ref schedule) = self.schedule {
+            schedule.validate()?;
+        }
+        for hop in &self.jump_hosts {
+            JumpHost::parse(hop, &self.username)?;
+        }
+        if !self.jump_hosts.is_empty() && self.transport.is_some() {
+            return Err("Jump hosts can't be combined with a transport".to_string());
+        }
+        if let Some(ref target) = self.socks_probe {
+            if split_host_port(target).is_none() {
+                return Err(format!("Invalid SOCKS probe target '{}'", target));
+            }
+        }
+        if let Some(ref check) = self.health_check {
+            if check.interval_sec == Some(0) || check.failure_threshold == Some(0) {
+                return Err("Health check interval and threshold must be at least 1".to_string());
+            }
+        }
+        if let Some(ref wake) = self.wake_on_lan {
+            crate::wol::parse_mac(&wake.mac)?;
+        }
+        if let Some(ref condition) = self.auto_connect_on {
+            condition.validate()?;
+        }
+        if let Some(Transport::Proxy(ref proxy)) = self.transport {
+            if proxy.host.trim().is_empty() || proxy.port == 0 {
+                return Err("The proxy needs a host and port".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the tunnel `tunnel` depends on exists among `tunnels` and
+/// doesn't, through its own dependencies, depend on `tunnel` in turn.
+pub fn check_depends_on(tunnels: &[TunnelConfig], tunnel: &TunnelConfig) -> Result<(), String> {
+    let mut parent = tunnel.depends_on.as_deref();
+    let mut hops = 0;
+    while let Some(id) = parent {
+        if id == tunnel.id && hops == 0 {
+            return Err(format!("'{}' can't depend on itself", tunnel.name));
+        }
+        let Some(next) = tunnels.iter().find(|t| t.id == id) else {
+            return Err(format!(
+                "'{}' depends on a tunnel that doesn't exist",
+                tunnel.name
+            ));
+        };
+        hops += 1;
+        if id == tunnel.id || hops > tunnels.len() {
+            return Err(format!("'{}' is part of a dependency loop", next.name));
+        }
+        parent = next.depends_on.as_deref();
+    }
+    Ok(())
+}
+
+/// Puts `tunnels` in the order of `ids`; ones left out keep their order
+/// after those listed.
+pub fn reorder(tunnels: &mut Vec<TunnelConfig>, ids: &[String]) -> Result<(), String> {
+    let mut rest = std::mem::take(tunnels);
+    for id in ids {
+        let i = rest
+            .iter()
+            .position(|t| &t.id == id)
+            .ok_or_else(|| format!("Unknown or repeated tunnel '{}'", id))?;
+        tunnels.push(rest.remove(i));
+    }
+    tunnels.append(&mut rest);
+    Ok(())
+}
+
+/// `path` with a leading `~` and `%VAR%`, `$VAR` and `${VAR}` environment
+/// variables expanded, so one config works for users with different home
+/// directories. Unknown variables are left as they are.
+pub fn expand_path(path: &str) -> String {
+    // Each system's home variable, for configs shared between them
+    let lookup = |name: &str| {
+        std::env::var(name).ok().or_else(|| {
+            matches!(name, "HOME" | "USERPROFILE")
+                .then(dirs::home_dir)
+                .flatten()
+                .map(|home| home.display().to_string())
+        })
+    };
+    let path = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            lookup("HOME").map_or_else(|| path.to_string(), |home| home + rest)
+        }
+        _ => path.to_string(),
+    };
+
+    let mut expanded = String::new();
+    let mut rest = path.as_str();
+    while let Some(i) = rest.find(['%', '$']) {
+        expanded.push_str(&rest[..i]);
+        let sigil = &rest[i..i + 1];
+        let after = &rest[i + 1..];
+        let (name, tail) = if sigil == "%" {
+            after.split_once('%').unwrap_or(("", after))
+        } else if let Some(braced) = after.strip_prefix('{') {
+            braced.split_once('}').unwrap_or(("", after))
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            after.split_at(end)
+        };
+        match lookup(name).filter(|_| !name.is_empty()) {
+            Some(value) => {
+                expanded.push_str(&value);
+                rest = tail;
+            }
+            None => {
+                expanded.push_str(sigil);
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Whether a pinned host key fingerprint is an MD5 one: `MD5:` or
+/// colon-separated hex pairs.
+#[cfg(any(feature = "native-ssh", test))]
+pub fn is_md5_fingerprint(fingerprint: &str) -> bool {
+    fingerprint.starts_with("MD5:")
+        || (fingerprint.len() == 47
+            && fingerprint
+                .split(':')
+                .all(|pair| pair.len() == 2 && pair.chars().all(|c| c.is_ascii_hexdigit())))
+}
+
+/// Whether `pinned` is the key whose SHA256 fingerprint is `sha256`, in
+/// base64 as OpenSSH prints it. The pin may be a whole fingerprint line
+/// (`ssh-ed25519 255 SHA256:...`), its `SHA256:` prefix and base64 padding
+/// are optional, and an MD5 pin never matches.
+#[cfg(any(feature = "native-ssh", test))]
+pub fn pin_matches(pinned: &str, sha256: &str) -> bool {
+    let Some(pinned) = pinned.split_whitespace().last() else {
+        return false;
+    };
+    if is_md5_fingerprint(pinned) {
+        return false;
+    }
+    let pinned = pinned.strip_prefix("SHA256:").unwrap_or(pinned);
+    pinned.trim_end_matches('=') == sha256.trim_end_matches('=')
+}
+
+/// `host` as it appears before `:port`: IPv6 addresses are bracketed.
+pub fn bracket_host(host: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
+
+/// `host` with IPv6 brackets removed, for connecting or binding.
+pub fn unbracket_host(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Splits `host:port` or `[ipv6]:port`; the host comes back unbracketed.
+pub fn split_host_port(address: &str) -> Option<(String, u16)> {
+    let (host, port) = address.rsplit_once(':')?;
+    // A bare IPv6 address without a port
+    if host.contains(':') && !host.starts_with('[') {
+        return None;
+    }
+    Some((unbracket_host(host).to_string(), port.parse().ok()?))
+}
+
+/// A parsed entry of `TunnelConfig::jump_hosts`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JumpHost {
+    pub username: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl JumpHost {
+    /// Parses `[user@]host[:port]` (IPv6 hosts with a port in brackets); the
+    /// user defaults to `username`.
+    pub fn parse(spec: &str, username: &str) -> Result<Self, String> {
+        let (user, address) = match spec.trim().rsplit_once('@') {
+            Some((user, address)) => (user.to_string(), address),
+            None => (username.to_string(), spec.trim()),
+        };
+        let (host, port) = match split_host_port(address) {
+            Some(host_port) => host_port,
+            // No port, or a bare IPv6 address
+            None if address.matches(':').count() != 1 => (unbracket_host(address).to_string(), 22),
+            None => return Err(format!("Invalid port in jump host '{}'", spec)),
+        };
+        if host.is_empty() || user.is_empty() {
+            return Err(format!("Invalid jump host '{}'", spec));
+        }
+        Ok(Self {
+            username: user,
+            host,
+            port,
+        })
+    }
 }
 
 pub fn config_dir() -> PathBuf {
+    // Tests keep their files to themselves
+    if cfg!(test) {
+        return std::env::temp_dir()
+            .join(format!("opentunnel-home-{}", std::process::id()))
+            .join(".opentunnel");
+    }
     let base = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
     base.join(".opentunnel")
 }
 
+/// Directory of the active profile's config and backups.
+pub fn profile_dir() -> PathBuf {
+    profiles::dir(&profiles::active())
+}
+
 pub fn config_path() -> PathBuf {
-    config_dir().join("config.json")
+    profile_dir().join("config.json")
+}
+
+/// Parsed config along with the file (the profile's) and stamp it was read
+/// at.
+struct CachedConfig {
+    path: PathBuf,
+    stamp: (Option<SystemTime>, u64),
+    config: AppConfig,
+}
+
+static CONFIG_CACHE: Mutex<Option<CachedConfig>> = Mutex::new(None);
+
+/// Why the last save couldn't move passwords to the credential store;
+/// reported once until it works again.
+static STASH_FAILED: Mutex<Option<String>> = Mutex::new(None);
+
+/// A config file that couldn't be read, and why, so it isn't saved over
+/// with the defaults it was replaced with.
+static UNREADABLE: Mutex<Option<(PathBuf, String)>> = Mutex::new(None);
+
+/// Why the active config file couldn't be read at the last load, if it
+/// couldn't; `load_config` has the defaults instead.
+pub fn load_error() -> Option<String> {
+    let path = config_path();
+    UNREADABLE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .filter(|(unreadable, _)| *unreadable == path)
+        .map(|(_, e)| e.clone())
+}
+
+/// Serializes read-modify-write cycles from concurrent commands.
+static UPDATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Modification time and size; a change in either means the file was
+/// edited behind our back.
+fn file_stamp(meta: &fs::Metadata) -> (Option<SystemTime>, u64) {
+    (meta.modified().ok(), meta.len())
+}
+
+/// A config file's path along with its stamp.
+pub type DiskStamp = (PathBuf, (Option<SystemTime>, u64));
+
+/// The config as this app last wrote it.
+static WRITTEN: Mutex<Option<DiskStamp>> = Mutex::new(None);
+
+/// Stamp of the active config file, if there is one.
+pub fn disk_stamp() -> Option<DiskStamp> {
+    let path = config_path();
+    let stamp = file_stamp(&fs::metadata(&path).ok()?);
+    Some((path, stamp))
+}
+
+/// Whether the config file at `stamp` was written by this app.
+pub fn written_here(stamp: &Option<DiskStamp>) -> bool {
+    stamp.is_some() && *WRITTEN.lock().unwrap() == *stamp
 }
 
 pub fn load_config() -> AppConfig {
     let path = config_path();
-    if !path.exists() {
+    let Ok(meta) = fs::metadata(&path) else {
+        *UNREADABLE.lock().unwrap() = None;
         return AppConfig::default();
+    };
+    let stamp = file_stamp(&meta);
+
+    let mut cache = CONFIG_CACHE.lock().unwrap();
+    if let Some(cached) = cache
+        .as_ref()
+        .filter(|c| c.path == path && c.stamp == stamp)
+    {
+        return cached.config.clone();
     }
-    match fs::read_to_string(&path) {
-        Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
-        Err(_) => AppConfig::default(),
+
+    // The file as it was stays next to the config whenever it's about to
+    // be rewritten in another format. One that can't be read is kept as
+    // well, and isn't saved over until it's fixed
+    let parsed = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read config: {}", e))
+        .and_then(|content| {
+            let parsed = dpapi::open(&content).and_then(|json| parse_config(&json));
+            match parsed {
+                Ok((_, version)) if version < CONFIG_VERSION => {
+                    info!("Migrating config from version {}", version);
+                    keep_copy(&content, &format!("config.v{}", version));
+                }
+                Ok(_) => {}
+                Err(_) => keep_copy(&content, "config.unreadable"),
+            }
+            parsed
+        });
+    let mut config = match parsed {
+        Ok((config, _)) => {
+            *UNREADABLE.lock().unwrap() = None;
+            config
+        }
+        Err(e) => {
+            error!("{}; starting from the defaults", e);
+            *UNREADABLE.lock().unwrap() = Some((path.clone(), e));
+            AppConfig::default()
+        }
+    };
+    secrets::fill(&mut config);
+    *cache = Some(CachedConfig {
+        path,
+        stamp,
+        config: config.clone(),
+    });
+    config
+}
+
+/// Copies of the config kept next to it by `keep_copy`.
+fn kept_copies() -> Vec<PathBuf> {
+    fs::read_dir(profile_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+                        n.starts_with("config.") && n.ends_with(".json") && n != "config.json"
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Writes `content`, without passwords in the clear, to
+/// `<stem>-<time>.json` in the profile directory, unless it's already kept
+/// there.
+fn keep_copy(content: &str, stem: &str) {
+    let content = scrubbed(content).unwrap_or_else(|| content.to_string());
+    if kept_copies()
+        .iter()
+        .any(|p| fs::read_to_string(p).is_ok_and(|kept| kept == content))
+    {
+        return;
+    }
+    let name = format!("{}-{}.json", stem, Local::now().format("%Y%m%d-%H%M%S%.3f"));
+    if let Err(e) = fs::write(profile_dir().join(&name), content) {
+        error!("Failed to keep a copy of the config as {}: {}", name, e);
+    }
+}
+
+pub fn backups_dir() -> PathBuf {
+    profile_dir().join("backups")
+}
+
+/// `content` with its passwords in the clear dropped, if it's a plain
+/// config that has any.
+fn scrubbed(content: &str) -> Option<String> {
+    let mut value: Value = serde_json::from_str(content).ok()?;
+    if !secrets::scrub(&mut value) {
+        return None;
+    }
+    serde_json::to_string_pretty(&value).ok()
+}
+
+/// Backups in `backups_dir`, oldest first.
+fn backups() -> Result<Vec<PathBuf>, String> {
+    // Names sort by date
+    let mut backups: Vec<PathBuf> = fs::read_dir(backups_dir())
+        .map_err(|e| format!("Failed to list backups: {}", e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("config-") && n.ends_with(".json"))
+        })
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+/// Copies the config file into `backups`, without any passwords in the
+/// clear, keeping the `keep` latest copies.
+fn back_up(path: &Path, keep: usize) -> Result<(), String> {
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+    let dir = backups_dir();
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create backups dir: {}", e))?;
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Failed to back up config: {}", e))?;
+    let name = format!("config-{}.json", Local::now().format("%Y%m%d-%H%M%S%.3f"));
+    fs::write(dir.join(name), scrubbed(&content).unwrap_or(content))
+        .map_err(|e| format!("Failed to back up config: {}", e))?;
+
+    let backups = backups()?;
+    let excess = backups.len().saturating_sub(keep);
+    for old in &backups[..excess] {
+        let _ = fs::remove_file(old);
+    }
+    Ok(())
+}
+
+/// Drops the passwords in the clear from the backups and copies taken
+/// before they moved to the credential store.
+fn scrub_copies() {
+    for path in backups()
+        .unwrap_or_default()
+        .into_iter()
+        .chain(kept_copies())
+    {
+        let Some(content) = fs::read_to_string(&path).ok().and_then(|c| scrubbed(&c)) else {
+            continue;
+        };
+        if let Err(e) = fs::write(&path, content) {
+            error!("Failed to scrub passwords from {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Encrypts the backups and copies still in the clear, stamping those that
+/// aren't a config with `version`.
+fn seal_copies(version: u32) {
+    for path in backups()
+        .unwrap_or_default()
+        .into_iter()
+        .chain(kept_copies())
+    {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        if dpapi::is_sealed(&content) {
+            continue;
+        }
+        let version = serde_json::from_str::<Value>(&content)
+            .ok()
+            .and_then(|v| v.get("version")?.as_u64())
+            .map_or(version, |v| v as u32);
+        let sealed = dpapi::seal(&content, version).and_then(|sealed| {
+            fs::write(&path, sealed).map_err(|e| format!("Failed to write: {}", e))
+        });
+        if let Err(e) = sealed {
+            error!("Failed to encrypt {}: {}", path.display(), e);
+        }
     }
 }
 
 pub fn save_config(config: &AppConfig) -> Result<(), String> {
-    let dir = config_dir();
+    if let Some((path, e)) = UNREADABLE.lock().unwrap().as_ref() {
+        if *path == config_path() {
+            return Err(format!(
+                "Not saving over {}, which can't be read ({}); fix or remove it first",
+                path.display(),
+                e
+            ));
+        }
+    }
+    let dir = profile_dir();
     fs::create_dir_all(&dir).map_err(|e| format!("Failed to create config dir: {}", e))?;
-    let json =
-        serde_json::to_string_pretty(config).map_err(|e| format!("Failed to serialize: {}", e))?;
-    fs::write(config_path(), json).map_err(|e| format!("Failed to write config: {}", e))?;
+    // Passwords go to the credential store, not the file, unless it's
+    // unavailable
+    let mut on_disk = config.clone();
+    let stash_failed = secrets::stash(&mut on_disk);
+    {
+        let mut failed = STASH_FAILED.lock().unwrap();
+        if let Some(e) = stash_failed.as_ref().filter(|_| failed.is_none()) {
+            error!("{}; passwords stay in the config file until it's back", e);
+        }
+        *failed = stash_failed;
+    }
+    let mut json = serde_json::to_string_pretty(&on_disk)
+        .map_err(|e| format!("Failed to serialize: {}", e))?;
+    if config.settings.encrypt_config {
+        json = dpapi::seal(&json, config.version)?;
+    }
+
+    let mut cache = CONFIG_CACHE.lock().unwrap();
+    let path = config_path();
+    // Turning encryption on leaves the backups and copies in the clear
+    let sealing = config.settings.encrypt_config
+        && !fs::read_to_string(&path).is_ok_and(|content| dpapi::is_sealed(&content));
+    // A backup failing shouldn't stop the save
+    if let Err(e) = back_up(&path, config.settings.config_backups) {
+        error!("{}", e);
+    }
+    // Written aside and renamed over the config, so a crash mid-write
+    // leaves the old one whole
+    let tmp = dir.join("config.json.tmp");
+    let written = fs::File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(json.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, &path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        *cache = None;
+        return Err(format!("Failed to write config: {}", e));
+    }
+    *cache = fs::metadata(&path).ok().map(|meta| CachedConfig {
+        path: path.clone(),
+        stamp: file_stamp(&meta),
+        config: config.clone(),
+    });
+    *WRITTEN.lock().unwrap() = cache.as_ref().map(|c| (c.path.clone(), c.stamp));
+    if sealing {
+        seal_copies(config.version);
+    }
     Ok(())
 }
+
+/// Makes `name` the active profile, creating it (with the current settings
+/// and no tunnels) if it doesn't exist yet, and returns its config.
+pub fn switch_profile(name: &str) -> Result<AppConfig, String> {
+    profiles::validate_name(name)?;
+    let _guard = UPDATE_LOCK.lock().unwrap();
+    let previous = profiles::active();
+    let current = load_config();
+    let created = !profiles::dir(name).join("config.json").exists();
+    profiles::set_active(name)?;
+    if created && name != profiles::DEFAULT {
+        let fresh = AppConfig {
+            settings: current.settings,
+            ..AppConfig::default()
+        };
+        if let Err(e) = save_config(&fresh) {
+            let _ = profiles::set_active(&previous);
+            return Err(e);
+        }
+    }
+    Ok(load_config())
+}
+
+/// `load_config` on the blocking pool, for use from async code.
+pub async fn load_config_async() -> AppConfig {
+    tokio::task::spawn_blocking(load_config)
+        .await
+        .unwrap_or_default()
+}
+
+/// Saves the config again if it still has passwords in the clear, which
+/// moves them to the credential store.
+pub async fn stash_secrets() -> Result<(), String> {
+    tokio::task::spawn_blocking(|| {
+        let _guard = UPDATE_LOCK.lock().unwrap();
+        let config = load_config();
+        if !secrets::any_in_clear(&config) {
+            return Ok(());
+        }
+        info!("Moving stored passwords to the credential manager");
+        save_config(&config)?;
+        if STASH_FAILED.lock().unwrap().is_none() {
+            scrub_copies();
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("Config update failed: {}", e))?
+}
+
+/// Loads the config, applies `f` and saves the result, all on the blocking
+/// pool. Returns the saved config.
+pub async fn update_config<F>(f: F) -> Result<AppConfig, String>
+where
+    F: FnOnce(&mut AppConfig) -> Result<(), String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let _guard = UPDATE_LOCK.lock().unwrap();
+        let mut config = load_config();
+        f(&mut config)?;
+        save_config(&config)?;
+        Ok(config)
+    })
+    .await
+    .map_err(|e| format!("Config update failed: {}", e))?
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,123 @@
+use super::*;
+
+fn test_tunnel(name: &str) -> TunnelConfig {
+    TunnelConfig::new(
+        name.to_string(),
+        "bastion.invalid".to_string(),
+        "tester".to_string(),
+    )
+}
+
+#[test]
+fn dependency_loops_are_refused() {
+    let mut socks = test_tunnel("socks");
+    let mut db = test_tunnel("db");
+    db.depends_on = Some(socks.id.clone());
+    let tunnels = vec![socks.clone(), db.clone()];
+    assert!(check_depends_on(&tunnels, &db).is_ok());
+
+    socks.depends_on = Some(db.id.clone());
+    let err = check_depends_on(&tunnels, &socks).unwrap_err();
+    assert!(err.contains("loop"), "{}", err);
+
+    db.depends_on = Some("gone".to_string());
+    assert!(check_depends_on(&tunnels, &db).is_err());
+}
+
+#[test]
+fn tunnel_filter_matches_tag_host_and_status() {
+    let mut tunnel = test_tunnel("db");
+    tunnel.tags = vec!["prod".to_string(), "db".to_string()];
+
+    let by_tag = TunnelFilter {
+        tag: Some("PROD".to_string()),
+        ..Default::default()
+    };
+    assert!(by_tag.matches(&tunnel, TunnelStatus::Stopped));
+    let by_host = TunnelFilter {
+        host: Some("bastion".to_string()),
+        status: Some(TunnelStatus::Running),
+        ..Default::default()
+    };
+    assert!(by_host.matches(&tunnel, TunnelStatus::Running));
+    assert!(!by_host.matches(&tunnel, TunnelStatus::Stopped));
+    let other_tag = TunnelFilter {
+        tag: Some("staging".to_string()),
+        ..by_host
+    };
+    assert!(!other_tag.matches(&tunnel, TunnelStatus::Running));
+}
+
+#[test]
+fn reorder_moves_listed_tunnels_first() {
+    let tunnels: Vec<TunnelConfig> = ["a", "b", "c"].into_iter().map(test_tunnel).collect();
+    let names =
+        |tunnels: &[TunnelConfig]| tunnels.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+
+    let mut reordered = tunnels.clone();
+    reorder(&mut reordered, &[tunnels[2].id.clone()]).unwrap();
+    assert_eq!(names(&reordered), ["c", "a", "b"]);
+
+    let ids = [tunnels[1].id.clone(), tunnels[1].id.clone()];
+    assert!(reorder(&mut tunnels.clone(), &ids).is_err());
+}
+
+#[test]
+fn paths_expand_home_and_environment_variables() {
+    // Set here and read nowhere else, so no other test sees it change
+    std::env::set_var("OPENTUNNEL_TEST_EXPAND_KEYS", "/keys");
+    let home = expand_path("~");
+    assert!(!home.is_empty() && home != "~");
+    assert_eq!(expand_path("~/.ssh/id"), format!("{}/.ssh/id", home));
+    assert_eq!(expand_path("$OPENTUNNEL_TEST_EXPAND_KEYS/id"), "/keys/id");
+    assert_eq!(
+        expand_path("${OPENTUNNEL_TEST_EXPAND_KEYS}_old"),
+        "/keys_old"
+    );
+    assert_eq!(
+        expand_path("%OPENTUNNEL_TEST_EXPAND_KEYS%\\id.ppk"),
+        "/keys\\id.ppk"
+    );
+    assert_eq!(
+        expand_path("$OPENTUNNEL_TEST_UNSET/id"),
+        "$OPENTUNNEL_TEST_UNSET/id"
+    );
+    assert_eq!(expand_path("100% $5"), "100% $5");
+}
+
+#[test]
+fn unversioned_config_is_migrated_and_newer_refused() {
+    let old = r#"{"tunnels": [], "settings": {"plinkPath": "plink.exe"}}"#;
+    let (config, version) = parse_config(old).unwrap();
+    assert_eq!(version, 0);
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!(config.settings.plink_path, "plink.exe");
+
+    let newer = r#"{"version": 999, "tunnels": [], "settings": {}}"#;
+    assert!(parse_config(newer).is_err());
+}
+
+#[test]
+fn pinned_host_keys_match_their_sha256_fingerprint() {
+    let sha256 = "nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8";
+    assert!(pin_matches(&format!("SHA256:{}", sha256), sha256));
+    assert!(pin_matches(sha256, sha256));
+    assert!(pin_matches(&format!("SHA256:{}=", sha256), sha256));
+    assert!(pin_matches(
+        &format!("ssh-ed25519 255 SHA256:{}", sha256),
+        sha256
+    ));
+    assert!(!pin_matches(
+        "SHA256:AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl",
+        sha256
+    ));
+    assert!(!pin_matches("", sha256));
+
+    let md5 = "16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48";
+    assert!(is_md5_fingerprint(md5));
+    assert!(is_md5_fingerprint(
+        "MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48"
+    ));
+    assert!(!pin_matches(md5, sha256));
+    assert!(!is_md5_fingerprint(sha256));
+}
@@ -0,0 +1,96 @@
+//! Reloads the config when it's edited outside the app (by hand, by a sync
+//! tool): emits `config-changed` with the new config and brings running
+//! tunnels in line with their new definitions. The file is polled, going
+//! by the same stamp as the config cache. An edit that can't be read is
+//! ignored until it's fixed.
+
+use crate::config::{self, AppConfig, TunnelConfig};
+use crate::jumplist;
+use crate::notify;
+use crate::tunnel::{self, TunnelManager};
+use log::{info, warn};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+const POLL: Duration = Duration::from_secs(2);
+
+pub async fn run(manager: TunnelManager, app_handle: AppHandle) {
+    let mut known = config::disk_stamp();
+    let mut previous = config::load_config_async().await;
+    loop {
+        tokio::time::sleep(POLL).await;
+        let stamp = config::disk_stamp();
+        if stamp == known {
+            continue;
+        }
+        // Switching profiles changes the file, not its contents
+        let edited = !config::written_here(&stamp)
+            && stamp.as_ref().map(|s| &s.0) == known.as_ref().map(|s| &s.0);
+        known = stamp;
+        let current = config::load_config_async().await;
+        // A half-saved or mistyped edit reads as the defaults; acting on
+        // those would stop every tunnel
+        if let Some(e) = config::load_error() {
+            warn!("Ignoring the config on disk until it's fixed: {}", e);
+            if edited {
+                notify::send(
+                    &app_handle,
+                    "config",
+                    "error",
+                    format!("The config file can't be read, ignoring the edit: {}", e),
+                );
+            }
+            continue;
+        }
+        if edited {
+            info!("Config changed on disk, reloading it");
+            let _ = app_handle.emit("config-changed", &current);
+            jumplist::refresh(&current);
+            reconcile(&manager, &previous, &current, &app_handle).await;
+        }
+        previous = current;
+    }
+}
+
+fn changed(before: &TunnelConfig, after: &TunnelConfig) -> bool {
+    serde_json::to_value(before).ok() != serde_json::to_value(after).ok()
+}
+
+/// Stops running tunnels that were deleted or disabled, and restarts those
+/// whose definition changed.
+async fn reconcile(
+    manager: &TunnelManager,
+    previous: &AppConfig,
+    current: &AppConfig,
+    app_handle: &AppHandle,
+) {
+    for id in manager.ids() {
+        let before = previous.tunnels.iter().find(|t| t.id == id);
+        let Some(after) = current.tunnels.iter().find(|t| t.id == id && t.enabled) else {
+            match tunnel::stop_tunnel(manager, &id, app_handle).await {
+                Ok(()) => info!("Stopped '{}': removed or disabled in the config", id),
+                Err(e) => warn!("Failed to stop '{}': {}", id, e),
+            }
+            continue;
+        };
+        if !before.is_some_and(|before| changed(before, after)) {
+            continue;
+        }
+        info!("Restarting '{}' with its new definition", after.name);
+        let restarted = match tunnel::stop_tunnel(manager, &id, app_handle).await {
+            Ok(()) => {
+                tunnel::start_tunnel(
+                    manager,
+                    after,
+                    &current.settings.plink_path,
+                    app_handle.clone(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = restarted {
+            warn!("Failed to restart '{}': {}", after.name, e);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::config::{AuthMethod, TunnelConfig};
+use serde::Serialize;
+use std::fs;
+use std::process::Command;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentDiagnostics {
+    pub pageant: bool,
+    #[serde(rename = "windowsAgent")]
+    pub windows_agent: bool,
+    #[serde(rename = "sshAuthSock")]
+    pub ssh_auth_sock: Option<String>,
+    #[serde(rename = "sshAuthSockReachable")]
+    pub ssh_auth_sock_reachable: bool,
+    /// `None` when there is no key to look for or no agent we can list.
+    #[serde(rename = "keyLoaded")]
+    pub key_loaded: Option<bool>,
+    pub messages: Vec<String>,
+}
+
+/// Process id of the running Pageant.
+#[cfg(windows)]
+pub fn pageant_pid() -> Option<u32> {
+    let output = Command::new("tasklist")
+        .args(["/FI", "IMAGENAME eq pageant.exe", "/FO", "CSV", "/NH"])
+        .output()
+        .ok()?;
+    // "pageant.exe","1234","Console","1","12,345 K"
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split(',').map(|f| f.trim_matches('"'));
+            if !fields.next()?.eq_ignore_ascii_case("pageant.exe") {
+                return None;
+            }
+            fields.next()?.parse().ok()
+        })
+}
+
+#[cfg(not(windows))]
+pub fn pageant_pid() -> Option<u32> {
+    None
+}
+
+pub fn pageant_running() -> bool {
+    pageant_pid().is_some()
+}
+
+#[cfg(windows)]
+fn windows_agent_running() -> bool {
+    Command::new("sc")
+        .args(["query", "ssh-agent"])
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).contains("RUNNING"))
+        .unwrap_or(false)
+}
+
+#[cfg(not(windows))]
+fn windows_agent_running() -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn socket_reachable(path: &str) -> bool {
+    std::os::unix::net::UnixStream::connect(path).is_ok()
+}
+
+#[cfg(not(unix))]
+fn socket_reachable(path: &str) -> bool {
+    std::path::Path::new(path).exists()
+}
+
+/// Base64 public key blob of a private key, read from `<key>.pub` or the
+/// `Public-Lines` section of a PuTTY `.ppk` file.
+fn public_key_blob(key_path: &str) -> Option<String> {
+    let pub_path = format!("{}.pub", key_path);
+    if let Ok(content) = fs::read_to_string(&pub_path) {
+        return content.split_whitespace().nth(1).map(str::to_string);
+    }
+
+    let content = fs::read_to_string(key_path).ok()?;
+    let mut lines = content.lines();
+    let count: usize = lines
+        .by_ref()
+        .find_map(|l| l.strip_prefix("Public-Lines:"))?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(lines.take(count).map(str::trim).collect())
+}
+
+/// Public key blobs held by the OpenSSH agent (`ssh-add -L`), if reachable.
+fn agent_key_blobs() -> Option<Vec<String>> {
+    let output = Command::new("ssh-add").arg("-L").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|l| l.split_whitespace().nth(1).map(str::to_string))
+            .collect(),
+    )
+}
+
+/// Reports which SSH agents are available and whether the tunnel's key is
+/// loaded in one of them.
+pub fn check_agents(tunnel: Option<&TunnelConfig>) -> AgentDiagnostics {
+    let mut messages = Vec::new();
+
+    let pageant = pageant_running();
+    let windows_agent = windows_agent_running();
+    let ssh_auth_sock = std::env::var("SSH_AUTH_SOCK")
+        .ok()
+        .filter(|s| !s.is_empty());
+    let ssh_auth_sock_reachable = ssh_auth_sock
+        .as_deref()
+        .map(socket_reachable)
+        .unwrap_or(false);
+
+    if cfg!(windows) {
+        messages.push(format!(
+            "Pageant: {}",
+            if pageant { "running" } else { "not running" }
+        ));
+        messages.push(format!(
+            "Windows ssh-agent service: {}",
+            if windows_agent {
+                "running"
+            } else {
+                "not running"
+            }
+        ));
+    }
+    match &ssh_auth_sock {
+        Some(sock) if ssh_auth_sock_reachable => {
+            messages.push(format!("SSH_AUTH_SOCK: {} (reachable)", sock))
+        }
+        Some(sock) => messages.push(format!("SSH_AUTH_SOCK: {} (not reachable)", sock)),
+        None => messages.push("SSH_AUTH_SOCK: not set".to_string()),
+    }
+
+    let key_path = tunnel
+        .filter(|t| t.auth_method == AuthMethod::Key)
+        .and_then(|t| t.key_path.as_deref())
+        .map(crate::config::expand_path);
+    let key_path = key_path.as_deref();
+    let key_loaded = match key_path {
+        None => None,
+        Some(key) => match (public_key_blob(key), agent_key_blobs()) {
+            (None, _) => {
+                messages.push(format!("Could not read public key for {}", key));
+                None
+            }
+            (Some(_), None) => {
+                if pageant {
+                    messages.push("Key status unknown: Pageant keys can't be listed".to_string());
+                } else {
+                    messages.push("No OpenSSH agent reachable to list keys".to_string());
+                }
+                None
+            }
+            (Some(blob), Some(loaded)) => {
+                let found = loaded.contains(&blob);
+                messages.push(format!(
+                    "Key {} {} loaded in the agent",
+                    key,
+                    if found { "is" } else { "is not" }
+                ));
+                Some(found)
+            }
+        },
+    };
+
+    // Agent tunnels only need the agent to hold some key
+    let key_loaded = match tunnel.map(|t| &t.auth_method) {
+        Some(AuthMethod::Agent) => {
+            if windows_agent && !pageant {
+                messages.push(
+                    "plink only uses Pageant; keys in the Windows ssh-agent need ssh.exe as the SSH client"
+                        .to_string(),
+                );
+            }
+            match agent_key_blobs() {
+                Some(keys) => {
+                    messages.push(format!("The OpenSSH agent holds {} key(s)", keys.len()));
+                    Some(!keys.is_empty())
+                }
+                None => key_loaded,
+            }
+        }
+        _ => key_loaded,
+    };
+
+    AgentDiagnostics {
+        pageant,
+        windows_agent,
+        ssh_auth_sock,
+        ssh_auth_sock_reachable,
+        key_loaded,
+        messages,
+    }
+}
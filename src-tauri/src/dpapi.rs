@@ -0,0 +1,106 @@
+//! Encryption of the config file at rest with Windows DPAPI, tied to the
+//! user account: other accounts, and copies of the file taken off the
+//! machine, can't read it. An encrypted config is a JSON envelope,
+//! `{"version": N, "dpapi": "<hex>"}`, so it's still recognizable as ours.
+
+use serde_json::Value;
+
+/// Field of the envelope holding the encrypted config.
+const FIELD: &str = "dpapi";
+
+/// `json` wrapped in an encrypted envelope stamped with `version`.
+pub fn seal(json: &str, version: u32) -> Result<String, String> {
+    let sealed = protect(json.as_bytes())?;
+    let hex: String = sealed.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(serde_json::json!({ "version": version, FIELD: hex }).to_string())
+}
+
+/// Whether `content` is an encrypted envelope.
+pub fn is_sealed(content: &str) -> bool {
+    serde_json::from_str::<Value>(content).is_ok_and(|v| v.get(FIELD).is_some_and(Value::is_string))
+}
+
+/// The config inside `content` if it's an encrypted envelope, or `content`
+/// itself if it's a plain config.
+pub fn open(content: &str) -> Result<String, String> {
+    let Ok(Value::Object(envelope)) = serde_json::from_str::<Value>(content) else {
+        return Ok(content.to_string());
+    };
+    let Some(hex) = envelope.get(FIELD).and_then(Value::as_str) else {
+        return Ok(content.to_string());
+    };
+    let sealed = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or(""), 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "Encrypted config is corrupt".to_string())?;
+    let plain = unprotect(&sealed)?;
+    String::from_utf8(plain).map_err(|_| "Encrypted config is corrupt".to_string())
+}
+
+#[cfg(windows)]
+fn protect(data: &[u8]) -> Result<Vec<u8>, String> {
+    crypt(data, true).map_err(|e| format!("Failed to encrypt config: {}", e))
+}
+
+#[cfg(windows)]
+fn unprotect(data: &[u8]) -> Result<Vec<u8>, String> {
+    crypt(data, false).map_err(|e| {
+        format!(
+            "Failed to decrypt config (was it encrypted by another user?): {}",
+            e
+        )
+    })
+}
+
+#[cfg(windows)]
+fn crypt(data: &[u8], encrypt: bool) -> windows::core::Result<Vec<u8>> {
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{LocalFree, HLOCAL};
+    use windows::Win32::Security::Cryptography::{
+        CryptProtectData, CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB,
+    };
+
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: data.len() as u32,
+        pbData: data.as_ptr() as *mut u8,
+    };
+    let mut output = CRYPT_INTEGER_BLOB::default();
+    unsafe {
+        if encrypt {
+            CryptProtectData(
+                &input,
+                PCWSTR::null(),
+                None,
+                None,
+                None,
+                CRYPTPROTECT_UI_FORBIDDEN,
+                &mut output,
+            )?;
+        } else {
+            CryptUnprotectData(
+                &input,
+                None,
+                None,
+                None,
+                None,
+                CRYPTPROTECT_UI_FORBIDDEN,
+                &mut output,
+            )?;
+        }
+        // The output buffer is ours to free
+        let bytes = std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec();
+        let _ = LocalFree(Some(HLOCAL(output.pbData as *mut std::ffi::c_void)));
+        Ok(bytes)
+    }
+}
+
+#[cfg(not(windows))]
+fn protect(_data: &[u8]) -> Result<Vec<u8>, String> {
+    Err("Config encryption is only available on Windows".to_string())
+}
+
+#[cfg(not(windows))]
+fn unprotect(_data: &[u8]) -> Result<Vec<u8>, String> {
+    Err("This config was encrypted on Windows and can't be read here".to_string())
+}
@@ -0,0 +1,112 @@
+use crate::config::{self, TunnelConfig, TunnelType};
+use crate::tunnel::{self, TunnelManager, TunnelStatus};
+use log::{info, warn};
+use serde::Serialize;
+use std::time::Duration;
+use tauri::{AppHandle, Runtime};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+/// Server ports handed out to exposed services.
+const PORT_RANGE: (u16, u16) = (20000, 29999);
+/// Ports tried before giving up.
+const ATTEMPTS: usize = 3;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A local service published on a server, ngrok style.
+#[derive(Debug, Clone, Serialize)]
+pub struct Exposure {
+    pub tunnel: TunnelConfig,
+    /// Public `host:port` the service is reachable at.
+    pub endpoint: String,
+}
+
+/// Remote tunnel publishing `local_port` on a random port of `server`,
+/// logging in the same way `server` does.
+fn exposure_config(server: &TunnelConfig, local_port: u16, remote_port: u16) -> TunnelConfig {
+    let mut tunnel =
+        TunnelConfig::on_server_of(format!("Expose :{} on {}", local_port, server.host), server);
+    tunnel.tunnel_type = TunnelType::Remote;
+    tunnel.local_port = local_port;
+    tunnel.remote_port = remote_port;
+    tunnel.bind_address = Some("0.0.0.0".to_string());
+    tunnel.access_log = true;
+    tunnel
+}
+
+fn random_port() -> u16 {
+    let (low, high) = PORT_RANGE;
+    low + (Uuid::new_v4().as_u128() % u128::from(high - low + 1)) as u16
+}
+
+/// Publishes `local_port` on `server` and checks it's reachable from here.
+/// Ports the server refuses are retried with another one.
+pub async fn expose<R: Runtime>(
+    manager: &TunnelManager,
+    server: &TunnelConfig,
+    local_port: u16,
+    plink_path: &str,
+    app_handle: &AppHandle<R>,
+) -> Result<Exposure, String> {
+    if server.provider.is_some() {
+        return Err(format!("'{}' isn't an SSH tunnel", server.name));
+    }
+
+    let mut last_error = String::new();
+    for _ in 0..ATTEMPTS {
+        let tunnel = exposure_config(server, local_port, random_port());
+        let endpoint = format!(
+            "{}:{}",
+            config::bracket_host(&tunnel.host),
+            tunnel.remote_port
+        );
+
+        tunnel::start_tunnel(manager, &tunnel, plink_path, app_handle.clone()).await?;
+        match verify(manager, &tunnel).await {
+            Ok(()) => {
+                info!("Exposed port {} at {}", local_port, endpoint);
+                return Ok(Exposure { tunnel, endpoint });
+            }
+            Err(e) => {
+                warn!("Exposing port {} at {} failed: {}", local_port, endpoint, e);
+                tunnel::stop_tunnel(manager, &tunnel.id, app_handle).await?;
+                last_error = e;
+            }
+        }
+    }
+    Err(format!(
+        "Couldn't expose port {} on {}: {}",
+        local_port, server.host, last_error
+    ))
+}
+
+/// Waits for the tunnel to connect, then connects to its public endpoint.
+async fn verify(manager: &TunnelManager, tunnel: &TunnelConfig) -> Result<(), String> {
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    loop {
+        let state = manager.states().into_iter().find(|s| s.id == tunnel.id);
+        match state {
+            Some(s) if s.status == TunnelStatus::Running => break,
+            Some(s) if s.status == TunnelStatus::Error => {
+                return Err(s.last_error.unwrap_or_else(|| "Tunnel failed".to_string()))
+            }
+            None => return Err("Tunnel stopped".to_string()),
+            _ => {}
+        }
+        if Instant::now() >= deadline {
+            return Err("Timed out connecting".to_string());
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    let probe = TcpStream::connect((tunnel.host.as_str(), tunnel.remote_port));
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(Ok(_)) => Ok(()),
+        _ => Err(format!(
+            "{}:{} isn't reachable (is GatewayPorts enabled on the server?)",
+            tunnel.host, tunnel.remote_port
+        )),
+    }
+}
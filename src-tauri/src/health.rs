@@ -0,0 +1,124 @@
+//! Overall health of the running tunnels, emitted as `app-health` whenever
+//! it changes so the tray and dashboards don't have to work it out from
+//! every tunnel's state, and each tunnel's recent health check results.
+
+use crate::tunnel::{TunnelManager, TunnelState, TunnelStatus};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Listener, Manager};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HealthStatus {
+    /// No tunnel is failing (including when none is running).
+    AllHealthy,
+    /// Some tunnels are up, others failing.
+    Degraded,
+    /// Every tunnel that should be up is failing.
+    AllDown,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AppHealth {
+    pub status: HealthStatus,
+    pub running: usize,
+    /// Tunnels in error or waiting to reconnect.
+    pub failing: usize,
+}
+
+/// One health check of a tunnel.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthSample {
+    pub at: DateTime<Utc>,
+    pub ok: bool,
+    /// Round trip of the end-to-end probe, if it has one.
+    #[serde(rename = "latencyMs", skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Checks kept per tunnel.
+const HISTORY_LEN: usize = 120;
+
+static LAST: Mutex<Option<AppHealth>> = Mutex::new(None);
+/// Recent checks by tunnel id.
+static HISTORY: Mutex<Vec<(String, VecDeque<HealthSample>)>> = Mutex::new(Vec::new());
+
+/// Records the outcome of one of `tunnel_id`'s health checks.
+pub fn record(tunnel_id: &str, outcome: Result<Option<Duration>, &str>) {
+    let sample = HealthSample {
+        at: Utc::now(),
+        ok: outcome.is_ok(),
+        latency_ms: outcome.ok().flatten().map(|rtt| rtt.as_millis() as u64),
+        error: outcome.err().map(str::to_string),
+    };
+    let mut history = HISTORY.lock().unwrap();
+    let i = match history.iter().position(|(id, _)| id == tunnel_id) {
+        Some(i) => i,
+        None => {
+            history.push((tunnel_id.to_string(), VecDeque::new()));
+            history.len() - 1
+        }
+    };
+    let samples = &mut history[i].1;
+    if samples.len() == HISTORY_LEN {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+/// `tunnel_id`'s recent health checks, oldest first.
+pub fn history(tunnel_id: &str) -> Vec<HealthSample> {
+    HISTORY
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(id, _)| id == tunnel_id)
+        .map(|(_, samples)| samples.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+pub fn forget(tunnel_id: &str) {
+    HISTORY.lock().unwrap().retain(|(id, _)| id != tunnel_id);
+}
+
+/// Sums up tracked tunnels; ones starting count for neither side.
+pub fn aggregate(states: &[TunnelState]) -> AppHealth {
+    let count = |f: fn(TunnelStatus) -> bool| states.iter().filter(|s| f(s.status)).count();
+    let running = count(|s| s == TunnelStatus::Running);
+    let failing = count(|s| matches!(s, TunnelStatus::Error | TunnelStatus::Reconnecting));
+    let status = match (running, failing) {
+        (_, 0) => HealthStatus::AllHealthy,
+        (0, _) => HealthStatus::AllDown,
+        _ => HealthStatus::Degraded,
+    };
+    AppHealth {
+        status,
+        running,
+        failing,
+    }
+}
+
+pub fn current(manager: &TunnelManager) -> AppHealth {
+    aggregate(&manager.states())
+}
+
+/// Emits `app-health` after every `tunnel-status` that changes it.
+pub fn init(app: &AppHandle) {
+    let handle = app.clone();
+    app.listen("tunnel-status", move |_event| {
+        let health = current(&handle.state::<TunnelManager>());
+        {
+            let mut last = LAST.lock().unwrap();
+            if last.as_ref() == Some(&health) {
+                return;
+            }
+            *last = Some(health.clone());
+        }
+        let _ = handle.emit("app-health", &health);
+    });
+}
@@ -0,0 +1,150 @@
+use log::info;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const MARKER: &str = "# opentunnel:";
+
+pub fn hosts_path() -> PathBuf {
+    #[cfg(windows)]
+    {
+        let root = std::env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".to_string());
+        PathBuf::from(root).join(r"System32\drivers\etc\hosts")
+    }
+    #[cfg(not(windows))]
+    {
+        PathBuf::from("/etc/hosts")
+    }
+}
+
+fn is_valid_alias(alias: &str) -> bool {
+    !alias.is_empty()
+        && alias.len() <= 253
+        && alias
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+}
+
+fn is_owned_by(line: &str, tunnel_id: &str) -> bool {
+    line.trim_end()
+        .ends_with(&format!("{}{}", MARKER, tunnel_id))
+}
+
+/// Adds (or replaces) the `127.0.0.1 <alias>` entry owned by a tunnel.
+pub fn add_alias(tunnel_id: &str, alias: &str) -> Result<(), String> {
+    if !is_valid_alias(alias) {
+        return Err(format!("Invalid hosts alias '{}'", alias));
+    }
+
+    let entry = format!("127.0.0.1\t{}\t{}{}", alias, MARKER, tunnel_id);
+    let content = read_hosts()?;
+    if content.lines().any(|l| l.trim_end() == entry) {
+        return Ok(());
+    }
+
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut lines: Vec<&str> = content
+        .lines()
+        .filter(|l| !is_owned_by(l, tunnel_id))
+        .collect();
+    lines.push(&entry);
+
+    write_hosts(&(lines.join(newline) + newline))?;
+    info!("Added hosts alias '{}'", alias);
+    Ok(())
+}
+
+/// Removes any hosts entry owned by a tunnel. No-op if there is none.
+pub fn remove_alias(tunnel_id: &str) -> Result<(), String> {
+    let content = read_hosts()?;
+    if !content.lines().any(|l| is_owned_by(l, tunnel_id)) {
+        return Ok(());
+    }
+
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let lines: Vec<&str> = content
+        .lines()
+        .filter(|l| !is_owned_by(l, tunnel_id))
+        .collect();
+
+    write_hosts(&(lines.join(newline) + newline))?;
+    info!("Removed hosts alias for tunnel {}", tunnel_id);
+    Ok(())
+}
+
+fn read_hosts() -> Result<String, String> {
+    let path = hosts_path();
+    match fs::read_to_string(&path) {
+        Ok(c) => Ok(c),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+fn write_hosts(content: &str) -> Result<(), String> {
+    let path = hosts_path();
+    match replace(&path, content) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => write_elevated(content),
+        Err(e) => Err(format!("Failed to write {}: {}", path.display(), e)),
+    }
+}
+
+/// Writes `content` next to `path` and renames it over it, so a crash
+/// halfway never leaves a truncated hosts file. The new file gets the old
+/// one's permissions.
+fn replace(path: &Path, content: &str) -> std::io::Result<()> {
+    let tmp = path.with_extension("opentunnel.tmp");
+    let written = fs::write(&tmp, content)
+        .and_then(|()| match fs::metadata(path) {
+            Ok(old) => fs::set_permissions(&tmp, old.permissions()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        })
+        .and_then(|()| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// Copies the new hosts file into place through an elevated (UAC) copy.
+#[cfg(windows)]
+fn write_elevated(content: &str) -> Result<(), String> {
+    use std::process::Command;
+
+    let tmp = std::env::temp_dir().join("opentunnel-hosts.tmp");
+    fs::write(&tmp, content).map_err(|e| format!("Failed to write temp hosts file: {}", e))?;
+
+    let script = format!(
+        "$p = Start-Process -FilePath cmd -ArgumentList '/c copy /y \"{}\" \"{}\"' -Verb RunAs -Wait -WindowStyle Hidden -PassThru; exit $p.ExitCode",
+        tmp.display(),
+        hosts_path().display()
+    );
+    let status = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .status()
+        .map_err(|e| format!("Failed to request elevation: {}", e));
+    let _ = fs::remove_file(&tmp);
+
+    match status? {
+        s if s.success() => Ok(()),
+        _ => Err("Elevation was refused; hosts file not updated".to_string()),
+    }
+}
+
+#[cfg(not(windows))]
+fn write_elevated(_content: &str) -> Result<(), String> {
+    Err(format!(
+        "Permission denied writing {}; OpenTunnel needs write access to manage aliases",
+        hosts_path().display()
+    ))
+}
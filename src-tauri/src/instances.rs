@@ -0,0 +1,210 @@
+use crate::config::{self, TunnelConfig, TunnelType};
+use chrono::Utc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// Leases older than this are considered left behind by a dead instance.
+const LEASE_TTL_SECS: i64 = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortClaim {
+    #[serde(rename = "tunnelId")]
+    pub tunnel_id: String,
+    #[serde(rename = "tunnelName")]
+    pub tunnel_name: String,
+    pub port: u16,
+}
+
+/// What a running instance publishes about itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceLease {
+    pub pid: u32,
+    pub user: String,
+    #[serde(rename = "configPath")]
+    pub config_path: String,
+    pub claims: Vec<PortClaim>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: i64,
+}
+
+impl InstanceLease {
+    pub fn owner(&self) -> String {
+        format!("OpenTunnel pid {} ({})", self.pid, self.user)
+    }
+}
+
+/// A tunnel or port held by another instance, for labelling in the UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct ForeignClaim {
+    #[serde(rename = "tunnelId")]
+    pub tunnel_id: Option<String>,
+    pub port: u16,
+    pub owner: String,
+}
+
+/// Machine-wide so instances of other users are visible too.
+fn registry_dir() -> PathBuf {
+    // Tests neither see nor disturb the instances actually running
+    if cfg!(test) {
+        return std::env::temp_dir().join(format!("opentunnel-instances-{}", std::process::id()));
+    }
+    #[cfg(windows)]
+    {
+        let base = std::env::var("ProgramData").unwrap_or_else(|_| r"C:\ProgramData".to_string());
+        PathBuf::from(base).join("OpenTunnel").join("instances")
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::temp_dir().join("opentunnel-instances")
+    }
+}
+
+fn ensure_registry_dir() -> std::io::Result<PathBuf> {
+    let dir = registry_dir();
+    if !dir.exists() {
+        fs::create_dir_all(&dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&dir, fs::Permissions::from_mode(0o1777));
+        }
+    }
+    Ok(dir)
+}
+
+fn lease_path(pid: u32) -> PathBuf {
+    registry_dir().join(format!("{}.json", pid))
+}
+
+fn current_user() -> String {
+    std::env::var("USERNAME")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Local port `tunnel` listens on, if any.
+pub fn claimed_port(tunnel: &TunnelConfig) -> Option<u16> {
+    match tunnel.tunnel_type {
+        TunnelType::Local | TunnelType::Dynamic => Some(tunnel.local_port),
+        TunnelType::Remote => None,
+    }
+}
+
+/// Writes this instance's lease; also serves as the heartbeat.
+pub fn publish<'a>(running: impl Iterator<Item = &'a TunnelConfig>) {
+    let lease = InstanceLease {
+        pid: std::process::id(),
+        user: current_user(),
+        config_path: config::config_path().to_string_lossy().to_string(),
+        claims: running
+            .filter_map(|t| {
+                claimed_port(t).map(|port| PortClaim {
+                    tunnel_id: t.id.clone(),
+                    tunnel_name: t.name.clone(),
+                    port,
+                })
+            })
+            .collect(),
+        updated_at: Utc::now().timestamp(),
+    };
+
+    let result = ensure_registry_dir().and_then(|_| {
+        let json = serde_json::to_string(&lease).map_err(std::io::Error::other)?;
+        fs::write(lease_path(lease.pid), json)
+    });
+    if let Err(e) = result {
+        warn!("Failed to publish instance lease: {}", e);
+    }
+}
+
+/// `publish` on the blocking pool, for use from async code.
+pub async fn publish_async(running: Vec<TunnelConfig>) {
+    let _ = tokio::task::spawn_blocking(move || publish(running.iter())).await;
+}
+
+/// Removes this instance's lease.
+pub fn release() {
+    let _ = fs::remove_file(lease_path(std::process::id()));
+}
+
+/// Live leases of every other instance.
+pub fn others() -> Vec<InstanceLease> {
+    let own_pid = std::process::id();
+    let now = Utc::now().timestamp();
+    let Ok(entries) = fs::read_dir(registry_dir()) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .filter_map(|c| serde_json::from_str::<InstanceLease>(&c).ok())
+        .filter(|l| l.pid != own_pid && now - l.updated_at <= LEASE_TTL_SECS)
+        .collect()
+}
+
+/// `others` on the blocking pool, for use from async code.
+async fn others_async() -> Vec<InstanceLease> {
+    tokio::task::spawn_blocking(others)
+        .await
+        .unwrap_or_default()
+}
+
+/// Other instances working on the same config file as us.
+pub fn sharing_config() -> Vec<InstanceLease> {
+    let ours = config::config_path().to_string_lossy().to_string();
+    others()
+        .into_iter()
+        .filter(|l| l.config_path == ours)
+        .collect()
+}
+
+/// Why `tunnel` can't be started here, if another instance already holds it
+/// (same tunnel from a shared config) or its local port.
+pub async fn find_conflict(tunnel: &TunnelConfig) -> Option<String> {
+    let ours = config::config_path().to_string_lossy().to_string();
+    let port = claimed_port(tunnel);
+
+    for lease in others_async().await {
+        for claim in &lease.claims {
+            if lease.config_path == ours && claim.tunnel_id == tunnel.id {
+                return Some(format!("Tunnel is already running in {}", lease.owner()));
+            }
+            if Some(claim.port) == port {
+                return Some(format!(
+                    "Port {} is used by '{}' in {}",
+                    claim.port,
+                    claim.tunnel_name,
+                    lease.owner()
+                ));
+            }
+        }
+    }
+    None
+}
+
+/// Tunnels of `tunnels` whose id or local port another instance holds.
+pub async fn foreign_claims(tunnels: &[TunnelConfig]) -> Vec<ForeignClaim> {
+    let ours = config::config_path().to_string_lossy().to_string();
+    let mut result = Vec::new();
+
+    for lease in others_async().await {
+        for claim in &lease.claims {
+            let tunnel_id = tunnels
+                .iter()
+                .find(|t| {
+                    (lease.config_path == ours && t.id == claim.tunnel_id)
+                        || claimed_port(t) == Some(claim.port)
+                })
+                .map(|t| t.id.clone());
+            result.push(ForeignClaim {
+                tunnel_id,
+                port: claim.port,
+                owner: lease.owner(),
+            });
+        }
+    }
+    result
+}
@@ -0,0 +1,201 @@
+use crate::commands;
+#[cfg(windows)]
+use crate::config::TunnelConfig;
+use crate::config::{self, AppConfig};
+use log::warn;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use tauri::Manager;
+
+#[cfg(windows)]
+const MAX_ENTRIES: usize = 8;
+
+/// Most tunnels whose starts are counted; the least used go first.
+const MAX_TRACKED: usize = 200;
+
+fn usage_path() -> PathBuf {
+    config::config_dir().join("usage.json")
+}
+
+fn load_usage() -> HashMap<String, u32> {
+    fs::read_to_string(usage_path())
+        .ok()
+        .and_then(|c| serde_json::from_str(&c).ok())
+        .unwrap_or_default()
+}
+
+/// Counts a manual start so the jump list can rank tunnels by use.
+pub fn record_start(config: &AppConfig, tunnel_id: &str) {
+    let mut usage = load_usage();
+    count_start(&mut usage, config, tunnel_id);
+    if let Ok(json) = serde_json::to_string(&usage) {
+        let _ = fs::write(usage_path(), json);
+    }
+}
+
+/// Counts a start of `tunnel_id`, forgetting deleted tunnels and, past
+/// `MAX_TRACKED`, the least started others.
+fn count_start(usage: &mut HashMap<String, u32>, config: &AppConfig, tunnel_id: &str) {
+    let count = usage.entry(tunnel_id.to_string()).or_insert(0);
+    *count = count.saturating_add(1);
+    usage.retain(|id, _| config.tunnels.iter().any(|t| &t.id == id));
+    if usage.len() > MAX_TRACKED {
+        let mut counts: Vec<(String, u32)> = usage
+            .iter()
+            .filter(|(id, _)| *id != tunnel_id)
+            .map(|(id, count)| (id.clone(), *count))
+            .collect();
+        counts.sort_by_key(|(_, count)| *count);
+        for (id, _) in counts.into_iter().take(usage.len() - MAX_TRACKED) {
+            usage.remove(&id);
+        }
+    }
+}
+
+/// Enabled tunnels, most started first (ties keep config order).
+#[cfg(windows)]
+fn most_used(config: &AppConfig) -> Vec<&TunnelConfig> {
+    let usage = load_usage();
+    let mut tunnels: Vec<&TunnelConfig> = config.tunnels.iter().filter(|t| t.enabled).collect();
+    tunnels.sort_by_key(|t| std::cmp::Reverse(usage.get(&t.id).copied().unwrap_or(0)));
+    tunnels.truncate(MAX_ENTRIES);
+    tunnels
+}
+
+/// Handles command-line actions, either from our own launch or forwarded by
+/// the single-instance plugin: `--start <id>`, `--stop <id>`, `--start-all`,
+/// `--stop-all`. Without an action the main window is brought to front.
+pub fn handle_args(app: &tauri::AppHandle, args: &[String]) {
+    let mut handled = false;
+    let mut iter = args.iter().skip(1);
+
+    while let Some(arg) = iter.next() {
+        let app = app.clone();
+        match arg.as_str() {
+            "--start" => {
+                let Some(id) = iter.next().cloned() else {
+                    break;
+                };
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = commands::start_tunnel_cmd(id, app.state(), app.clone()).await {
+                        warn!("Jump list start failed: {}", e);
+                    }
+                });
+            }
+            "--stop" => {
+                let Some(id) = iter.next().cloned() else {
+                    break;
+                };
+                tauri::async_runtime::spawn(async move {
+                    let _ = commands::stop_tunnel_cmd(id, app.state(), app.clone()).await;
+                });
+            }
+            "--start-all" => {
+                tauri::async_runtime::spawn(async move {
+                    let _ = commands::start_all_tunnels(app.state(), app.clone()).await;
+                });
+            }
+            "--stop-all" => {
+                tauri::async_runtime::spawn(async move {
+                    let _ = commands::stop_all_tunnels(app.state(), app.clone()).await;
+                });
+            }
+            _ => continue,
+        }
+        handled = true;
+    }
+
+    if !handled {
+        if let Some(window) = app.get_webview_window("main") {
+            let _ = window.unminimize();
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+    }
+}
+
+/// Rebuilds the taskbar jump list from the current config.
+#[cfg(windows)]
+pub fn refresh(config: &AppConfig) {
+    let entries: Vec<(String, String)> = most_used(config)
+        .into_iter()
+        .map(|t| (t.id.clone(), t.name.clone()))
+        .collect();
+
+    // COM needs its own apartment; keep it off the async runtime's threads.
+    std::thread::spawn(move || {
+        if let Err(e) = unsafe { build_jump_list(&entries) } {
+            warn!("Failed to update jump list: {}", e);
+        }
+    });
+}
+
+#[cfg(not(windows))]
+pub fn refresh(_config: &AppConfig) {}
+
+#[cfg(windows)]
+unsafe fn build_jump_list(entries: &[(String, String)]) -> windows::core::Result<()> {
+    use windows::core::Interface;
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_INPROC_SERVER,
+        COINIT_APARTMENTTHREADED,
+    };
+    use windows::Win32::UI::Shell::Common::{IObjectArray, IObjectCollection};
+    use windows::Win32::UI::Shell::{
+        DestinationList, EnumerableObjectCollection, ICustomDestinationList,
+    };
+
+    let exe = match std::env::current_exe() {
+        Ok(p) => p,
+        Err(_) => return Ok(()),
+    };
+
+    let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+    let result = (|| {
+        let list: ICustomDestinationList =
+            CoCreateInstance(&DestinationList, None, CLSCTX_INPROC_SERVER)?;
+        let mut max_slots = 0u32;
+        let _removed: IObjectArray = list.BeginList(&mut max_slots)?;
+
+        let tasks: IObjectCollection =
+            CoCreateInstance(&EnumerableObjectCollection, None, CLSCTX_INPROC_SERVER)?;
+        for (id, name) in entries {
+            let args = format!("--start {}", id);
+            tasks.AddObject(&shell_link(&exe, &args, &format!("Start {}", name))?)?;
+        }
+        tasks.AddObject(&shell_link(&exe, "--start-all", "Start all")?)?;
+        tasks.AddObject(&shell_link(&exe, "--stop-all", "Stop all")?)?;
+
+        list.AddUserTasks(&tasks.cast::<IObjectArray>()?)?;
+        list.CommitList()
+    })();
+    CoUninitialize();
+    result
+}
+
+#[cfg(windows)]
+unsafe fn shell_link(
+    exe: &std::path::Path,
+    args: &str,
+    title: &str,
+) -> windows::core::Result<windows::Win32::UI::Shell::IShellLinkW> {
+    use windows::core::{Interface, HSTRING, PROPVARIANT};
+    use windows::Win32::Storage::EnhancedStorage::PKEY_Title;
+    use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+    use windows::Win32::UI::Shell::PropertiesSystem::IPropertyStore;
+    use windows::Win32::UI::Shell::{IShellLinkW, ShellLink};
+
+    let link: IShellLinkW = CoCreateInstance(&ShellLink, None, CLSCTX_INPROC_SERVER)?;
+    link.SetPath(&HSTRING::from(exe))?;
+    link.SetArguments(&HSTRING::from(args))?;
+    link.SetIconLocation(&HSTRING::from(exe), 0)?;
+
+    let store: IPropertyStore = link.cast()?;
+    store.SetValue(&PKEY_Title, &PROPVARIANT::from(title))?;
+    store.Commit()?;
+    Ok(link)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,37 @@
+use super::*;
+use crate::config::TunnelConfig;
+
+fn with_tunnels(count: usize) -> AppConfig {
+    let tunnels = (0..count)
+        .map(|i| {
+            TunnelConfig::new(
+                format!("tunnel {}", i),
+                "bastion.invalid".to_string(),
+                "tester".to_string(),
+            )
+        })
+        .collect();
+    AppConfig {
+        tunnels,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn usage_forgets_deleted_and_least_started_tunnels() {
+    let config = with_tunnels(MAX_TRACKED + 1);
+    let ids: Vec<String> = config.tunnels.iter().map(|t| t.id.clone()).collect();
+    let mut usage: HashMap<String, u32> = ids[1..].iter().map(|id| (id.clone(), 5)).collect();
+    usage.insert(ids[1].clone(), 1);
+    usage.insert("deleted".to_string(), 100);
+
+    count_start(&mut usage, &config, &ids[0]);
+    assert_eq!(usage.len(), MAX_TRACKED);
+    assert_eq!(usage.get(&ids[0]), Some(&1));
+    assert!(!usage.contains_key(&ids[1]));
+    assert!(!usage.contains_key("deleted"));
+
+    count_start(&mut usage, &config, &ids[2]);
+    assert_eq!(usage.get(&ids[2]), Some(&6));
+    assert_eq!(usage.len(), MAX_TRACKED);
+}
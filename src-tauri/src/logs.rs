@@ -0,0 +1,218 @@
+use crate::config;
+use crate::tunnel::LogEntry;
+use chrono::Utc;
+use log::warn;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{Emitter, Manager};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+/// Lines per `tunnel-log` event.
+const MAX_BATCH: usize = 200;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+/// The log file is rotated to `opentunnel.log.1` past this size.
+const MAX_LOG_FILE_BYTES: u64 = 50 * 1024 * 1024;
+/// Bytes of log file read between `log-export-progress` events.
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportProgress {
+    pub written: u64,
+    pub total: u64,
+}
+
+#[derive(Default)]
+struct Pending {
+    entries: VecDeque<LogEntry>,
+    dropped: usize,
+}
+
+/// Buffers log lines between the stderr readers and the frontend so a
+/// chatty tunnel costs one IPC event per batch instead of per line.
+#[derive(Clone)]
+pub struct LogBus {
+    pending: Arc<Mutex<Pending>>,
+    /// Lines kept while waiting for the next flush; older ones are dropped.
+    capacity: Arc<AtomicUsize>,
+}
+
+impl LogBus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pending: Arc::default(),
+            capacity: Arc::new(AtomicUsize::new(capacity.max(1))),
+        }
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity.max(1), Ordering::Relaxed);
+    }
+
+    pub fn push(&self, entry: LogEntry) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let mut pending = self.pending.lock().unwrap();
+        while pending.entries.len() >= capacity {
+            pending.entries.pop_front();
+            pending.dropped += 1;
+        }
+        pending.entries.push_back(entry);
+    }
+
+    /// Empties the buffer, noting any lines dropped since the last call.
+    fn take(&self) -> Vec<LogEntry> {
+        let (mut entries, dropped) = {
+            let mut pending = self.pending.lock().unwrap();
+            let dropped = std::mem::take(&mut pending.dropped);
+            (std::mem::take(&mut pending.entries), dropped)
+        };
+
+        if dropped > 0 {
+            entries.push_front(LogEntry {
+                timestamp: Utc::now().to_rfc3339(),
+                tunnel_id: "system".to_string(),
+                tunnel_name: "OpenTunnel".to_string(),
+                level: "error".to_string(),
+                message: format!("{} log lines dropped", dropped),
+                stream: None,
+            });
+        }
+
+        entries.into()
+    }
+
+    /// Sends everything buffered so far to the frontend and the log file.
+    pub fn flush(&self, app_handle: &tauri::AppHandle) {
+        let entries = self.take();
+        emit(app_handle, &entries);
+        if let Err(e) = append_to_file(&entries) {
+            warn!("Failed to write log file: {}", e);
+        }
+    }
+}
+
+fn emit(app_handle: &tauri::AppHandle, entries: &[LogEntry]) {
+    for batch in entries.chunks(MAX_BATCH) {
+        let _ = app_handle.emit("tunnel-log", batch);
+    }
+}
+
+fn log_file() -> PathBuf {
+    config::config_dir().join("logs").join("opentunnel.log")
+}
+
+fn rotated_log_file() -> PathBuf {
+    log_file().with_extension("log.1")
+}
+
+/// Appends entries as JSON lines, rotating the file when it gets too big.
+fn append_to_file(entries: &[LogEntry]) -> std::io::Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let path = log_file();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::metadata(&path).map(|m| m.len()).unwrap_or(0) > MAX_LOG_FILE_BYTES {
+        fs::rename(&path, rotated_log_file())?;
+    }
+
+    let mut out = BufWriter::new(OpenOptions::new().create(true).append(true).open(&path)?);
+    for entry in entries {
+        serde_json::to_writer(&mut out, entry)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
+/// Queues a log line for the frontend.
+pub fn push<R: tauri::Runtime>(app_handle: &tauri::AppHandle<R>, entry: LogEntry) {
+    app_handle.state::<LogBus>().push(entry);
+}
+
+/// Flushes the buffer every `FLUSH_INTERVAL`; file writes go to the
+/// blocking pool.
+pub async fn run_flusher(bus: LogBus, app_handle: tauri::AppHandle) {
+    loop {
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        let entries = bus.take();
+        if entries.is_empty() {
+            continue;
+        }
+        emit(&app_handle, &entries);
+        let written = tokio::task::spawn_blocking(move || append_to_file(&entries)).await;
+        if let Ok(Err(e)) = written {
+            warn!("Failed to write log file: {}", e);
+        }
+    }
+}
+
+/// Writes the log file (oldest rotation first) to `dest` as plain text, one
+/// line at a time, emitting `log-export-progress` along the way.
+pub async fn export(app_handle: &tauri::AppHandle, dest: &Path) -> Result<(), String> {
+    let sources: Vec<PathBuf> = [rotated_log_file(), log_file()]
+        .into_iter()
+        .filter(|p| p.exists())
+        .collect();
+    let mut total = 0;
+    for source in &sources {
+        total += tokio::fs::metadata(source)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+    }
+
+    let file = tokio::fs::File::create(dest)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    let mut out = tokio::io::BufWriter::new(file);
+    let mut written = 0;
+    let mut next_report = PROGRESS_STEP;
+
+    for source in &sources {
+        let file = tokio::fs::File::open(source)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        let mut lines = tokio::io::BufReader::new(file).lines();
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", source.display(), e))?
+        {
+            written += line.len() as u64 + 1;
+            let Ok(entry) = serde_json::from_str::<LogEntry>(&line) else {
+                continue;
+            };
+            let text = format!(
+                "[{}] [{}] {}: {}\n",
+                entry.timestamp, entry.tunnel_name, entry.level, entry.message
+            );
+            out.write_all(text.as_bytes())
+                .await
+                .map_err(|e| format!("Failed to write export: {}", e))?;
+
+            if written >= next_report {
+                next_report = written + PROGRESS_STEP;
+                let _ = app_handle.emit("log-export-progress", ExportProgress { written, total });
+            }
+        }
+    }
+
+    out.flush()
+        .await
+        .map_err(|e| format!("Failed to write export: {}", e))?;
+    let _ = app_handle.emit(
+        "log-export-progress",
+        ExportProgress {
+            written: total,
+            total,
+        },
+    );
+    Ok(())
+}
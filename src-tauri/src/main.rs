@@ -1,70 +1,193 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod actions;
+mod backend;
 mod commands;
 mod config;
+mod configwatch;
+mod diagnostics;
+mod dpapi;
+mod expose;
+mod health;
+mod hosts;
+mod instances;
+mod jumplist;
+mod logs;
 mod monitor;
+mod mux;
+#[cfg(feature = "native-ssh")]
+mod native;
+mod netwatch;
+mod notify;
+mod orphans;
+mod pageant;
+mod password;
+mod probe;
+mod process;
+mod profiles;
+mod proxy;
 mod putty_import;
+mod relay;
+mod schedule;
+mod secrets;
+mod shutdown;
+mod state;
+mod templates;
+mod tray;
 mod tunnel;
+mod wol;
 
 use config::load_config;
-use log::info;
+use log::warn;
 
 fn main() {
+    // SSH clients run this executable to reach servers through a proxy
+    proxy::run_if_helper();
+    // Tunnels on a shared connection hold their forward through a helper
+    mux::run_if_helper();
+    // Tunnels on the built-in client re-run this executable as their helper
+    #[cfg(feature = "native-ssh")]
+    native::run_if_helper();
+    // OpenSSH runs this executable to answer password prompts
+    password::run_if_askpass();
+
     env_logger::init();
 
     let manager = tunnel::new_manager();
     let mon = monitor::new_monitor();
+    let log_bus = logs::LogBus::new(load_config().settings.log_buffer_lines);
 
     tauri::Builder::default()
+        .plugin(tauri_plugin_single_instance::init(|app, argv, _cwd| {
+            jumplist::handle_args(app, &argv);
+        }))
         .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_shell::init())
         .manage(manager.clone())
         .manage(mon.clone())
+        .manage(log_bus.clone())
         .invoke_handler(tauri::generate_handler![
             commands::get_config,
+            commands::list_tunnels,
+            commands::reorder_tunnels,
+            commands::list_templates,
+            commands::save_template,
+            commands::delete_template,
+            commands::create_from_template,
+            commands::list_profiles,
+            commands::switch_profile,
             commands::save_settings,
             commands::add_tunnel,
             commands::update_tunnel,
             commands::delete_tunnel,
+            commands::save_group,
+            commands::rename_group,
             commands::start_tunnel_cmd,
             commands::stop_tunnel_cmd,
+            commands::accept_host_key,
+            commands::expose_port,
             commands::start_all_tunnels,
             commands::stop_all_tunnels,
             commands::get_tunnel_states,
+            commands::export_logs,
+            commands::diagnose_agents,
+            commands::get_foreign_claims,
+            commands::get_app_health,
+            commands::get_health_history,
+            commands::pause_monitor,
+            commands::resume_monitor,
+            commands::is_monitor_paused,
+            commands::get_monitor_summary,
+            commands::reset_reconnect_attempts,
+            commands::reconnect_now,
             commands::import_putty_sessions,
             commands::set_autostart,
         ])
         .setup(move |app| {
+            #[cfg(target_os = "macos")]
+            if load_config().settings.hide_dock_icon {
+                app.set_activation_policy(tauri::ActivationPolicy::Accessory);
+            }
+
+            tray::init(app.handle())?;
+            health::init(app.handle());
+
+            instances::publish(std::iter::empty());
+            for other in instances::sharing_config() {
+                warn!(
+                    "{} uses the same config; its tunnels won't be started here",
+                    other.owner()
+                );
+            }
+
             let handle = app.handle().clone();
             let mgr = manager.clone();
             let monitor_state = mon.clone();
 
-            // Start monitor thread
+            // Deliver buffered log lines in batches
+            tauri::async_runtime::spawn(logs::run_flusher(log_bus.clone(), app.handle().clone()));
+
+            // Start monitor thread, woken early by network changes
+            netwatch::start();
             tauri::async_runtime::spawn(async move {
                 monitor::start_monitor(mgr.clone(), monitor_state, handle.clone()).await;
             });
 
-            // Auto-connect tunnels
+            // Pick up edits made to the config outside the app
+            tauri::async_runtime::spawn(configwatch::run(manager.clone(), app.handle().clone()));
+
+            // Passwords from before the credential store move there
+            tauri::async_runtime::spawn(async {
+                if let Err(e) = config::stash_secrets().await {
+                    warn!("{}", e);
+                }
+            });
+
+            // Jump list entries and any action we were launched with
+            jumplist::refresh(&load_config());
+            let args: Vec<String> = std::env::args().collect();
+            jumplist::handle_args(app.handle(), &args);
+
+            // Auto-connect tunnels, each on its own so a slow host doesn't
+            // hold up the others
             let mgr2 = manager.clone();
+            let mon2 = mon.clone();
             let handle2 = app.handle().clone();
             tauri::async_runtime::spawn(async move {
-                let cfg = load_config();
-                for t in &cfg.tunnels {
-                    if t.auto_connect && t.enabled {
-                        info!("Auto-connecting tunnel '{}'", t.name);
-                        let _ = tunnel::start_tunnel(
-                            &mgr2,
-                            t,
-                            &cfg.settings.plink_path,
-                            handle2.clone(),
-                        )
-                        .await;
-                    }
-                }
+                // Clients a crashed instance left behind hold ports these
+                // tunnels need
+                let _ = tauri::async_runtime::spawn_blocking(orphans::cleanup).await;
+
+                let cfg = config::load_config_async().await;
+                monitor::auto_connect(&mgr2, &mon2, cfg, &handle2).await;
             });
 
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running OpenTunnel");
+        .on_window_event(|window, event| {
+            // On macOS the menu bar item keeps the app alive; closing the
+            // window just hides it.
+            #[cfg(target_os = "macos")]
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                let _ = window.hide();
+                api.prevent_close();
+            }
+            #[cfg(not(target_os = "macos"))]
+            let _ = (window, event);
+        })
+        .build(tauri::generate_context!())
+        .expect("error while running OpenTunnel")
+        .run(|app, event| match event {
+            // Stop tunnels in order first, then exit for real
+            tauri::RunEvent::ExitRequested { api, .. } if shutdown::begin() => {
+                api.prevent_exit();
+                let handle = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    shutdown::run(&handle).await;
+                    handle.exit(0);
+                });
+            }
+            tauri::RunEvent::Exit => instances::release(),
+            _ => {}
+        });
 }
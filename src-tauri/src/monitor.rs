@@ -1,26 +1,368 @@
-use crate::config::{load_config, AppConfig};
-use crate::tunnel::{self, TunnelManager, TunnelStatus};
+use crate::config::{self, load_config_async, AppConfig, Settings, TunnelConfig};
+use crate::instances;
+use crate::logs;
+use crate::netwatch;
+use crate::notify;
+use crate::tunnel::{self, LogEntry, TunnelManager, TunnelState, TunnelStatus};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
 use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use tauri::Emitter;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, Semaphore};
 use tokio::time::sleep;
 
+/// A poll taking this much longer than it should means the machine was
+/// asleep.
+const RESUME_GAP: Duration = Duration::from_secs(30);
+/// Half-second polls a restarted tunnel waits for the one it goes through.
+const PARENT_WAIT_POLLS: u32 = 120;
+/// How often `monitor-summary` is emitted.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct MonitorState {
     pub running: bool,
     pub reconnect_attempts: std::collections::HashMap<String, u32>,
+    /// Whether each scheduled tunnel's window was open at the last check.
+    pub schedule_active: std::collections::HashMap<String, bool>,
+    /// Dead tunnels to reconnect once the machine is back online.
+    pub waiting_for_network: std::collections::HashSet<String>,
+    /// Auto-reconnect suspended by the user, e.g. during server maintenance.
+    pub paused: bool,
+    /// Dead tunnels to reconnect once the monitor is resumed.
+    pub waiting_for_resume: std::collections::HashSet<String>,
+    /// Dead tunnels to reconnect once the tunnel they go through is back.
+    pub waiting_for_parent: HashSet<String>,
+    /// Tunnels that died within their startup grace period, looked at
+    /// again once it's over.
+    pub in_grace: HashSet<String>,
+    /// Tunnels given up on that the user asked to retry.
+    pub rearmed: std::collections::HashSet<String>,
+    /// Recent drops of each tunnel, to tell when it's flapping.
+    pub drops: std::collections::HashMap<String, VecDeque<Instant>>,
+    /// Cuts short the backoff of each tunnel waiting to reconnect.
+    pub retry_now: std::collections::HashMap<String, Arc<Notify>>,
+    /// Tunnels auto-reconnect gave up on, not auto-connected at startup
+    /// either until re-armed or started by hand.
+    pub given_up: HashSet<String>,
+    /// End of each flapping tunnel's hold-down.
+    pub held_until: HashMap<String, DateTime<Utc>>,
+    pub started_at: DateTime<Utc>,
+    /// Time each tunnel has been running, out of the time it's been started
+    /// (running or trying to be), since `started_at`.
+    pub uptime: HashMap<String, (Duration, Duration)>,
+    /// Successful reconnects of each tunnel since `started_at`.
+    pub reconnects: HashMap<String, u32>,
+}
+
+/// A tunnel's figures in `monitor-summary`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelSummary {
+    #[serde(rename = "tunnelId")]
+    pub tunnel_id: String,
+    /// Share of the time it was started that it was running.
+    #[serde(rename = "uptimePercent")]
+    pub uptime_percent: f64,
+    pub reconnects: u32,
+    /// Failed reconnect attempts in a row, so far.
+    #[serde(rename = "consecutiveFailures")]
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorSummary {
+    pub since: DateTime<Utc>,
+    pub tunnels: Vec<TunnelSummary>,
+}
+
+/// What the monitor remembers across restarts, so a restart doesn't reset
+/// the retry budget of a server that's been refusing connections for hours.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Persisted {
+    #[serde(rename = "reconnectAttempts", default)]
+    reconnect_attempts: HashMap<String, u32>,
+    #[serde(rename = "givenUp", default)]
+    given_up: HashSet<String>,
+    #[serde(rename = "heldUntil", default)]
+    held_until: HashMap<String, DateTime<Utc>>,
+}
+
+/// Last state written, to skip writes when nothing changed.
+static SAVED: std::sync::Mutex<Option<Persisted>> = std::sync::Mutex::new(None);
+
+fn persisted_path() -> PathBuf {
+    config::profile_dir().join("monitor.json")
+}
+
+fn read_persisted(path: &Path) -> Persisted {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Writes `persisted` aside and renames it over `path`, so a crash
+/// mid-write leaves the old state whole.
+fn write_persisted(path: &Path, persisted: &Persisted) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(persisted).map_err(|e| e.to_string())?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&tmp);
+            e.to_string()
+        })
+}
+
+fn load_persisted() -> Persisted {
+    let persisted = read_persisted(&persisted_path());
+    *SAVED.lock().unwrap() = Some(persisted.clone());
+    persisted
+}
+
+/// Writes the monitor's state to disk if it changed since the last write.
+pub async fn save(monitor: &Monitor) {
+    let persisted = {
+        let mon = monitor.lock().await;
+        Persisted {
+            reconnect_attempts: mon.reconnect_attempts.clone(),
+            given_up: mon.given_up.clone(),
+            held_until: mon.held_until.clone(),
+        }
+    };
+    let _ = tokio::task::spawn_blocking(move || {
+        let mut saved = SAVED.lock().unwrap();
+        if saved.as_ref() == Some(&persisted) {
+            return;
+        }
+        match write_persisted(&persisted_path(), &persisted) {
+            Ok(()) => *saved = Some(persisted),
+            Err(e) => warn!("Failed to save monitor state: {}", e),
+        }
+    })
+    .await;
+}
+
+/// Why `tunnel_id` shouldn't be auto-connected at startup right away: it
+/// was given up on (`Some(None)`), or is held down until the returned time.
+pub async fn startup_hold(monitor: &Monitor, tunnel_id: &str) -> Option<Option<DateTime<Utc>>> {
+    let mon = monitor.lock().await;
+    if mon.given_up.contains(tunnel_id) {
+        return Some(None);
+    }
+    mon.held_until
+        .get(tunnel_id)
+        .filter(|until| **until > Utc::now())
+        .map(|until| Some(*until))
+}
+
+/// Starts `cfg`'s auto-connect tunnels that are on their network, each
+/// on its own so a slow host doesn't hold up the others, at most
+/// `start_concurrency` at a time. Scheduled tunnels are left to the
+/// monitor, and tunnels it gave up on or holds down stay that way.
+pub async fn auto_connect(
+    manager: &TunnelManager,
+    monitor: &Monitor,
+    cfg: AppConfig,
+    app_handle: &tauri::AppHandle,
+) {
+    let network = netwatch::for_auto_connect(&cfg).await;
+    let limit = Arc::new(Semaphore::new(cfg.settings.start_concurrency.max(1)));
+    for t in cfg.tunnels {
+        if !(t.auto_connect && t.enabled) || t.schedule.is_some() {
+            continue;
+        }
+        if !netwatch::allows_auto_connect(&t, &cfg.settings, &network) {
+            info!("Not auto-connecting '{}': not on its network", t.name);
+            continue;
+        }
+        // Restarting the app doesn't reset the monitor's verdict
+        let held = match startup_hold(monitor, &t.id).await {
+            Some(None) => {
+                info!(
+                    "Not auto-connecting '{}': reconnecting was given up on",
+                    t.name
+                );
+                tunnel::emit_state(
+                    app_handle,
+                    &TunnelState::untracked(
+                        &t.id,
+                        TunnelStatus::Error,
+                        Some(
+                            "Auto-reconnect gave up on this tunnel; start it once the problem is fixed"
+                                .to_string(),
+                        ),
+                    ),
+                );
+                continue;
+            }
+            Some(Some(until)) => Some(until),
+            None => None,
+        };
+        info!("Auto-connecting tunnel '{}'", t.name);
+        let mut state = TunnelState::untracked(&t.id, TunnelStatus::Starting, None);
+        if let Some(until) = held {
+            state.status = TunnelStatus::Reconnecting;
+            state.next_retry_at = Some(until.to_rfc3339());
+        }
+        tunnel::emit_state(app_handle, &state);
+
+        let manager = manager.clone();
+        let app_handle = app_handle.clone();
+        let plink_path = cfg.settings.plink_path.clone();
+        let limit = limit.clone();
+        tauri::async_runtime::spawn(async move {
+            // Still held down for flapping before the restart
+            if let Some(wait) = held.and_then(|until| (until - Utc::now()).to_std().ok()) {
+                info!("'{}' is held down for another {}s", t.name, wait.as_secs());
+                sleep(wait).await;
+            }
+            let _permit = limit.acquire_owned().await;
+            if let Err(e) =
+                tunnel::start_tunnel(&manager, &t, &plink_path, app_handle.clone()).await
+            {
+                warn!("Auto-connect of '{}' failed: {}", t.name, e);
+                tunnel::emit_state(
+                    &app_handle,
+                    &TunnelState::untracked(&t.id, TunnelStatus::Error, Some(e)),
+                );
+            }
+        });
+    }
 }
 
 pub type Monitor = Arc<Mutex<MonitorState>>;
 
 pub fn new_monitor() -> Monitor {
+    let persisted = load_persisted();
     Arc::new(Mutex::new(MonitorState {
         running: false,
-        reconnect_attempts: std::collections::HashMap::new(),
+        reconnect_attempts: persisted.reconnect_attempts,
+        schedule_active: std::collections::HashMap::new(),
+        waiting_for_network: std::collections::HashSet::new(),
+        paused: false,
+        waiting_for_resume: std::collections::HashSet::new(),
+        waiting_for_parent: HashSet::new(),
+        in_grace: HashSet::new(),
+        rearmed: std::collections::HashSet::new(),
+        drops: std::collections::HashMap::new(),
+        retry_now: std::collections::HashMap::new(),
+        given_up: persisted.given_up,
+        held_until: persisted.held_until,
+        started_at: Utc::now(),
+        uptime: HashMap::new(),
+        reconnects: HashMap::new(),
     }))
 }
 
+/// Per-tunnel uptime, reconnects and failures since the app started.
+pub async fn summary(monitor: &Monitor) -> MonitorSummary {
+    let mon = monitor.lock().await;
+    let mut ids: Vec<&String> = mon.uptime.keys().chain(mon.reconnects.keys()).collect();
+    ids.sort();
+    ids.dedup();
+    let tunnels = ids
+        .into_iter()
+        .map(|id| {
+            let (up, started) = mon.uptime.get(id).copied().unwrap_or_default();
+            TunnelSummary {
+                tunnel_id: id.clone(),
+                uptime_percent: if started.is_zero() {
+                    0.0
+                } else {
+                    100.0 * up.as_secs_f64() / started.as_secs_f64()
+                },
+                reconnects: mon.reconnects.get(id).copied().unwrap_or(0),
+                consecutive_failures: mon.reconnect_attempts.get(id).copied().unwrap_or(0),
+            }
+        })
+        .collect();
+    MonitorSummary {
+        since: mon.started_at,
+        tunnels,
+    }
+}
+
+/// Credits `elapsed` to every started tunnel, and to the running ones'
+/// uptime.
+async fn tally_uptime(manager: &TunnelManager, monitor: &Monitor, elapsed: Duration) {
+    let states = manager.states();
+    let mut mon = monitor.lock().await;
+    for state in states {
+        let (up, started) = mon.uptime.entry(state.id).or_default();
+        *started += elapsed;
+        if state.status == TunnelStatus::Running {
+            *up += elapsed;
+        }
+    }
+}
+
+/// Ends the backoff `tunnel_id` is waiting out, so it reconnects right
+/// away.
+pub async fn reconnect_now(monitor: &Monitor, tunnel_id: &str) -> Result<(), String> {
+    match monitor.lock().await.retry_now.get(tunnel_id) {
+        Some(retry) => {
+            retry.notify_one();
+            Ok(())
+        }
+        None => Err("Tunnel isn't waiting to reconnect".to_string()),
+    }
+}
+
+/// Clears the reconnect counter of `tunnel_id`, or of every tunnel, and
+/// lets the monitor retry tunnels it gave up on (after too many attempts or
+/// a failure retrying couldn't fix) at its next check.
+pub async fn reset_attempts(manager: &TunnelManager, monitor: &Monitor, tunnel_id: Option<&str>) {
+    let given_up: Vec<String> = manager
+        .states()
+        .into_iter()
+        .filter(|s| s.status == TunnelStatus::Error)
+        .filter(|s| tunnel_id.is_none_or(|id| s.id == id))
+        .map(|s| s.id)
+        .collect();
+    let mut mon = monitor.lock().await;
+    match tunnel_id {
+        Some(id) => {
+            mon.reconnect_attempts.remove(id);
+            mon.given_up.remove(id);
+            mon.held_until.remove(id);
+        }
+        None => {
+            mon.reconnect_attempts.clear();
+            mon.given_up.clear();
+            mon.held_until.clear();
+        }
+    }
+    for id in given_up {
+        // Whatever was wrong is presumably fixed now
+        manager.update_state(&id, |state| {
+            state.reconnect_count = 0;
+            state.last_error_kind = None;
+        });
+        mon.rearmed.insert(id);
+    }
+}
+
+/// Suspends or resumes auto-reconnect. Tunnels that die while paused wait
+/// until it's resumed.
+pub async fn set_paused(monitor: &Monitor, paused: bool, app_handle: &tauri::AppHandle) {
+    {
+        let mut mon = monitor.lock().await;
+        if mon.paused == paused {
+            return;
+        }
+        mon.paused = paused;
+    }
+    info!(
+        "Auto-reconnect {}",
+        if paused { "paused" } else { "resumed" }
+    );
+    let _ = app_handle.emit("monitor-paused", serde_json::json!({ "paused": paused }));
+}
+
 pub async fn start_monitor(
     manager: TunnelManager,
     monitor: Monitor,
@@ -35,8 +377,11 @@ pub async fn start_monitor(
     }
 
     info!("Tunnel monitor started");
+    let mut last_summary = Instant::now();
+    let mut network = netwatch::Watch::new();
 
     loop {
+        save(&monitor).await;
         {
             let mon = monitor.lock().await;
             if !mon.running {
@@ -44,26 +389,245 @@ pub async fn start_monitor(
             }
         }
 
-        sleep(Duration::from_secs(3)).await;
+        let interval = load_config_async().await.settings.monitor_interval();
+        let before = SystemTime::now();
+        tokio::select! {
+            _ = sleep(interval) => {}
+            _ = network.changed() => {
+                // Failures on the old network say nothing about the new one
+                info!("Network changed, re-checking tunnels");
+                sleep(netwatch::SETTLE).await;
+                monitor.lock().await.reconnect_attempts.clear();
+            }
+        }
+
+        // The wall clock keeps going while the machine sleeps; the timer
+        // doesn't. So a resume is noticed by the poll overrunning by more
+        // than RESUME_GAP, at the first poll after it: a sleep shorter than
+        // that, or a wall clock set forward, can be missed or taken for one
+        let slept = SystemTime::now().duration_since(before).unwrap_or_default();
+        if slept > interval + RESUME_GAP {
+            info!("Resumed after {}s, restarting tunnels", slept.as_secs());
+            restart_all(&manager, &monitor, &app_handle).await;
+        }
+
+        // Time asleep counts for nothing
+        tally_uptime(&manager, &monitor, slept.min(interval)).await;
+        if last_summary.elapsed() >= SUMMARY_INTERVAL {
+            last_summary = Instant::now();
+            let _ = app_handle.emit("monitor-summary", summary(&monitor).await);
+        }
+
+        // Heartbeat for other instances
+        instances::publish_async(manager.configs()).await;
+
+        apply_schedules(&manager, &monitor, &app_handle).await;
+        stop_idle(&manager, &app_handle).await;
 
-        // Check health
-        let dead = tunnel::check_tunnel_health(&manager).await;
+        // Tunnels given up on that have been started by hand since
+        {
+            let states = manager.states();
+            monitor.lock().await.given_up.retain(|id| {
+                !states
+                    .iter()
+                    .any(|s| &s.id == id && s.status == TunnelStatus::Running)
+            });
+        }
+        refresh_long_sessions(&manager, &app_handle).await;
+
+        // Check health; tunnels parked while offline, paused or waiting for
+        // their parent count as dead again once that's over
+        let mut dead = tunnel::check_tunnel_health(&manager);
+        let online = netwatch::online();
+        let paused = {
+            let mut mon = monitor.lock().await;
+            if online && !mon.waiting_for_network.is_empty() {
+                info!("Network is back, resuming reconnects");
+                dead.extend(mon.waiting_for_network.drain());
+            }
+            if !mon.paused {
+                dead.extend(mon.waiting_for_resume.drain());
+            }
+            dead.extend(mon.rearmed.drain());
+            dead.extend(mon.in_grace.drain());
+            if !mon.waiting_for_parent.is_empty() {
+                let config = load_config_async().await;
+                mon.waiting_for_parent.retain(|id| {
+                    let tunnel = config.tunnels.iter().find(|t| &t.id == id);
+                    let ready = tunnel.is_none_or(|t| parent_down(&manager, &config, t).is_none());
+                    if ready {
+                        dead.push(id.clone());
+                    }
+                    !ready
+                });
+            }
+            mon.paused
+        };
 
         if dead.is_empty() {
             continue;
         }
 
         // Try to reconnect dead tunnels
-        let config: AppConfig = load_config();
+        let config: AppConfig = load_config_async().await;
 
+        // Forget what's known about deleted tunnels
+        {
+            let mut mon = monitor.lock().await;
+            let exists = |id: &String| config.tunnels.iter().any(|t| &t.id == id);
+            mon.reconnect_attempts.retain(|id, _| exists(id));
+            mon.drops.retain(|id, _| exists(id));
+            mon.given_up.retain(exists);
+            mon.held_until.retain(|id, _| exists(id));
+            mon.uptime.retain(|id, _| exists(id));
+            mon.reconnects.retain(|id, _| exists(id));
+        }
+
+        let now = Local::now().naive_local();
+        let network = netwatch::for_auto_connect(&config).await;
         for tunnel_id in &dead {
             let tunnel_config = config.tunnels.iter().find(|t| &t.id == tunnel_id);
 
             let tunnel_config = match tunnel_config {
-                Some(t) if t.auto_connect && t.enabled => t,
-                _ => continue,
+                Some(t) if should_reconnect(t, &config.settings, &network, now) => t,
+                _ => {
+                    // Nothing will take the port or connection back
+                    manager.release(tunnel_id);
+                    continue;
+                }
             };
 
+            // Started again since it died (at boot, or by hand): that
+            // start's outcome is what counts
+            let status = manager
+                .states()
+                .into_iter()
+                .find(|s| &s.id == tunnel_id)
+                .map(|s| s.status);
+            if matches!(
+                status,
+                Some(TunnelStatus::Starting) | Some(TunnelStatus::Running)
+            ) {
+                continue;
+            }
+            // A fresh start may still be authenticating; the monitor's own
+            // attempts have the backoff instead
+            let grace = Duration::from_secs(config.settings.startup_grace_sec);
+            {
+                let mut mon = monitor.lock().await;
+                if !mon.reconnect_attempts.contains_key(tunnel_id)
+                    && manager.since_start(tunnel_id).is_some_and(|t| t < grace)
+                {
+                    mon.in_grace.insert(tunnel_id.clone());
+                    continue;
+                }
+            }
+
+            if paused {
+                park_until_resumed(&manager, &monitor, tunnel_id, &app_handle).await;
+                continue;
+            }
+
+            // Retrying with the same credentials or host key won't help
+            let kind = manager
+                .states()
+                .into_iter()
+                .find(|s| &s.id == tunnel_id)
+                .and_then(|s| s.last_error_kind);
+            if let Some(kind) = kind.filter(|k| !k.retryable()) {
+                warn!(
+                    "Not reconnecting tunnel '{}' after a {:?} failure",
+                    tunnel_config.name, kind
+                );
+                if config.settings.notify_on_disconnect {
+                    notify::send(
+                        &app_handle,
+                        &tunnel_config.id,
+                        "error",
+                        format!(
+                            "Tunnel '{}' needs attention before it can reconnect",
+                            tunnel_config.name
+                        ),
+                    );
+                }
+                monitor.lock().await.given_up.insert(tunnel_id.clone());
+                manager.release(tunnel_id);
+                continue;
+            }
+
+            // Without any network every attempt would fail
+            if !online {
+                park_until_online(&manager, &monitor, tunnel_id, &app_handle).await;
+                continue;
+            }
+
+            // The tunnel it goes through comes back first
+            if let Some(parent) = parent_down(&manager, &config, tunnel_config) {
+                let reason = format!("Waiting for '{}'", parent.name);
+                park(
+                    &manager,
+                    &monitor,
+                    tunnel_id,
+                    &app_handle,
+                    |m| &mut m.waiting_for_parent,
+                    reason,
+                )
+                .await;
+                continue;
+            }
+
+            // A tunnel that keeps dropping right after reconnecting is
+            // held down for a while rather than reconnected every few
+            // seconds
+            if is_flapping(&monitor, tunnel_id, &config.settings).await {
+                let hold = config.settings.flap_hold_min;
+                warn!(
+                    "Tunnel '{}' is flapping, holding it down for {} min",
+                    tunnel_config.name, hold
+                );
+                if config.settings.notify_on_disconnect {
+                    notify::send(
+                        &app_handle,
+                        &tunnel_config.id,
+                        "error",
+                        format!(
+                            "Tunnel '{}' keeps dropping; retrying in {} min",
+                            tunnel_config.name, hold
+                        ),
+                    );
+                }
+                let wait = Duration::from_secs(hold * 60);
+                let due = Utc::now() + chrono::Duration::from_std(wait).unwrap_or_default();
+                manager.update_state(tunnel_id, |state| {
+                    state.next_retry_at = Some(due.to_rfc3339())
+                });
+                monitor
+                    .lock()
+                    .await
+                    .held_until
+                    .insert(tunnel_id.clone(), due);
+                manager.transition(
+                    &app_handle,
+                    tunnel_id,
+                    TunnelStatus::Reconnecting,
+                    Some(format!(
+                        "Held for {} min after dropping {} times in {} min",
+                        hold,
+                        config.settings.flap_threshold + 1,
+                        config.settings.flap_window_min
+                    )),
+                );
+                tauri::async_runtime::spawn(reconnect_after(
+                    wait,
+                    manager.clone(),
+                    monitor.clone(),
+                    tunnel_config.clone(),
+                    config.settings.clone(),
+                    app_handle.clone(),
+                ));
+                continue;
+            }
+
             let attempts = {
                 let mut mon = monitor.lock().await;
                 let count = mon.reconnect_attempts.entry(tunnel_id.clone()).or_insert(0);
@@ -81,19 +645,23 @@ pub async fn start_monitor(
                 );
 
                 if config.settings.notify_on_disconnect {
-                    let _ = app_handle.emit(
-                        "notification",
-                        serde_json::json!({
-                            "title": "OpenTunnel",
-                            "body": format!("Tunnel '{}' failed after {} attempts", tunnel_config.name, attempts),
-                            "type": "error"
-                        }),
+                    notify::send(
+                        &app_handle,
+                        &tunnel_config.id,
+                        "error",
+                        format!(
+                            "Tunnel '{}' failed after {} attempts",
+                            tunnel_config.name, attempts
+                        ),
                     );
                 }
+                monitor.lock().await.given_up.insert(tunnel_id.clone());
+                manager.release(tunnel_id);
                 continue;
             }
 
-            // Exponential backoff: base_delay * 2^(attempts-1), max 300s
+            // Exponential backoff: base_delay * 2^(attempts-1), max 300s, plus
+            // jitter
             let delay = std::cmp::min(
                 config.settings.reconnect_delay_sec * 2u64.pow(attempts.saturating_sub(1)),
                 300,
@@ -105,68 +673,556 @@ pub async fn start_monitor(
             );
 
             // Update status to reconnecting
-            {
-                let mut mgr = manager.lock().await;
-                if let Some(process) = mgr.get_mut(tunnel_id) {
-                    process.state.status = TunnelStatus::Reconnecting;
-                    process.state.reconnect_count = attempts;
+            let wait = with_jitter(Duration::from_secs(delay));
+            let due = Utc::now() + chrono::Duration::from_std(wait).unwrap_or_default();
+            manager.update_state(tunnel_id, |state| {
+                state.reconnect_count = attempts;
+                state.next_retry_at = Some(due.to_rfc3339());
+            });
+            manager.transition(
+                &app_handle,
+                tunnel_id,
+                TunnelStatus::Reconnecting,
+                Some(format!("Retrying in {}s (attempt {})", delay, attempts)),
+            );
+
+            // Each tunnel waits out its own backoff, so a long one doesn't
+            // hold up the others
+            tauri::async_runtime::spawn(reconnect_after(
+                wait,
+                manager.clone(),
+                monitor.clone(),
+                tunnel_config.clone(),
+                config.settings.clone(),
+                app_handle.clone(),
+            ));
+        }
+    }
+}
+
+/// Records a drop of `tunnel_id` and tells whether it has now dropped more
+/// than `flap_threshold` times within `flap_window_min`. A flapping tunnel
+/// starts over with a clean slate once held down.
+async fn is_flapping(monitor: &Monitor, tunnel_id: &str, settings: &Settings) -> bool {
+    if settings.flap_threshold == 0 {
+        return false;
+    }
+    let window = Duration::from_secs(settings.flap_window_min * 60);
+    let now = Instant::now();
+    let mut mon = monitor.lock().await;
+    let drops = mon.drops.entry(tunnel_id.to_string()).or_default();
+    drops.push_back(now);
+    while drops
+        .front()
+        .is_some_and(|&at| now.duration_since(at) > window)
+    {
+        drops.pop_front();
+    }
+    if drops.len() <= settings.flap_threshold {
+        return false;
+    }
+    mon.drops.remove(tunnel_id);
+    true
+}
+
+/// `delay` plus up to a quarter more at random, so tunnels that dropped
+/// together don't all hit their server at the same instant.
+fn with_jitter(delay: Duration) -> Duration {
+    let spread = delay.as_millis() as u64 / 4;
+    delay + Duration::from_millis(fastrand::u64(0..=spread))
+}
+
+/// Restarts a dead tunnel once its backoff has passed, unless the app is
+/// shutting down or the tunnel was stopped or started by hand meanwhile.
+async fn reconnect_after(
+    delay: Duration,
+    manager: TunnelManager,
+    monitor: Monitor,
+    tunnel_config: TunnelConfig,
+    settings: Settings,
+    app_handle: tauri::AppHandle,
+) {
+    let retry_now = monitor
+        .lock()
+        .await
+        .retry_now
+        .entry(tunnel_config.id.clone())
+        .or_default()
+        .clone();
+    // A new network is worth trying right away, as is a server the user
+    // knows is back
+    let mut network = netwatch::Watch::new();
+    tokio::select! {
+        _ = sleep(delay) => {}
+        _ = network.changed() => sleep(netwatch::SETTLE).await,
+        _ = retry_now.notified() => {
+            info!("Reconnecting tunnel '{}' now", tunnel_config.name);
+        }
+    }
+    {
+        let mut mon = monitor.lock().await;
+        mon.retry_now.remove(&tunnel_config.id);
+        mon.held_until.remove(&tunnel_config.id);
+    }
+
+    // Don't bring tunnels back while the app is shutting down
+    if !monitor.lock().await.running {
+        return;
+    }
+    let waiting = manager
+        .states()
+        .iter()
+        .any(|s| s.id == tunnel_config.id && s.status == TunnelStatus::Reconnecting);
+    if !waiting {
+        return;
+    }
+    // Went offline or got paused during the backoff: this attempt doesn't
+    // count
+    let paused = monitor.lock().await.paused;
+    let online = netwatch::online();
+    if paused || !online {
+        if let Some(count) = monitor
+            .lock()
+            .await
+            .reconnect_attempts
+            .get_mut(&tunnel_config.id)
+        {
+            *count = count.saturating_sub(1);
+        }
+        if paused {
+            park_until_resumed(&manager, &monitor, &tunnel_config.id, &app_handle).await;
+        } else {
+            park_until_online(&manager, &monitor, &tunnel_config.id, &app_handle).await;
+        }
+        return;
+    }
+
+    // A port picked at random is kept, so whatever was pointed at it
+    // reaches the tunnel again
+    let mut tunnel_config = tunnel_config;
+    if instances::claimed_port(&tunnel_config) == Some(0) {
+        if let Some(running) = manager
+            .configs()
+            .into_iter()
+            .find(|t| t.id == tunnel_config.id)
+        {
+            tunnel_config.local_port = running.local_port;
+        }
+    }
+
+    // Remove dead process before restarting; the new one starts from a
+    // fresh state, but it's still the same attempt
+    let attempt = manager
+        .remove(&tunnel_config.id)
+        .map_or(0, |handle| handle.state().reconnect_count);
+
+    // Restart; a client that spawns may still fail to connect, so only
+    // one that comes up counts as reconnected
+    let started = tunnel::start_tunnel(
+        &manager,
+        &tunnel_config,
+        &settings.plink_path,
+        app_handle.clone(),
+    )
+    .await;
+    let result = match started {
+        Ok(_) => {
+            manager.update_state(&tunnel_config.id, |state| {
+                if state.status == TunnelStatus::Starting {
+                    state.reconnect_count = attempt;
                 }
+            });
+            if let Some(state) = manager
+                .states()
+                .into_iter()
+                .find(|s| s.id == tunnel_config.id)
+            {
+                tunnel::emit_state(&app_handle, &state);
             }
+            came_up(&manager, &tunnel_config.id).await
+        }
+        Err(e) => Some(Err(e)),
+    };
+    match result {
+        // Stopped by hand meanwhile
+        None => {}
+        Some(Ok(())) => {
+            info!("Tunnel '{}' reconnected successfully", tunnel_config.name);
+            // Reset attempts on success
+            let attempts = {
+                let mut mon = monitor.lock().await;
+                *mon.reconnects.entry(tunnel_config.id.clone()).or_default() += 1;
+                mon.reconnect_attempts
+                    .remove(&tunnel_config.id)
+                    .unwrap_or(0)
+            };
 
-            let _ = app_handle.emit(
-                "tunnel-status",
-                &tunnel::get_all_states(&manager).await,
-            );
+            // Blips whose failures weren't announced recover quietly too
+            let announced = attempts.saturating_sub(1) >= settings.notify_after_failures;
+            if settings.notify_on_reconnect && (settings.notify_after_failures <= 1 || announced) {
+                notify::send(
+                    &app_handle,
+                    &tunnel_config.id,
+                    "success",
+                    format!("Tunnel '{}' reconnected", tunnel_config.name),
+                );
+            }
+        }
+        Some(Err(e)) => {
+            warn!("Failed to reconnect '{}': {}", tunnel_config.name, e);
+
+            let failures = monitor
+                .lock()
+                .await
+                .reconnect_attempts
+                .get(&tunnel_config.id)
+                .copied()
+                .unwrap_or(0);
+            if settings.notify_on_disconnect && failures >= settings.notify_after_failures {
+                notify::send(
+                    &app_handle,
+                    &tunnel_config.id,
+                    "error",
+                    format!("Tunnel '{}' reconnect failed: {}", tunnel_config.name, e),
+                );
+            }
+        }
+    }
+}
+
+/// Waits for a just started tunnel to leave `Starting`: `Ok` once it's
+/// running, or why it isn't; `None` if it was stopped meanwhile.
+async fn came_up(manager: &TunnelManager, tunnel_id: &str) -> Option<Result<(), String>> {
+    loop {
+        let state = manager.states().into_iter().find(|s| s.id == tunnel_id);
+        match state {
+            Some(s) if s.status == TunnelStatus::Starting => {
+                sleep(Duration::from_millis(500)).await;
+            }
+            Some(s) if s.status == TunnelStatus::Running => return Some(Ok(())),
+            Some(s) => {
+                return Some(Err(s
+                    .last_error
+                    .unwrap_or_else(|| "Client exited before connecting".to_string())))
+            }
+            None => return None,
+        }
+    }
+}
 
-            sleep(Duration::from_secs(delay)).await;
+/// Leaves a dead tunnel in one of the monitor's `waiting` sets, showing
+/// `reason` the first time.
+async fn park(
+    manager: &TunnelManager,
+    monitor: &Monitor,
+    tunnel_id: &str,
+    app_handle: &tauri::AppHandle,
+    waiting: fn(&mut MonitorState) -> &mut HashSet<String>,
+    reason: String,
+) {
+    let newly = waiting(&mut *monitor.lock().await).insert(tunnel_id.to_string());
+    if newly {
+        manager.update_state(tunnel_id, |state| state.next_retry_at = None);
+        manager.transition(
+            app_handle,
+            tunnel_id,
+            TunnelStatus::Reconnecting,
+            Some(reason),
+        );
+    }
+}
+
+/// Leaves a dead tunnel waiting for the network instead of spending its
+/// reconnect attempts while offline.
+async fn park_until_online(
+    manager: &TunnelManager,
+    monitor: &Monitor,
+    tunnel_id: &str,
+    app_handle: &tauri::AppHandle,
+) {
+    let reason = "Waiting for network".to_string();
+    park(
+        manager,
+        monitor,
+        tunnel_id,
+        app_handle,
+        |m| &mut m.waiting_for_network,
+        reason,
+    )
+    .await;
+}
 
-            // Remove dead process before restarting
+/// Leaves a dead tunnel waiting while auto-reconnect is paused.
+async fn park_until_resumed(
+    manager: &TunnelManager,
+    monitor: &Monitor,
+    tunnel_id: &str,
+    app_handle: &tauri::AppHandle,
+) {
+    let reason = "Auto-reconnect paused".to_string();
+    park(
+        manager,
+        monitor,
+        tunnel_id,
+        app_handle,
+        |m| &mut m.waiting_for_resume,
+        reason,
+    )
+    .await;
+}
+
+/// The tunnel `tunnel` goes through, if it isn't running: reconnecting
+/// before it would only fail.
+fn parent_down(
+    manager: &TunnelManager,
+    config: &AppConfig,
+    tunnel: &TunnelConfig,
+) -> Option<TunnelConfig> {
+    let parent = config
+        .tunnels
+        .iter()
+        .find(|t| Some(&t.id) == tunnel.depends_on.as_ref())?;
+    let running = manager
+        .states()
+        .iter()
+        .any(|s| s.id == parent.id && s.status == TunnelStatus::Running);
+    (!running).then(|| parent.clone())
+}
+
+/// Waits (a while) for the tunnel `tunnel` goes through to be running, if
+/// it's being started.
+async fn wait_for_parent(manager: &TunnelManager, tunnel: &TunnelConfig) {
+    let Some(ref parent_id) = tunnel.depends_on else {
+        return;
+    };
+    for _ in 0..PARENT_WAIT_POLLS {
+        let parent = manager.states().into_iter().find(|s| &s.id == parent_id);
+        match parent {
+            Some(s)
+                if matches!(
+                    s.status,
+                    TunnelStatus::Starting | TunnelStatus::Reconnecting
+                ) =>
             {
-                let mut mgr = manager.lock().await;
-                mgr.remove(tunnel_id);
+                sleep(Duration::from_millis(500)).await;
             }
+            _ => return,
+        }
+    }
+}
+
+/// Stops every up (or coming up) tunnel and starts it again, each on its
+/// own. After a suspend their connections are gone, though the clients may
+/// not notice for minutes. Tunnels given up on, held down or disabled are
+/// left to the monitor; those that died are reconnected as usual.
+async fn restart_all(manager: &TunnelManager, monitor: &Monitor, app_handle: &tauri::AppHandle) {
+    let (given_up, held) = {
+        let mut mon = monitor.lock().await;
+        mon.reconnect_attempts.clear();
+        (mon.given_up.clone(), mon.held_until.clone())
+    };
+    let config = load_config_async().await;
+    let live = manager.states().into_iter().filter(|s| {
+        matches!(s.status, TunnelStatus::Running | TunnelStatus::Starting)
+            && !given_up.contains(&s.id)
+            && !held.contains_key(&s.id)
+    });
+    for state in live {
+        let Some(tunnel_config) = config
+            .tunnels
+            .iter()
+            .find(|t| t.id == state.id && t.enabled)
+            .cloned()
+        else {
+            continue;
+        };
+        spawn_restart(manager, tunnel_config, &config.settings, app_handle);
+    }
+}
+
+/// Restarts tunnels that have been up for their `max_session_hours`, for
+/// servers or bastions that limit session length.
+async fn refresh_long_sessions(manager: &TunnelManager, app_handle: &tauri::AppHandle) {
+    let due: Vec<(String, u64)> = manager
+        .configs()
+        .into_iter()
+        .filter_map(|t| Some((t.id, t.max_session_hours.filter(|&h| h > 0)?)))
+        .filter(|(id, hours)| {
+            manager.states().iter().any(|s| {
+                &s.id == id
+                    && s.status == TunnelStatus::Running
+                    && s.uptime_sec.unwrap_or(0) >= hours * 3600
+            })
+        })
+        .collect();
+    if due.is_empty() {
+        return;
+    }
+    let config = load_config_async().await;
+    for (id, hours) in due {
+        let Some(tunnel_config) = config.tunnels.iter().find(|t| t.id == id).cloned() else {
+            continue;
+        };
+        info!(
+            "Refreshing tunnel '{}' after {}h of uptime",
+            tunnel_config.name, hours
+        );
+        spawn_restart(manager, tunnel_config, &config.settings, app_handle);
+    }
+}
+
+/// Stops a tunnel and starts it again in the background.
+fn spawn_restart(
+    manager: &TunnelManager,
+    tunnel_config: TunnelConfig,
+    settings: &Settings,
+    app_handle: &tauri::AppHandle,
+) {
+    let manager = manager.clone();
+    let plink_path = settings.plink_path.clone();
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let _ = tunnel::stop_tunnel(&manager, &tunnel_config.id, &app_handle).await;
+        wait_for_parent(&manager, &tunnel_config).await;
+        if let Err(e) =
+            tunnel::start_tunnel(&manager, &tunnel_config, &plink_path, app_handle.clone()).await
+        {
+            warn!("Restarting '{}' failed: {}", tunnel_config.name, e);
+        }
+    });
+}
+
+/// Stops tunnels nothing has been connected to for their
+/// `idle_disconnect_min`.
+async fn stop_idle(manager: &TunnelManager, app_handle: &tauri::AppHandle) {
+    for tunnel_config in manager.configs() {
+        let Some(minutes) = tunnel_config.idle_disconnect_min.filter(|&m| m > 0) else {
+            continue;
+        };
+        let idle = manager.idle_for(&tunnel_config.id).unwrap_or_default();
+        if idle < Duration::from_secs(minutes * 60) {
+            continue;
+        }
+        info!(
+            "Stopping tunnel '{}' after {} min idle",
+            tunnel_config.name, minutes
+        );
+        if let Err(e) = tunnel::stop_tunnel(manager, &tunnel_config.id, app_handle).await {
+            warn!("Failed to stop idle tunnel '{}': {}", tunnel_config.name, e);
+            continue;
+        }
+        notify::send(
+            app_handle,
+            &tunnel_config.id,
+            "info",
+            format!(
+                "Tunnel '{}' disconnected after {} min idle",
+                tunnel_config.name, minutes
+            ),
+        );
+    }
+}
+
+/// Scheduled tunnels are kept up inside their window only; others when
+/// they auto-connect.
+fn should_reconnect(
+    tunnel: &TunnelConfig,
+    settings: &Settings,
+    network: &netwatch::Network,
+    now: NaiveDateTime,
+) -> bool {
+    tunnel.enabled
+        && match tunnel.schedule {
+            Some(ref schedule) => schedule.is_active(now),
+            None => tunnel.auto_connect,
+        }
+        && netwatch::allows_auto_connect(tunnel, settings, network)
+}
+
+/// Starts scheduled tunnels when their window opens and stops them when it
+/// closes. In between, the user may start or stop them by hand.
+async fn apply_schedules(
+    manager: &TunnelManager,
+    monitor: &Monitor,
+    app_handle: &tauri::AppHandle,
+) {
+    let config = load_config_async().await;
+    let now = Local::now().naive_local();
+
+    let scheduled: Vec<&TunnelConfig> = config
+        .tunnels
+        .iter()
+        .filter(|t| t.enabled && t.schedule.is_some())
+        .collect();
+    monitor
+        .lock()
+        .await
+        .schedule_active
+        .retain(|id, _| scheduled.iter().any(|t| &t.id == id));
+
+    for tunnel_config in scheduled {
+        let Some(ref schedule) = tunnel_config.schedule else {
+            continue;
+        };
+        let active = schedule.is_active(now);
+        let was_active = monitor
+            .lock()
+            .await
+            .schedule_active
+            .insert(tunnel_config.id.clone(), active);
+        if was_active == Some(active) {
+            continue;
+        }
 
-            // Restart
-            match tunnel::start_tunnel(
-                &manager,
+        let running = manager.ids().contains(&tunnel_config.id);
+        if active && !running {
+            schedule_log(
+                app_handle,
+                tunnel_config,
+                "Schedule window opened, starting",
+            );
+            if let Err(e) = tunnel::start_tunnel(
+                manager,
                 tunnel_config,
                 &config.settings.plink_path,
                 app_handle.clone(),
             )
             .await
             {
-                Ok(_) => {
-                    info!("Tunnel '{}' reconnected successfully", tunnel_config.name);
-                    // Reset attempts on success
-                    let mut mon = monitor.lock().await;
-                    mon.reconnect_attempts.remove(tunnel_id);
-
-                    if config.settings.notify_on_reconnect {
-                        let _ = app_handle.emit(
-                            "notification",
-                            serde_json::json!({
-                                "title": "OpenTunnel",
-                                "body": format!("Tunnel '{}' reconnected", tunnel_config.name),
-                                "type": "success"
-                            }),
-                        );
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to reconnect '{}': {}", tunnel_config.name, e);
-
-                    if config.settings.notify_on_disconnect {
-                        let _ = app_handle.emit(
-                            "notification",
-                            serde_json::json!({
-                                "title": "OpenTunnel",
-                                "body": format!("Tunnel '{}' reconnect failed: {}", tunnel_config.name, e),
-                                "type": "error"
-                            }),
-                        );
-                    }
-                }
+                warn!("Scheduled start of '{}' failed: {}", tunnel_config.name, e);
+            }
+        } else if !active && running {
+            schedule_log(
+                app_handle,
+                tunnel_config,
+                "Schedule window closed, stopping",
+            );
+            monitor
+                .lock()
+                .await
+                .reconnect_attempts
+                .remove(&tunnel_config.id);
+            if let Err(e) = tunnel::stop_tunnel(manager, &tunnel_config.id, app_handle).await {
+                warn!("Scheduled stop of '{}' failed: {}", tunnel_config.name, e);
             }
         }
     }
 }
+
+fn schedule_log(app_handle: &tauri::AppHandle, tunnel: &TunnelConfig, message: &str) {
+    info!("{}: {}", tunnel.name, message);
+    logs::push(
+        app_handle,
+        LogEntry {
+            timestamp: Utc::now().to_rfc3339(),
+            tunnel_id: tunnel.id.clone(),
+            tunnel_name: tunnel.name.clone(),
+            level: "info".to_string(),
+            message: message.to_string(),
+            stream: None,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,32 @@
+use super::*;
+use uuid::Uuid;
+
+#[test]
+fn persisted_state_round_trips() {
+    let dir = std::env::temp_dir().join(format!("opentunnel-monitor-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("monitor.json");
+    assert_eq!(read_persisted(&path), Persisted::default());
+
+    let persisted = Persisted {
+        reconnect_attempts: HashMap::from([("a".to_string(), 3)]),
+        given_up: HashSet::from(["b".to_string()]),
+        held_until: HashMap::from([("c".to_string(), Utc::now())]),
+    };
+    write_persisted(&path, &persisted).unwrap();
+    assert_eq!(read_persisted(&path), persisted);
+    // Only the state itself is left behind
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn jitter_adds_at_most_a_quarter() {
+    let delay = Duration::from_secs(8);
+    for _ in 0..1000 {
+        let jittered = with_jitter(delay);
+        assert!(jittered >= delay && jittered <= delay + delay / 4);
+    }
+    assert_eq!(with_jitter(Duration::ZERO), Duration::ZERO);
+}
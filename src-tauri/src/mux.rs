@@ -0,0 +1,292 @@
+//! Shared OpenSSH connections: tunnels to the same user@host:port (over the
+//! same hops, key and options) attach their forwards to one master
+//! connection (ControlMaster) instead of each logging in. The master is
+//! closed when the last tunnel using it releases it.
+//!
+//! A tunnel's client is this executable, `opentunnel --mux-forward <ssh>
+//! <flag> <spec> <ssh args>`, which adds the forward to the master with
+//! `-O forward` and stays up for as long as the master does, so no remote
+//! session is held per tunnel.
+
+use crate::config::{self, TunnelConfig};
+use crate::orphans;
+use crate::password;
+use crate::process::{self, ProcessTree};
+use crate::proxy;
+use log::{info, warn};
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::time::Instant;
+
+const READY_TIMEOUT: Duration = Duration::from_secs(20);
+const READY_POLL: Duration = Duration::from_millis(250);
+/// How often the forward helper checks that the master is still up.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub const HELPER_FLAG: &str = "--mux-forward";
+
+struct Master {
+    key: String,
+    child: Child,
+    tree: ProcessTree,
+    /// Tunnels using the connection.
+    users: HashSet<String>,
+}
+
+static MASTERS: Mutex<Vec<Master>> = Mutex::new(Vec::new());
+/// Serializes master startup so tunnels starting together share one.
+static STARTING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// `orphans` marker of master processes.
+const MARKER: &str = "mux";
+
+/// `orphans` registry entry of the master for `key`.
+fn registry_id(key: &str) -> String {
+    format!("mux {}", key)
+}
+
+/// Tunnels share a master when they'd connect the same way: same server,
+/// hops, key, options and transport.
+fn key(tunnel: &TunnelConfig) -> String {
+    let mut key = format!(
+        "{}@{}:{}",
+        tunnel.username,
+        config::bracket_host(&tunnel.host),
+        tunnel.port
+    );
+    for hop in &tunnel.jump_hosts {
+        key.push_str(&format!(" via {}", hop));
+    }
+    if let Some(ref path) = tunnel.key_path {
+        key.push_str(&format!(" key {}", path));
+    }
+    if !tunnel.extra_args.is_empty() {
+        key.push_str(&format!(" args {}", tunnel.extra_args.join(" ")));
+    }
+    if let Some(ref transport) = tunnel.transport {
+        key.push_str(&format!(
+            " over {}",
+            serde_json::to_string(transport).unwrap_or_default()
+        ));
+    }
+    key
+}
+
+/// `-S` argument for the master and its clients: a socket per master key,
+/// named by its hash to stay within socket path limits.
+pub fn control_path(tunnel: &TunnelConfig) -> String {
+    let mut hasher = DefaultHasher::new();
+    key(tunnel).hash(&mut hasher);
+    config::config_dir()
+        .join("mux")
+        .join(format!("{:016x}", hasher.finish()))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Program and arguments of the helper holding a `flag` `spec` forward
+/// through the master, which ssh reaches with `common` arguments (`-S`,
+/// login options and destination).
+pub fn forward_command(
+    ssh: &str,
+    flag: &str,
+    spec: &str,
+    common: &[String],
+) -> (String, Vec<String>) {
+    let exe = std::env::current_exe()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "opentunnel".to_string());
+    let mut args = vec![
+        HELPER_FLAG.to_string(),
+        ssh.to_string(),
+        flag.to_string(),
+        spec.to_string(),
+    ];
+    args.extend_from_slice(common);
+    (exe, args)
+}
+
+/// Becomes the forward helper (and never returns) when started with
+/// `HELPER_FLAG`.
+pub fn run_if_helper() {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, helper, ssh, flag, spec, common @ ..] = &args[..] else {
+        return;
+    };
+    if helper != HELPER_FLAG {
+        return;
+    }
+    let run = |operation: &[&str]| {
+        std::process::Command::new(ssh)
+            .args(operation)
+            .args(common)
+            .stdin(Stdio::null())
+            .output()
+    };
+    match run(&["-O", "forward", flag, spec]) {
+        Ok(out) if out.status.success() => {}
+        Ok(out) => {
+            eprint!("{}", String::from_utf8_lossy(&out.stderr));
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to start {}: {}", ssh, e);
+            std::process::exit(1);
+        }
+    }
+    loop {
+        std::thread::sleep(CHECK_INTERVAL);
+        if !run(&["-O", "check"]).is_ok_and(|out| out.status.success()) {
+            eprintln!("Shared connection lost");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Registers `tunnel` as a user of the master for its server, starting the
+/// master (with `login` options) if there's none or it died.
+pub async fn acquire(tunnel: &TunnelConfig, ssh: &str, login: &[String]) -> Result<(), String> {
+    let _starting = STARTING.lock().await;
+    let key = key(tunnel);
+    let mut dropped = false;
+    {
+        let mut masters = MASTERS.lock().unwrap();
+        if let Some(i) = masters
+            .iter_mut()
+            .position(|m| m.key == key && !matches!(m.child.try_wait(), Ok(None)))
+        {
+            warn!("Shared connection to {} dropped", key);
+            let mut master = masters.remove(i);
+            master.tree.kill(&mut master.child);
+            dropped = true;
+        }
+        if let Some(master) = masters.iter_mut().find(|m| m.key == key) {
+            master.users.insert(tunnel.id.clone());
+            return Ok(());
+        }
+    }
+    if dropped {
+        orphans::forget(&registry_id(&key)).await;
+    }
+
+    let _ = std::fs::create_dir_all(config::config_dir().join("mux"));
+    let destination = format!("{}@{}", tunnel.username, tunnel.host);
+    let mut command = Command::new(ssh);
+    command
+        .args([
+            "-M",
+            "-N",
+            "-S",
+            &control_path(tunnel),
+            "-o",
+            "ControlPersist=no",
+        ])
+        .args(login)
+        .arg(&destination)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .env(orphans::MARKER_ENV, MARKER)
+        .kill_on_drop(true);
+    password::apply_env(&mut command, tunnel);
+    proxy::apply_env(&mut command, tunnel);
+    process::new_group(&mut command);
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to start {}: {}. Is it in PATH?", ssh, e))?;
+    let tree = ProcessTree::attach(&child);
+    if let Err(e) = wait_ready(&mut child, ssh, tunnel, &destination, login).await {
+        tree.kill(&mut child);
+        return Err(e);
+    }
+
+    info!("Opened shared connection to {}", key);
+    if let Some(pid) = child.id() {
+        orphans::record(&registry_id(&key), MARKER, pid).await;
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let key = key.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                warn!("[{}] {}", key, line);
+            }
+        });
+    }
+    MASTERS.lock().unwrap().push(Master {
+        key,
+        child,
+        tree,
+        users: HashSet::from([tunnel.id.clone()]),
+    });
+    Ok(())
+}
+
+/// Waits until the master accepts clients, or fails with its last words.
+async fn wait_ready(
+    child: &mut Child,
+    ssh: &str,
+    tunnel: &TunnelConfig,
+    destination: &str,
+    login: &[String],
+) -> Result<(), String> {
+    let deadline = Instant::now() + READY_TIMEOUT;
+    loop {
+        if let Ok(Some(status)) = child.try_wait() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr).await;
+            }
+            return Err(format!(
+                "Shared connection to {} failed ({}): {}",
+                destination,
+                status,
+                stderr.lines().last().unwrap_or_default().trim()
+            ));
+        }
+        let check = Command::new(ssh)
+            .args(["-S", &control_path(tunnel), "-O", "check"])
+            .args(login)
+            .arg(destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+        if matches!(check, Ok(status) if status.success()) {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "Timed out opening shared connection to {}",
+                destination
+            ));
+        }
+        tokio::time::sleep(READY_POLL).await;
+    }
+}
+
+/// Drops `tunnel_id` from the masters it uses, closing any left unused.
+pub fn release(tunnel_id: &str) {
+    let mut masters = MASTERS.lock().unwrap();
+    masters.retain_mut(|master| {
+        master.users.remove(tunnel_id);
+        if !master.users.is_empty() {
+            return true;
+        }
+        info!("Closing shared connection to {}", master.key);
+        master.tree.kill(&mut master.child);
+        let _ = master.child.start_kill();
+        let id = registry_id(&master.key);
+        tauri::async_runtime::spawn(async move { orphans::forget(&id).await });
+        false
+    });
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,25 @@
+use super::*;
+
+#[test]
+fn tunnels_connecting_differently_get_masters_of_their_own() {
+    let tunnel = TunnelConfig::new(
+        "a".to_string(),
+        "example.com".to_string(),
+        "user".to_string(),
+    );
+    let mut other = tunnel.clone();
+    other.id = "b".to_string();
+    assert_eq!(control_path(&tunnel), control_path(&other));
+
+    let variants: [fn(&mut TunnelConfig); 3] = [
+        |t| t.jump_hosts = vec!["bastion".to_string()],
+        |t| t.key_path = Some("~/.ssh/other".to_string()),
+        |t| t.extra_args = vec!["-4".to_string()],
+    ];
+    for vary in variants {
+        let mut other = tunnel.clone();
+        vary(&mut other);
+        assert_ne!(key(&tunnel), key(&other));
+        assert_ne!(control_path(&tunnel), control_path(&other));
+    }
+}
@@ -0,0 +1,450 @@
+//! Built-in SSH client (russh), for machines without plink or OpenSSH.
+//!
+//! It runs in a child process, `opentunnel --ssh-helper` reading the tunnel
+//! as JSON from stdin, so it's spawned, logged, health-checked and killed
+//! exactly like the external clients. Errors go to stderr prefixed with
+//! `FATAL ERROR:` as plink does.
+
+use crate::config::{self, AuthMethod, Transport, TunnelConfig, TunnelType};
+use crate::password;
+use async_trait::async_trait;
+use russh::client::{self, Handle, Msg, Session};
+use russh::Channel;
+use russh_keys::agent::client::AgentClient;
+use russh_keys::key::PublicKey;
+use std::io::Read;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const HELPER_FLAG: &str = "--ssh-helper";
+/// SSH client path that selects this client.
+pub const CLIENT_NAME: &str = "builtin";
+
+/// Unanswered keepalives before the connection counts as dead.
+const KEEPALIVE_MAX: usize = 3;
+/// Pause after a failed accept, e.g. out of file descriptors, before the
+/// next one.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Refuses what this client doesn't do, rather than silently ignoring it.
+pub fn validate(tunnel: &TunnelConfig) -> Result<(), String> {
+    if tunnel.compression {
+        return Err("The built-in client doesn't support compression".to_string());
+    }
+    if !tunnel.extra_args.is_empty() {
+        return Err("The built-in client doesn't take extra arguments".to_string());
+    }
+    if let Some(ref pinned) = tunnel.host_key_fingerprint {
+        if config::is_md5_fingerprint(pinned.split_whitespace().last().unwrap_or(pinned)) {
+            return Err(
+                "The built-in client only checks SHA256: host key fingerprints".to_string(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Becomes the SSH helper (and never returns) when started with
+/// `HELPER_FLAG`.
+pub fn run_if_helper() {
+    if std::env::args().nth(1).as_deref() != Some(HELPER_FLAG) {
+        return;
+    }
+
+    let mut input = String::new();
+    let tunnel = std::io::stdin()
+        .read_to_string(&mut input)
+        .ok()
+        .and_then(|_| serde_json::from_str::<TunnelConfig>(&input).ok());
+    let code = match tunnel {
+        Some(tunnel) => {
+            let result = tokio::runtime::Runtime::new()
+                .map_err(|e| format!("Failed to start runtime: {}", e))
+                .and_then(|rt| rt.block_on(run(tunnel)));
+            match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("FATAL ERROR: {}", e);
+                    1
+                }
+            }
+        }
+        None => {
+            eprintln!("FATAL ERROR: Invalid {} input", HELPER_FLAG);
+            2
+        }
+    };
+    std::process::exit(code);
+}
+
+/// Connects, sets up the forward and runs until the connection drops.
+async fn run(tunnel: TunnelConfig) -> Result<(), String> {
+    if !tunnel.jump_hosts.is_empty() {
+        return Err("Jump hosts need plink or OpenSSH".to_string());
+    }
+    let config = Arc::new(client::Config {
+        keepalive_interval: match tunnel
+            .keepalive_sec
+            .unwrap_or(config::DEFAULT_KEEPALIVE_SEC)
+        {
+            0 => None,
+            interval => Some(Duration::from_secs(interval)),
+        },
+        keepalive_max: KEEPALIVE_MAX,
+        ..Default::default()
+    });
+    let handler = Client {
+        host: tunnel.host.clone(),
+        port: tunnel.port,
+        // -R remote_port:remote_host:local_port
+        target: (tunnel.remote_host.clone(), tunnel.local_port),
+        access_log: tunnel.access_log,
+        fingerprint: tunnel.host_key_fingerprint.clone(),
+    };
+
+    eprintln!("Connecting to {} port {}", tunnel.host, tunnel.port);
+    let session = match &tunnel.transport {
+        None => client::connect(config, (tunnel.host.as_str(), tunnel.port), handler).await,
+        Some(Transport::Proxy(proxy)) => {
+            let mut proxy = proxy.clone();
+            proxy.password = std::env::var(crate::proxy::PASSWORD_ENV).ok();
+            let (host, port) = (tunnel.host.clone(), tunnel.port);
+            let stream =
+                tokio::task::spawn_blocking(move || crate::proxy::connect(&proxy, &host, port))
+                    .await
+                    .map_err(|e| e.to_string())??;
+            stream
+                .set_nonblocking(true)
+                .map_err(|e| format!("Network error: {}", e))?;
+            let stream =
+                TcpStream::from_std(stream).map_err(|e| format!("Network error: {}", e))?;
+            client::connect_stream(config, stream, handler).await
+        }
+        Some(Transport::WebSocket(_)) => {
+            return Err("The WebSocket transport needs plink or OpenSSH".to_string())
+        }
+    };
+    let mut session = session.map_err(|e| format!("Network error: {}", e))?;
+    authenticate(&mut session, &tunnel).await?;
+    eprintln!("Authenticated as {}@{}", tunnel.username, tunnel.host);
+
+    let bind = tunnel
+        .bind_address
+        .as_deref()
+        .filter(|a| !a.is_empty())
+        .map(|a| config::unbracket_host(a).to_string());
+    let session = match tunnel.tunnel_type {
+        TunnelType::Remote => {
+            let address = bind.unwrap_or_else(|| "localhost".to_string());
+            session
+                .tcpip_forward(address.clone(), u32::from(tunnel.remote_port))
+                .await
+                .map_err(|e| {
+                    format!(
+                        "Remote port forwarding from {}:{} refused: {}",
+                        address, tunnel.remote_port, e
+                    )
+                })?;
+            Arc::new(session)
+        }
+        TunnelType::Local | TunnelType::Dynamic => {
+            let address = bind.unwrap_or_else(|| "127.0.0.1".to_string());
+            let listener = TcpListener::bind((address.as_str(), tunnel.local_port))
+                .await
+                .map_err(|e| format!("Failed to listen on port {}: {}", tunnel.local_port, e))?;
+            let session = Arc::new(session);
+            tokio::spawn(accept_loop(listener, session.clone(), tunnel.clone()));
+            session
+        }
+    };
+    eprintln!("Forwarding established");
+
+    while !session.is_closed() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err("Server closed the connection".to_string())
+}
+
+/// Logs in with the password from `password::FILE_ENV`, the agent's keys,
+/// or tries the configured key, or the usual keys in `~/.ssh`.
+async fn authenticate(session: &mut Handle<Client>, tunnel: &TunnelConfig) -> Result<(), String> {
+    if tunnel.auth_method == AuthMethod::Agent {
+        return authenticate_agent(session, tunnel).await;
+    }
+    if tunnel.auth_method == AuthMethod::Password {
+        let password = std::env::var_os(password::FILE_ENV)
+            .and_then(|file| std::fs::read_to_string(file).ok())
+            .ok_or("No password stored for password authentication")?;
+        let accepted = session
+            .authenticate_password(tunnel.username.clone(), password)
+            .await
+            .map_err(|e| format!("Authentication failed: {}", e))?;
+        return if accepted {
+            Ok(())
+        } else {
+            Err(format!(
+                "Access denied for {}@{}",
+                tunnel.username, tunnel.host
+            ))
+        };
+    }
+
+    let keys: Vec<PathBuf> = match (&tunnel.auth_method, &tunnel.key_path) {
+        (AuthMethod::Key, Some(path)) => vec![PathBuf::from(path)],
+        _ => {
+            let ssh_dir = dirs::home_dir().unwrap_or_default().join(".ssh");
+            ["id_ed25519", "id_ecdsa", "id_rsa"]
+                .iter()
+                .map(|name| ssh_dir.join(name))
+                .filter(|p| p.is_file())
+                .collect()
+        }
+    };
+    if keys.is_empty() {
+        return Err("No key configured and none found in ~/.ssh".to_string());
+    }
+
+    for path in keys {
+        let key = match russh_keys::load_secret_key(&path, None) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("Skipping key {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let accepted = session
+            .authenticate_publickey(tunnel.username.clone(), Arc::new(key))
+            .await
+            .map_err(|e| format!("Authentication failed: {}", e))?;
+        if accepted {
+            return Ok(());
+        }
+    }
+    Err(format!(
+        "Access denied for {}@{}",
+        tunnel.username, tunnel.host
+    ))
+}
+
+/// Tries each key the SSH agent holds: `SSH_AUTH_SOCK`, or the Windows
+/// ssh-agent service.
+async fn authenticate_agent(
+    session: &mut Handle<Client>,
+    tunnel: &TunnelConfig,
+) -> Result<(), String> {
+    #[cfg(unix)]
+    let agent = AgentClient::connect_env().await;
+    #[cfg(windows)]
+    let agent = AgentClient::connect_named_pipe(r"\\.\pipe\openssh-ssh-agent").await;
+    let mut agent = agent.map_err(|e| format!("No SSH agent reachable: {}", e))?;
+
+    let keys = agent
+        .request_identities()
+        .await
+        .map_err(|e| format!("Failed to list agent keys: {}", e))?;
+    if keys.is_empty() {
+        return Err("The SSH agent holds no keys".to_string());
+    }
+    for key in keys {
+        let (returned, accepted) = session
+            .authenticate_future(tunnel.username.clone(), key, agent)
+            .await;
+        agent = returned;
+        if accepted.map_err(|e| format!("Authentication failed: {}", e))? {
+            return Ok(());
+        }
+    }
+    Err(format!(
+        "Access denied for {}@{}",
+        tunnel.username, tunnel.host
+    ))
+}
+
+async fn accept_loop(listener: TcpListener, session: Arc<Handle<Client>>, tunnel: TunnelConfig) {
+    loop {
+        let (mut socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Retrying right away would spin while it lasts
+                eprintln!("Failed to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let session = session.clone();
+        let tunnel = tunnel.clone();
+        tokio::spawn(async move {
+            let target = if tunnel.tunnel_type == TunnelType::Dynamic {
+                match socks5_handshake(&mut socket).await {
+                    Ok(target) => target,
+                    Err(e) => {
+                        eprintln!("SOCKS request from {} rejected: {}", peer, e);
+                        return;
+                    }
+                }
+            } else {
+                (tunnel.remote_host.clone(), tunnel.remote_port)
+            };
+
+            let channel = session
+                .channel_open_direct_tcpip(
+                    target.0.clone(),
+                    u32::from(target.1),
+                    peer.ip().to_string(),
+                    u32::from(peer.port()),
+                )
+                .await;
+            let channel = match channel {
+                Ok(channel) => channel,
+                Err(e) => {
+                    eprintln!("Forward to {}:{} failed: {}", target.0, target.1, e);
+                    if tunnel.tunnel_type == TunnelType::Dynamic {
+                        let _ = socket.write_all(&SOCKS_FAILURE).await;
+                    }
+                    return;
+                }
+            };
+            if tunnel.tunnel_type == TunnelType::Dynamic
+                && socket.write_all(&SOCKS_SUCCESS).await.is_err()
+            {
+                return;
+            }
+            let mut stream = channel.into_stream();
+            let _ = tokio::io::copy_bidirectional(&mut socket, &mut stream).await;
+        });
+    }
+}
+
+const SOCKS_SUCCESS: [u8; 10] = [5, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+const SOCKS_FAILURE: [u8; 10] = [5, 1, 0, 1, 0, 0, 0, 0, 0, 0];
+
+/// Minimal SOCKS5 server side: no authentication, CONNECT only. Returns the
+/// requested host and port.
+async fn socks5_handshake(socket: &mut TcpStream) -> std::io::Result<(String, u16)> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut greeting = [0u8; 2];
+    socket.read_exact(&mut greeting).await?;
+    if greeting[0] != 5 {
+        return Err(invalid("not SOCKS5"));
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    socket.read_exact(&mut methods).await?;
+    socket.write_all(&[5, 0]).await?;
+
+    let mut request = [0u8; 4];
+    socket.read_exact(&mut request).await?;
+    if request[1] != 1 {
+        socket.write_all(&[5, 7, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(invalid("only CONNECT is supported"));
+    }
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            socket.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let mut len = [0u8; 1];
+            socket.read_exact(&mut len).await?;
+            let mut name = vec![0u8; len[0] as usize];
+            socket.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| invalid("bad host name"))?
+        }
+        4 => {
+            let mut ip = [0u8; 16];
+            socket.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        _ => return Err(invalid("bad address type")),
+    };
+    let mut port = [0u8; 2];
+    socket.read_exact(&mut port).await?;
+    Ok((host, u16::from_be_bytes(port)))
+}
+
+struct Client {
+    host: String,
+    port: u16,
+    /// Where connections arriving on a remote forward go.
+    target: (String, u16),
+    access_log: bool,
+    /// Pinned `SHA256:` host key fingerprint, checked instead of
+    /// known_hosts.
+    fingerprint: Option<String>,
+}
+
+#[async_trait]
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    /// Trusts known_hosts, learns unknown hosts and refuses changed keys.
+    /// A pinned fingerprint replaces all that.
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Self::Error> {
+        if let Some(ref pinned) = self.fingerprint {
+            let matches = config::pin_matches(pinned, &key.fingerprint());
+            if !matches {
+                eprintln!(
+                    "FATAL ERROR: Host key for {} is SHA256:{}, not the pinned {}",
+                    self.host,
+                    key.fingerprint(),
+                    pinned
+                );
+            }
+            return Ok(matches);
+        }
+        match russh_keys::check_known_hosts(&self.host, self.port, key) {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                eprintln!(
+                    "Adding {} key {} to known_hosts",
+                    self.host,
+                    key.fingerprint()
+                );
+                if let Err(e) = russh_keys::learn_known_hosts(&self.host, self.port, key) {
+                    eprintln!("Failed to update known_hosts: {}", e);
+                }
+                Ok(true)
+            }
+            Err(e) => {
+                eprintln!(
+                    "FATAL ERROR: Host key for {} does not match known_hosts: {}",
+                    self.host, e
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<Msg>,
+        _connected_address: &str,
+        _connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if self.access_log {
+            eprintln!("Connection from {}:{}", originator_address, originator_port);
+        }
+        let target = self.target.clone();
+        tokio::spawn(async move {
+            match TcpStream::connect((target.0.as_str(), target.1)).await {
+                Ok(mut socket) => {
+                    let mut stream = channel.into_stream();
+                    let _ = tokio::io::copy_bidirectional(&mut socket, &mut stream).await;
+                }
+                Err(e) => {
+                    eprintln!("Connect to {}:{} failed: {}", target.0, target.1, e);
+                    let _ = channel.close().await;
+                }
+            }
+        });
+        Ok(())
+    }
+}
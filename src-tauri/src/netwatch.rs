@@ -0,0 +1,351 @@
+//! Network change notifications, so tunnels are re-checked and reconnected
+//! right after switching networks instead of once their keepalives time
+//! out and their backoff has passed. Windows reports interface and default
+//! route changes; elsewhere a `Watch` never sees one. Also tells which
+//! network we're on, for tunnels only auto-connected on some.
+
+use crate::config::{AppConfig, NetworkCondition, Settings, TunnelConfig};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Time for a new network to come up (DHCP, DNS) before acting on it.
+pub const SETTLE: Duration = Duration::from_secs(2);
+
+/// Network changes so far; a watcher that saw fewer has one to act on.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static CHANGED: Notify = Notify::const_new();
+
+/// How long a looked-up network is trusted when no change is reported
+/// (there never is one outside Windows).
+const CACHE_FOR: Duration = Duration::from_secs(60);
+
+/// Last looked-up network, with the generation and time it was looked up.
+static CACHED: Mutex<Option<(u64, Instant, Network)>> = Mutex::new(None);
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn notify_changed() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    CHANGED.notify_waiters();
+}
+
+/// Network changes seen by one watcher. Changes that happen while it
+/// isn't waiting are kept for its next wait, not lost.
+pub struct Watch {
+    seen: u64,
+}
+
+impl Watch {
+    /// Watches for changes from now on.
+    pub fn new() -> Self {
+        Self {
+            seen: GENERATION.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Completes once the network has changed since the last call (or
+    /// since the watch was made). The new network may still be coming up;
+    /// give it `SETTLE` before relying on it.
+    pub async fn changed(&mut self) {
+        loop {
+            let notified = CHANGED.notified();
+            tokio::pin!(notified);
+            // Registered before looking, so a change in between isn't missed
+            notified.as_mut().enable();
+            let now = GENERATION.load(Ordering::SeqCst);
+            if now != self.seen {
+                self.seen = now;
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Whether the machine has a route to the internet at all. Connecting a
+/// UDP socket sends nothing; it only fails when there's no route.
+pub fn online() -> bool {
+    let routed = |bind: &str, target: &str| {
+        std::net::UdpSocket::bind(bind)
+            .and_then(|socket| socket.connect(target))
+            .is_ok()
+    };
+    routed("0.0.0.0:0", "192.0.2.1:9") || routed("[::]:0", "[2001:db8::1]:9")
+}
+
+/// What identifies the network we're on.
+#[derive(Debug, Default, Clone)]
+pub struct Network {
+    /// Our addresses on the default routes.
+    pub addresses: Vec<IpAddr>,
+    pub ssid: Option<String>,
+    /// DNS domains of the connections that are up.
+    pub domains: Vec<String>,
+}
+
+/// The network we're on. Runs the system's Wi-Fi tool, so call it off the
+/// async threads.
+pub fn current() -> Network {
+    // As in `online`, connecting a UDP socket picks the address the
+    // default route goes out from without sending anything
+    let address = |bind: &str, target: &str| {
+        let socket = std::net::UdpSocket::bind(bind).ok()?;
+        socket.connect(target).ok()?;
+        socket.local_addr().ok().map(|addr| addr.ip())
+    };
+    Network {
+        addresses: [
+            address("0.0.0.0:0", "192.0.2.1:9"),
+            address("[::]:0", "[2001:db8::1]:9"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        ssid: ssid(),
+        domains: domains(),
+    }
+}
+
+/// `current()`, if any of `config`'s auto-connect conditions needs it.
+/// Looked up again only after a network change or `CACHE_FOR`, so the
+/// monitor can ask on every tick.
+pub async fn for_auto_connect(config: &AppConfig) -> Network {
+    let needed = config.settings.auto_connect_on.is_some()
+        || config.tunnels.iter().any(|t| t.auto_connect_on.is_some());
+    if !needed {
+        return Network::default();
+    }
+    let generation = GENERATION.load(Ordering::SeqCst);
+    if let Some((seen, at, ref network)) = *CACHED.lock().unwrap() {
+        if seen == generation && at.elapsed() < CACHE_FOR {
+            return network.clone();
+        }
+    }
+    let network = tokio::task::spawn_blocking(current)
+        .await
+        .unwrap_or_default();
+    *CACHED.lock().unwrap() = Some((generation, Instant::now(), network.clone()));
+    network
+}
+
+/// `a.b.c.d/len` (or an IPv6 one) as its address and prefix length; a
+/// bare address is a subnet of one.
+pub fn parse_subnet(subnet: &str) -> Option<(IpAddr, u32)> {
+    let (address, len) = match subnet.trim().split_once('/') {
+        Some((address, len)) => (address.parse::<IpAddr>().ok()?, len.parse().ok()?),
+        None => {
+            let address = subnet.trim().parse::<IpAddr>().ok()?;
+            (address, if address.is_ipv4() { 32 } else { 128 })
+        }
+    };
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    (len <= max).then_some((address, len))
+}
+
+fn in_subnet(address: IpAddr, (network, len): (IpAddr, u32)) -> bool {
+    match (address, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+            u32::from(a) & mask == u32::from(n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => {
+            let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+            u128::from(a) & mask == u128::from(n) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Whether `condition` holds on `network`.
+pub fn matches(condition: &NetworkCondition, network: &Network) -> bool {
+    let on = condition
+        .ssid
+        .as_ref()
+        .is_none_or(|ssid| network.ssid.as_ref() == Some(ssid))
+        && condition.domain.as_ref().is_none_or(|domain| {
+            let domain = domain.trim_matches('.').to_lowercase();
+            network.domains.iter().any(|d| {
+                let d = d.to_lowercase();
+                d == domain || d.ends_with(&format!(".{}", domain))
+            })
+        })
+        && condition.subnet.as_ref().is_none_or(|subnet| {
+            parse_subnet(subnet)
+                .is_some_and(|subnet| network.addresses.iter().any(|a| in_subnet(*a, subnet)))
+        });
+    on != condition.except
+}
+
+/// Whether `tunnel` may be auto-connected on `network`, by its own
+/// condition and its profile's.
+pub fn allows_auto_connect(tunnel: &TunnelConfig, settings: &Settings, network: &Network) -> bool {
+    [&tunnel.auto_connect_on, &settings.auto_connect_on]
+        .into_iter()
+        .flatten()
+        .all(|condition| matches(condition, network))
+}
+
+/// Output of a command that succeeded.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(windows)]
+fn ssid() -> Option<String> {
+    // "    SSID                   : Office"; BSSID is on a line of its own
+    command_output("netsh", &["wlan", "show", "interfaces"])?
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == "SSID").then(|| value.trim().to_string())
+        })
+        .filter(|ssid| !ssid.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+fn ssid() -> Option<String> {
+    command_output("networksetup", &["-getairportnetwork", "en0"])?
+        .trim()
+        .strip_prefix("Current Wi-Fi Network: ")
+        .map(str::to_string)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn ssid() -> Option<String> {
+    command_output("iwgetid", &["-r"])
+        .map(|ssid| ssid.trim().to_string())
+        .filter(|ssid| !ssid.is_empty())
+}
+
+/// Connection-specific DNS suffixes of the adapters that are up.
+#[cfg(windows)]
+fn domains() -> Vec<String> {
+    use windows::Win32::Foundation::ERROR_BUFFER_OVERFLOW;
+    use windows::Win32::NetworkManagement::IpHelper::{
+        GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER,
+        GAA_FLAG_SKIP_MULTICAST, GAA_FLAG_SKIP_UNICAST, IP_ADAPTER_ADDRESSES_LH,
+    };
+    use windows::Win32::NetworkManagement::Ndis::IfOperStatusUp;
+    use windows::Win32::Networking::WinSock::AF_UNSPEC;
+
+    let flags = GAA_FLAG_SKIP_UNICAST
+        | GAA_FLAG_SKIP_ANYCAST
+        | GAA_FLAG_SKIP_MULTICAST
+        | GAA_FLAG_SKIP_DNS_SERVER;
+    let mut size = 16 * 1024u32;
+    // u64s keep the buffer aligned for the structs it's filled with
+    let mut buf: Vec<u64> = Vec::new();
+    for _ in 0..2 {
+        buf = vec![0; (size as usize).div_ceil(8)];
+        let result = unsafe {
+            GetAdaptersAddresses(
+                AF_UNSPEC.0 as u32,
+                flags,
+                None,
+                Some(buf.as_mut_ptr() as *mut IP_ADAPTER_ADDRESSES_LH),
+                &mut size,
+            )
+        };
+        match result {
+            0 => break,
+            r if r == ERROR_BUFFER_OVERFLOW.0 => continue,
+            _ => return Vec::new(),
+        }
+    }
+
+    let mut domains = Vec::new();
+    let mut adapter = buf.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+    while let Some(a) = unsafe { adapter.as_ref() } {
+        if a.OperStatus == IfOperStatusUp {
+            if let Ok(suffix) = unsafe { a.DnsSuffix.to_string() } {
+                if !suffix.is_empty() {
+                    domains.push(suffix);
+                }
+            }
+        }
+        adapter = a.Next;
+    }
+    domains
+}
+
+/// Domains of `/etc/resolv.conf`.
+#[cfg(unix)]
+fn domains() -> Vec<String> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix("search ")
+                .or_else(|| line.strip_prefix("domain "))
+        })
+        .flat_map(str::split_whitespace)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Subscribes to the system's network change notifications for the rest
+/// of the app's life.
+#[cfg(windows)]
+pub fn start() {
+    use std::ffi::c_void;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::NetworkManagement::IpHelper::{
+        NotifyIpInterfaceChange, NotifyRouteChange2, MIB_IPFORWARD_ROW2, MIB_IPINTERFACE_ROW,
+        MIB_NOTIFICATION_TYPE,
+    };
+    use windows::Win32::Networking::WinSock::AF_UNSPEC;
+
+    unsafe extern "system" fn on_interface(
+        _context: *const c_void,
+        _row: *const MIB_IPINTERFACE_ROW,
+        _kind: MIB_NOTIFICATION_TYPE,
+    ) {
+        notify_changed();
+    }
+
+    unsafe extern "system" fn on_route(
+        _context: *const c_void,
+        row: *const MIB_IPFORWARD_ROW2,
+        _kind: MIB_NOTIFICATION_TYPE,
+    ) {
+        // Only the default route decides where tunnels go
+        if !row.is_null() && (*row).DestinationPrefix.PrefixLength == 0 {
+            notify_changed();
+        }
+    }
+
+    let mut interface = HANDLE::default();
+    let mut route = HANDLE::default();
+    let subscribed = unsafe {
+        NotifyIpInterfaceChange(AF_UNSPEC, Some(on_interface), None, false, &mut interface)
+            .ok()
+            .and_then(|_| {
+                NotifyRouteChange2(
+                    AF_UNSPEC,
+                    Some(on_route),
+                    std::ptr::null(),
+                    false,
+                    &mut route,
+                )
+                .ok()
+            })
+    };
+    if let Err(e) = subscribed {
+        log::warn!("Network change notifications unavailable: {}", e);
+    }
+}
+
+#[cfg(not(windows))]
+pub fn start() {}
+
+#[cfg(test)]
+mod tests;
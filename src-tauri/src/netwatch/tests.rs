@@ -0,0 +1,45 @@
+use super::*;
+
+#[tokio::test]
+async fn changes_while_not_waiting_are_kept() {
+    let mut watch = Watch::new();
+    notify_changed();
+    notify_changed();
+    // Both seen at once, then nothing until the next change
+    tokio::time::timeout(Duration::from_secs(1), watch.changed())
+        .await
+        .unwrap();
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), watch.changed())
+            .await
+            .is_err()
+    );
+    notify_changed();
+    tokio::time::timeout(Duration::from_secs(1), watch.changed())
+        .await
+        .unwrap();
+}
+
+#[test]
+fn network_conditions_match_subnet_domain_and_except() {
+    let office = Network {
+        addresses: vec!["10.1.20.7".parse().unwrap()],
+        ssid: Some("Office".to_string()),
+        domains: vec!["lan.corp.example.com".to_string()],
+    };
+    let mut condition = NetworkCondition {
+        ssid: None,
+        domain: Some("corp.example.com".to_string()),
+        subnet: Some("10.1.0.0/16".to_string()),
+        except: false,
+    };
+    assert!(matches(&condition, &office));
+    assert!(!matches(&condition, &Network::default()));
+
+    condition.except = true;
+    assert!(!matches(&condition, &office));
+    assert!(matches(&condition, &Network::default()));
+
+    condition.subnet = Some("10.2.0.0/16".to_string());
+    assert!(matches(&condition, &office));
+}
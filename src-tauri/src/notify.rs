@@ -0,0 +1,112 @@
+//! Notifications (`notification` events), throttled per tunnel: after one
+//! goes out, the tunnel's next ones within `notifyThrottleSec` are held
+//! back and summed up when the window ends, one notification per kind, so
+//! a flaky connection doesn't produce dozens of them and an error isn't
+//! buried under the warnings that followed it.
+
+use crate::config;
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Runtime};
+
+/// A tunnel's throttle window, with the notifications it held back.
+struct Window {
+    tunnel_id: String,
+    held: Vec<Held>,
+}
+
+/// Held-back notifications of one kind: how many, and the latest.
+struct Held {
+    kind: String,
+    count: u32,
+    body: String,
+}
+
+static WINDOWS: Mutex<Vec<Window>> = Mutex::new(Vec::new());
+
+fn emit<R: Runtime>(app_handle: &AppHandle<R>, kind: &str, body: &str) {
+    let _ = app_handle.emit(
+        "notification",
+        json!({
+            "title": "OpenTunnel",
+            "body": body,
+            "type": kind
+        }),
+    );
+}
+
+/// Holds the notification back if `tunnel_id`'s window is open; otherwise
+/// opens one and returns true, for it to go out now.
+fn open(windows: &mut Vec<Window>, tunnel_id: &str, kind: &str, body: String) -> bool {
+    let Some(open) = windows.iter_mut().find(|w| w.tunnel_id == tunnel_id) else {
+        windows.push(Window {
+            tunnel_id: tunnel_id.to_string(),
+            held: Vec::new(),
+        });
+        return true;
+    };
+    match open.held.iter_mut().find(|h| h.kind == kind) {
+        Some(held) => {
+            held.count += 1;
+            held.body = body;
+        }
+        None => open.held.push(Held {
+            kind: kind.to_string(),
+            count: 1,
+            body,
+        }),
+    }
+    false
+}
+
+/// Closes `tunnel_id`'s window: one notification per kind it held back,
+/// the latest of each, with how many more there were.
+fn close(windows: &mut Vec<Window>, tunnel_id: &str, window: u64) -> Vec<(String, String)> {
+    let Some(i) = windows.iter().position(|w| w.tunnel_id == tunnel_id) else {
+        return Vec::new();
+    };
+    windows
+        .remove(i)
+        .held
+        .into_iter()
+        .map(|h| {
+            let body = match h.count {
+                1 => h.body,
+                n => format!("{} (and {} similar in the last {}s)", h.body, n - 1, window),
+            };
+            (h.kind, body)
+        })
+        .collect()
+}
+
+/// Notifies about `tunnel_id`, unless it was notified about within the
+/// throttle window.
+pub fn send<R: Runtime>(app_handle: &AppHandle<R>, tunnel_id: &str, kind: &str, body: String) {
+    let window = config::load_config().settings.notify_throttle_sec;
+    if window == 0 {
+        emit(app_handle, kind, &body);
+        return;
+    }
+    let body = {
+        let mut windows = WINDOWS.lock().unwrap();
+        if !open(&mut windows, tunnel_id, kind, body.clone()) {
+            return;
+        }
+        body
+    };
+    emit(app_handle, kind, &body);
+
+    let app_handle = app_handle.clone();
+    let tunnel_id = tunnel_id.to_string();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(window)).await;
+        let held = close(&mut WINDOWS.lock().unwrap(), &tunnel_id, window);
+        for (kind, body) in held {
+            emit(&app_handle, &kind, &body);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,33 @@
+use super::*;
+
+#[test]
+fn held_notifications_are_summed_up_per_kind() {
+    let mut windows = Vec::new();
+    assert!(open(&mut windows, "a", "error", "down".to_string()));
+    // Another tunnel has a window of its own
+    assert!(open(&mut windows, "b", "error", "down".to_string()));
+
+    assert!(!open(&mut windows, "a", "warning", "slow".to_string()));
+    assert!(!open(&mut windows, "a", "error", "down again".to_string()));
+    assert!(!open(
+        &mut windows,
+        "a",
+        "error",
+        "down for good".to_string()
+    ));
+
+    assert_eq!(
+        close(&mut windows, "a", 60),
+        [
+            ("warning".to_string(), "slow".to_string()),
+            (
+                "error".to_string(),
+                "down for good (and 1 similar in the last 60s)".to_string()
+            ),
+        ]
+    );
+    // Nothing held back, nothing more to say
+    assert!(close(&mut windows, "b", 60).is_empty());
+    assert!(windows.is_empty());
+    assert!(open(&mut windows, "a", "error", "down".to_string()));
+}
@@ -0,0 +1,278 @@
+//! Clients left running by an OpenTunnel that crashed or was killed. Each
+//! client is tagged with `MARKER_ENV` and recorded here while it runs; at
+//! startup the ones whose instance is gone are stopped so they don't hold
+//! ports the new tunnels need.
+//!
+//! On Windows clients normally die with the app through their Job Object;
+//! the registry covers those that couldn't be put in one. There a client is
+//! recognized by its start time rather than its environment.
+
+use crate::config;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+/// Environment variable carrying the tunnel id (or `mux`) of a client.
+pub const MARKER_ENV: &str = "OPENTUNNEL_TUNNEL";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Client {
+    /// Tunnel id, or the shared connection's key.
+    id: String,
+    /// Marker value the process was started with.
+    marker: String,
+    pid: u32,
+    /// The instance that started it.
+    owner: u32,
+    /// When the process started, where that's how it's recognized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    started: Option<u64>,
+}
+
+fn registry_path() -> PathBuf {
+    config::config_dir().join("clients.json")
+}
+
+fn load() -> Vec<Client> {
+    fs::read_to_string(registry_path())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Writes the registry aside and renames it over the old one, so readers
+/// never see half of it.
+fn save(clients: &[Client]) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(clients).map_err(|e| e.to_string())?;
+    let path = registry_path();
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            e.to_string()
+        })
+}
+
+/// Applies `f` to the registry, saving it if `f` says it changed. A lock
+/// file keeps other instances from updating it at the same time.
+fn update(f: impl FnOnce(&mut Vec<Client>) -> bool) {
+    let dir = config::config_dir();
+    let locked = fs::create_dir_all(&dir)
+        .and_then(|_| File::create(dir.join("clients.lock")))
+        .and_then(|lock| lock.lock().map(|_| lock));
+    let _lock = match locked {
+        Ok(lock) => lock,
+        Err(e) => {
+            warn!("Failed to lock client registry: {}", e);
+            return;
+        }
+    };
+    let mut clients = load();
+    if f(&mut clients) {
+        if let Err(e) = save(&clients) {
+            warn!("Failed to save client registry: {}", e);
+        }
+    }
+}
+
+/// Records the client process `pid` started for `id` with `marker`.
+pub async fn record(id: &str, marker: &str, pid: u32) {
+    let client = Client {
+        id: id.to_string(),
+        marker: marker.to_string(),
+        pid,
+        owner: std::process::id(),
+        started: platform::start_time(pid),
+    };
+    let _ = tokio::task::spawn_blocking(move || {
+        update(|clients| {
+            clients.retain(|c| c.id != client.id);
+            clients.push(client);
+            true
+        })
+    })
+    .await;
+}
+
+/// Drops `id` from the registry once its client is gone.
+pub async fn forget(id: &str) {
+    let id = id.to_string();
+    let _ = tokio::task::spawn_blocking(move || {
+        update(|clients| {
+            let before = clients.len();
+            clients.retain(|c| c.id != id);
+            clients.len() != before
+        })
+    })
+    .await;
+}
+
+/// Stops recorded clients whose instance is no longer running. Call at
+/// startup, before any tunnel starts, off the async runtime.
+pub fn cleanup() {
+    // One recorded with our pid was started by an earlier instance
+    stop_orphans(|owner| owner == std::process::id() || !platform::alive(owner));
+}
+
+/// Stops the recorded clients whose owner `gone` says is gone.
+fn stop_orphans(gone: impl Fn(u32) -> bool) {
+    update(|clients| {
+        let (orphans, kept): (Vec<Client>, Vec<Client>) =
+            clients.drain(..).partition(|c| gone(c.owner));
+        for client in &orphans {
+            if platform::is_ours(client) {
+                info!(
+                    "Stopping client {} left running for '{}'",
+                    client.pid, client.id
+                );
+                platform::stop(client.pid);
+            }
+        }
+        *clients = kept;
+        !orphans.is_empty()
+    });
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::{Client, MARKER_ENV};
+    use std::time::{Duration, Instant};
+
+    const GRACE: Duration = Duration::from_secs(2);
+
+    pub fn alive(pid: u32) -> bool {
+        unsafe { libc::kill(pid as i32, 0) == 0 }
+    }
+
+    /// Not needed: clients are recognized by their marker.
+    pub fn start_time(_pid: u32) -> Option<u64> {
+        None
+    }
+
+    /// Whether the client's pid still is the client we started, not a
+    /// process that reused its id.
+    pub fn is_ours(client: &Client) -> bool {
+        let pid = client.pid;
+        let tag = format!("{}={}", MARKER_ENV, client.marker);
+        #[cfg(target_os = "linux")]
+        {
+            std::fs::read(format!("/proc/{}/environ", pid))
+                .map(|env| env.split(|b| *b == 0).any(|var| var == tag.as_bytes()))
+                .unwrap_or(false)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            // BSD/macOS ps prints the environment after the command with -E
+            std::process::Command::new("ps")
+                .args(["-E", "-p", &pid.to_string(), "-o", "command="])
+                .output()
+                .map(|out| {
+                    String::from_utf8_lossy(&out.stdout)
+                        .split_whitespace()
+                        .any(|var| var == tag)
+                })
+                .unwrap_or(false)
+        }
+    }
+
+    /// Terminates the process group led by `pid`, killing it if it's still
+    /// there after a grace period.
+    pub fn stop(pid: u32) {
+        let pgid = pid as i32;
+        unsafe {
+            libc::killpg(pgid, libc::SIGTERM);
+        }
+        let deadline = Instant::now() + GRACE;
+        while alive(pid) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        unsafe {
+            libc::killpg(pgid, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::Client;
+    use windows::Win32::Foundation::{CloseHandle, FILETIME, HANDLE, STILL_ACTIVE};
+    use windows::Win32::System::Threading::{
+        GetExitCodeProcess, GetProcessTimes, OpenProcess, TerminateProcess, PROCESS_ACCESS_RIGHTS,
+        PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE,
+    };
+
+    /// Runs `f` on a handle to process `pid`, if it can be opened.
+    fn with_process<T>(
+        pid: u32,
+        access: PROCESS_ACCESS_RIGHTS,
+        f: impl FnOnce(HANDLE) -> Option<T>,
+    ) -> Option<T> {
+        unsafe {
+            let process = OpenProcess(access, false, pid).ok()?;
+            let result = f(process);
+            let _ = CloseHandle(process);
+            result
+        }
+    }
+
+    pub fn alive(pid: u32) -> bool {
+        with_process(pid, PROCESS_QUERY_LIMITED_INFORMATION, |process| {
+            let mut code = 0u32;
+            unsafe { GetExitCodeProcess(process, &mut code).ok()? };
+            Some(code == STILL_ACTIVE.0 as u32)
+        })
+        .unwrap_or(false)
+    }
+
+    /// When process `pid` started, in FILETIME units.
+    pub fn start_time(pid: u32) -> Option<u64> {
+        with_process(pid, PROCESS_QUERY_LIMITED_INFORMATION, |process| {
+            let mut created = FILETIME::default();
+            let mut exited = FILETIME::default();
+            let mut kernel = FILETIME::default();
+            let mut user = FILETIME::default();
+            unsafe {
+                GetProcessTimes(process, &mut created, &mut exited, &mut kernel, &mut user).ok()?
+            };
+            Some(((created.dwHighDateTime as u64) << 32) | created.dwLowDateTime as u64)
+        })
+    }
+
+    /// Whether the client's pid still is the process we started: one that
+    /// reused its id started at another time.
+    pub fn is_ours(client: &Client) -> bool {
+        client.started.is_some() && start_time(client.pid) == client.started
+    }
+
+    /// Kills the client; its helpers went with its Job Object, if it had
+    /// one.
+    pub fn stop(pid: u32) {
+        with_process(pid, PROCESS_TERMINATE, |process| unsafe {
+            TerminateProcess(process, 1).ok()
+        });
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use super::Client;
+
+    pub fn alive(_pid: u32) -> bool {
+        false
+    }
+
+    pub fn start_time(_pid: u32) -> Option<u64> {
+        None
+    }
+
+    pub fn is_ours(_client: &Client) -> bool {
+        false
+    }
+
+    pub fn stop(_pid: u32) {}
+}
+
+#[cfg(test)]
+mod tests;
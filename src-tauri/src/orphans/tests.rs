@@ -0,0 +1,49 @@
+use super::*;
+
+fn ids() -> Vec<String> {
+    load().into_iter().map(|c| c.id).collect()
+}
+
+#[tokio::test]
+async fn registry_records_forgets_and_cleans_up() {
+    record("orphans-a", "orphans-a", 1).await;
+    record("orphans-b", "orphans-b", 2).await;
+    record("orphans-a", "orphans-a", 3).await;
+    forget("orphans-b").await;
+    let clients = load();
+    assert!(clients.iter().any(|c| c.id == "orphans-a" && c.pid == 3));
+    assert!(!clients.iter().any(|c| c.id == "orphans-a" && c.pid == 1));
+    assert!(!ids().contains(&"orphans-b".to_string()));
+    assert!(!registry_path().with_extension("json.tmp").exists());
+
+    // A client of an instance that's gone
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let mut dead = std::process::Command::new("true").spawn().unwrap();
+        let owner = dead.id();
+        dead.wait().unwrap();
+        let mut orphan = std::process::Command::new("sleep")
+            .arg("30")
+            .env(MARKER_ENV, "orphans-c")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        update(|clients| {
+            clients.push(Client {
+                id: "orphans-c".to_string(),
+                marker: "orphans-c".to_string(),
+                pid: orphan.id(),
+                owner,
+                started: None,
+            });
+            true
+        });
+        // Not `cleanup`, which would take other tests' clients for orphans
+        tokio::task::spawn_blocking(move || stop_orphans(|o| o == owner))
+            .await
+            .unwrap();
+        assert!(orphan.wait().is_ok_and(|status| !status.success()));
+        assert!(!ids().contains(&"orphans-c".to_string()));
+    }
+}
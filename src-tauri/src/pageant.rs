@@ -0,0 +1,126 @@
+//! Loading a tunnel's key into Pageant before plink starts, so a
+//! passphrase-protected key only needs its passphrase typed once (in
+//! Pageant's own prompt) instead of failing in batch mode.
+
+use crate::config;
+use crate::diagnostics;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::Instant;
+
+/// How long Pageant gets to come up when we start it.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the user gets to type the passphrase.
+const PASSPHRASE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Keys loaded into Pageant, with the process id of the Pageant they went
+/// into: one started since holds none of them.
+static LOADED: Mutex<(Option<u32>, Vec<String>)> = Mutex::new((None, Vec::new()));
+/// Held while starting Pageant, so it's started once.
+static STARTING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+/// A lock per key, held while loading it, so tunnels starting together
+/// with the same key prompt once without holding up other keys.
+static LOADING: Mutex<Vec<(String, Arc<tokio::sync::Mutex<()>>)>> = Mutex::new(Vec::new());
+
+/// `pageant.exe` next to plink, or from PATH.
+fn pageant_path(plink_path: &str) -> PathBuf {
+    Path::new(plink_path)
+        .parent()
+        .map(|dir| dir.join("pageant.exe"))
+        .filter(|p| p.is_file())
+        .or_else(|| config::find_in_path("pageant.exe"))
+        .unwrap_or_else(|| PathBuf::from("pageant.exe"))
+}
+
+/// Process id of the running Pageant.
+async fn running() -> Option<u32> {
+    tokio::task::spawn_blocking(diagnostics::pageant_pid)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Process id of the running Pageant, started if there's none.
+async fn ensure_running(pageant: &Path) -> Result<u32, String> {
+    let _starting = STARTING.lock().await;
+    if let Some(pid) = running().await {
+        return Ok(pid);
+    }
+    Command::new(pageant)
+        .spawn()
+        .map_err(|e| format!("Failed to start {}: {}", pageant.display(), e))?;
+    let deadline = Instant::now() + START_TIMEOUT;
+    loop {
+        if let Some(pid) = running().await {
+            return Ok(pid);
+        }
+        if Instant::now() >= deadline {
+            return Err("Timed out waiting for Pageant to start".to_string());
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+/// Whether `key` went into the Pageant running as `pid`.
+fn is_loaded(pid: u32, key: &str) -> bool {
+    let loaded = LOADED.lock().unwrap();
+    loaded.0 == Some(pid) && loaded.1.iter().any(|k| k == key)
+}
+
+fn mark_loaded(pid: u32, key: &str) {
+    let mut loaded = LOADED.lock().unwrap();
+    if loaded.0 != Some(pid) {
+        *loaded = (Some(pid), Vec::new());
+    }
+    loaded.1.push(key.to_string());
+}
+
+/// Makes sure Pageant runs (starting it if needed) and holds `key`, which
+/// may prompt for its passphrase. Windows only; elsewhere it does nothing.
+/// Checked on every start against the Pageant running then; a key removed
+/// from a Pageant that kept running isn't noticed.
+pub async fn load_key(plink_path: &str, key: &str) -> Result<(), String> {
+    if !cfg!(windows) {
+        return Ok(());
+    }
+    let pageant = pageant_path(plink_path);
+    let pid = ensure_running(&pageant).await?;
+
+    let lock = {
+        let mut loading = LOADING.lock().unwrap();
+        match loading.iter().find(|(k, _)| k == key) {
+            Some((_, lock)) => lock.clone(),
+            None => {
+                let lock = Arc::new(tokio::sync::Mutex::new(()));
+                loading.push((key.to_string(), lock.clone()));
+                lock
+            }
+        }
+    };
+    let _loading = lock.lock().await;
+    if is_loaded(pid, key) {
+        return Ok(());
+    }
+
+    // A second Pageant hands its keys to the running one and exits once
+    // they're loaded; one still prompting when time's up is killed
+    let mut child = Command::new(&pageant)
+        .arg(key)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", pageant.display(), e))?;
+    match tokio::time::timeout(PASSPHRASE_TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if status.success() => {
+            mark_loaded(pid, key);
+            Ok(())
+        }
+        Ok(Ok(status)) => Err(format!("Pageant couldn't load {} ({})", key, status)),
+        Ok(Err(e)) => Err(format!("Failed to run {}: {}", pageant.display(), e)),
+        Err(_) => {
+            let _ = child.kill().await;
+            Err(format!("Timed out loading {} into Pageant", key))
+        }
+    }
+}
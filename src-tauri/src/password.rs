@@ -0,0 +1,148 @@
+//! Password authentication for unattended tunnels. The password goes to the
+//! client through a file only the user can read, never on the command line
+//! where other processes could see it (owner-only permissions on Unix, an
+//! owner-only ACL on Windows): plink reads it with `-pwfile`, OpenSSH
+//! through this executable run as its `SSH_ASKPASS`, and the built-in
+//! client directly. The file lives from just before the client starts
+//! until it has connected or exited.
+
+use crate::config::{self, AuthMethod, TunnelConfig};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Environment variable pointing the client's helpers at the password file.
+pub const FILE_ENV: &str = "OPENTUNNEL_PWFILE";
+
+/// Whether `tunnel` logs in with a stored password.
+pub fn needed(tunnel: &TunnelConfig) -> bool {
+    tunnel.auth_method == AuthMethod::Password
+        && tunnel.password.as_deref().is_some_and(|p| !p.is_empty())
+}
+
+pub fn file_path(tunnel_id: &str) -> PathBuf {
+    config::config_dir()
+        .join("run")
+        .join(format!("{}.pw", tunnel_id))
+}
+
+/// Writes the tunnel's password file, if it logs in with one.
+pub async fn write(tunnel: &TunnelConfig) -> Result<(), String> {
+    let Some(password) = tunnel.password.clone().filter(|_| needed(tunnel)) else {
+        return Ok(());
+    };
+    let path = file_path(&tunnel.id);
+    tokio::task::spawn_blocking(move || write_file(&path, &password))
+        .await
+        .map_err(|e| format!("Failed to write password file: {}", e))?
+}
+
+fn write_file(path: &Path, password: &str) -> Result<(), String> {
+    let error = |e: std::io::Error| format!("Failed to write password file: {}", e);
+
+    let mut dir = std::fs::DirBuilder::new();
+    dir.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut dir, 0o700);
+    if let Some(parent) = path.parent() {
+        dir.create(parent).map_err(error)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).map_err(error)?;
+    // Locked down while still empty
+    #[cfg(windows)]
+    owner_only(path).map_err(error)?;
+    std::io::Write::write_all(&mut file, password.as_bytes()).map_err(error)
+}
+
+/// Replaces the file's inherited ACL with one granting its owner alone
+/// access.
+#[cfg(windows)]
+fn owner_only(path: &Path) -> std::io::Result<()> {
+    use windows::core::{w, BOOL, HSTRING};
+    use windows::Win32::Foundation::{LocalFree, HLOCAL};
+    use windows::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SetNamedSecurityInfoW,
+        SDDL_REVISION_1, SE_FILE_OBJECT,
+    };
+    use windows::Win32::Security::{
+        GetSecurityDescriptorDacl, ACL, DACL_SECURITY_INFORMATION,
+        PROTECTED_DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR,
+    };
+
+    unsafe {
+        // Protected (not inheriting), full access for the owner
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            w!("D:P(A;;FA;;;OW)"),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            None,
+        )?;
+        let mut present = BOOL::default();
+        let mut defaulted = BOOL::default();
+        let mut dacl: *mut ACL = std::ptr::null_mut();
+        let result = GetSecurityDescriptorDacl(descriptor, &mut present, &mut dacl, &mut defaulted)
+            .and_then(|()| {
+                SetNamedSecurityInfoW(
+                    &HSTRING::from(path.as_os_str()),
+                    SE_FILE_OBJECT,
+                    DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+                    None,
+                    None,
+                    Some(dacl),
+                    None,
+                )
+                .ok()
+            });
+        let _ = LocalFree(Some(HLOCAL(descriptor.0)));
+        result.map_err(std::io::Error::other)
+    }
+}
+
+/// Deletes the tunnel's password file once the client no longer needs it.
+pub async fn forget(tunnel_id: &str) {
+    let path = file_path(tunnel_id);
+    let _ = tokio::task::spawn_blocking(move || std::fs::remove_file(path)).await;
+}
+
+/// Points `command` at the tunnel's password file, with this executable
+/// answering OpenSSH's password prompt.
+pub fn apply_env(command: &mut Command, tunnel: &TunnelConfig) {
+    if !needed(tunnel) {
+        return;
+    }
+    command.env(FILE_ENV, file_path(&tunnel.id));
+    if let Ok(exe) = std::env::current_exe() {
+        command
+            .env("SSH_ASKPASS", exe)
+            .env("SSH_ASKPASS_REQUIRE", "force");
+    }
+}
+
+/// Answers OpenSSH's prompt (and exits) when run as its `SSH_ASKPASS`. Only
+/// password prompts get an answer; anything else, like a host key
+/// confirmation, is refused.
+pub fn run_if_askpass() {
+    let Some(file) = std::env::var_os(FILE_ENV) else {
+        return;
+    };
+    let args: Vec<String> = std::env::args().collect();
+    let [_, prompt] = &args[..] else {
+        return;
+    };
+
+    if !prompt.to_lowercase().contains("password") {
+        std::process::exit(1);
+    }
+    match std::fs::read_to_string(file) {
+        Ok(password) => {
+            println!("{}", password);
+            std::process::exit(0);
+        }
+        Err(_) => std::process::exit(1),
+    }
+}
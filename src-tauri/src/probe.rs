@@ -0,0 +1,80 @@
+//! End-to-end health probes: connecting through a local forward to the
+//! service behind it, so a dead destination shows up even while the SSH
+//! session is fine.
+
+use crate::config::{self, HealthProbe, TunnelConfig, TunnelType};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Instant};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a TCP probe waits for the client to hang up, which it does
+/// when it can't reach the destination.
+const HANG_UP_GRACE: Duration = Duration::from_secs(1);
+
+/// Runs `tunnel`'s probe, if it has one, through its local port at `host`,
+/// returning its round trip. Only local forwards have a destination
+/// reachable from here.
+pub async fn check(tunnel: &TunnelConfig, host: &str) -> Result<Option<Duration>, String> {
+    let Some(ref probe) = tunnel.health_probe else {
+        return Ok(None);
+    };
+    if tunnel.tunnel_type != TunnelType::Local {
+        return Ok(None);
+    }
+    let started = Instant::now();
+    run(probe, tunnel, host).await?;
+    Ok(Some(started.elapsed()))
+}
+
+async fn run(probe: &HealthProbe, tunnel: &TunnelConfig, host: &str) -> Result<(), String> {
+    let destination = format!(
+        "{}:{}",
+        config::bracket_host(&tunnel.remote_host),
+        tunnel.remote_port
+    );
+
+    let connect = TcpStream::connect((host, tunnel.local_port));
+    let mut stream = timeout(CONNECT_TIMEOUT, connect)
+        .await
+        .map_err(|_| "timed out connecting through the tunnel".to_string())?
+        .map_err(|e| format!("can't connect through the tunnel: {}", e))?;
+    let mut buf = [0u8; 512];
+
+    match probe {
+        HealthProbe::Tcp => match timeout(HANG_UP_GRACE, stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => Err(format!("{} closed the connection", destination)),
+            _ => Ok(()),
+        },
+        HealthProbe::Http(http) => {
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                http.path, destination
+            );
+            stream
+                .write_all(request.as_bytes())
+                .await
+                .map_err(|e| format!("HTTP probe of {} failed: {}", destination, e))?;
+            let n = timeout(RESPONSE_TIMEOUT, stream.read(&mut buf))
+                .await
+                .map_err(|_| format!("{} didn't answer the HTTP probe", destination))?
+                .map_err(|e| format!("HTTP probe of {} failed: {}", destination, e))?;
+            let response = String::from_utf8_lossy(&buf[..n]);
+            let status = response
+                .lines()
+                .next()
+                .and_then(|line| line.split_whitespace().nth(1))
+                .and_then(|code| code.parse::<u16>().ok());
+            match status {
+                Some(code) if code == http.expect_status => Ok(()),
+                Some(code) => Err(format!(
+                    "{}{} answered {} instead of {}",
+                    destination, http.path, code, http.expect_status
+                )),
+                None => Err(format!("{} didn't answer the HTTP probe", destination)),
+            }
+        }
+    }
+}
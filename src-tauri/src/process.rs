@@ -0,0 +1,294 @@
+use std::io;
+use std::process::ExitStatus;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+/// How often a client is checked for having exited, when it has to stay
+/// unreaped meanwhile.
+#[cfg(unix)]
+const EXIT_POLL: Duration = Duration::from_millis(200);
+
+/// Puts the client in its own process group so its helpers can be
+/// signalled together, and on Windows so it can be sent CTRL_BREAK without
+/// OpenTunnel getting it too. Call before spawning. On Windows the client
+/// starts suspended, so it can't start anything before it's in its job;
+/// `ProcessTree::attach` lets it run.
+pub fn new_group(cmd: &mut Command) {
+    #[cfg(unix)]
+    cmd.process_group(0);
+    #[cfg(windows)]
+    cmd.creation_flags(windows_console::CREATE_NEW_PROCESS_GROUP | windows_job::CREATE_SUSPENDED);
+    #[cfg(not(any(unix, windows)))]
+    let _ = cmd;
+}
+
+/// A spawned client together with everything it starts (ProxyCommand
+/// helpers, cloudflared, ...): a process group on Unix, a Job Object on
+/// Windows.
+///
+/// A process group is named after its leader's pid, which can be reused
+/// once the leader is reaped, so the group is only signalled while the
+/// client is known not to have been.
+pub struct ProcessTree {
+    #[cfg(unix)]
+    pgid: Option<i32>,
+    /// Job handle, kept as an integer so the tree can move between threads.
+    #[cfg(windows)]
+    job: Option<isize>,
+    /// The client's process group, named after it.
+    #[cfg(windows)]
+    group: Option<u32>,
+}
+
+impl ProcessTree {
+    /// Tracks the tree rooted at `child`, which must have been spawned with
+    /// `new_group`, and lets it run.
+    pub fn attach(child: &Child) -> Self {
+        #[cfg(unix)]
+        {
+            Self {
+                pgid: child.id().map(|pid| pid as i32),
+            }
+        }
+        #[cfg(windows)]
+        {
+            let job = windows_job::assign(child);
+            // Resumed even if it couldn't be put in a job
+            windows_job::resume(child);
+            Self {
+                job,
+                group: child.id(),
+            }
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = child;
+            Self {}
+        }
+    }
+
+    /// The process group to signal, if `child` still holds its id: it
+    /// hasn't been reaped, though it may have exited.
+    #[cfg(unix)]
+    fn group(&self, child: &mut Child) -> Option<i32> {
+        self.pgid.filter(|_| matches!(child.try_wait(), Ok(None)))
+    }
+
+    /// Asks every process in the tree to exit: SIGTERM on Unix, CTRL_BREAK
+    /// to the client's process group on Windows.
+    pub fn terminate(&self, child: &mut Child) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.group(child) {
+            unsafe {
+                libc::killpg(pgid, libc::SIGTERM);
+            }
+        }
+        #[cfg(windows)]
+        if let Some(group) = self.group {
+            windows_console::ctrl_break(group);
+        }
+        #[cfg(not(unix))]
+        let _ = child;
+    }
+
+    /// Kills every process left in the tree.
+    pub fn kill(&self, child: &mut Child) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.group(child) {
+            unsafe {
+                libc::killpg(pgid, libc::SIGKILL);
+            }
+        }
+        #[cfg(windows)]
+        if let Some(job) = self.job {
+            windows_job::terminate(job);
+        }
+        #[cfg(not(unix))]
+        let _ = child;
+    }
+
+    /// Waits for the client to exit, then kills the helpers that outlived
+    /// it. On Unix it's left unreaped until then, so its pid still names
+    /// the group.
+    pub async fn wait(&self, child: &mut Child) -> io::Result<ExitStatus> {
+        #[cfg(unix)]
+        if let Some(pid) = self.pgid {
+            while !exited(pid) {
+                tokio::time::sleep(EXIT_POLL).await;
+            }
+        }
+        #[cfg(not(unix))]
+        let status = child.wait().await;
+        self.kill(child);
+        #[cfg(unix)]
+        let status = child.wait().await;
+        status
+    }
+}
+
+/// Whether child `pid` has exited, leaving it to be reaped.
+#[cfg(unix)]
+fn exited(pid: i32) -> bool {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let found = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    };
+    // Not our child any more (or never was): nothing to wait for
+    found != 0 || info.si_signo != 0
+}
+
+#[cfg(windows)]
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        if let Some(job) = self.job {
+            windows_job::close(job);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows_job {
+    use log::warn;
+    use tokio::process::Child;
+    use windows::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+    };
+    use windows::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, TerminateJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+    use windows::Win32::System::Threading::{OpenThread, ResumeThread, THREAD_SUSPEND_RESUME};
+
+    pub const CREATE_SUSPENDED: u32 = 0x0000_0004;
+
+    fn handle(job: isize) -> HANDLE {
+        HANDLE(job as *mut std::ffi::c_void)
+    }
+
+    /// Creates a job for `child` and puts it in; processes it starts
+    /// inherit the job. The job kills them all when its last handle is
+    /// closed, including by Windows when OpenTunnel crashes or is killed.
+    pub fn assign(child: &Child) -> Option<isize> {
+        let process = HANDLE(child.raw_handle()?);
+        unsafe {
+            let job = match CreateJobObjectW(None, None) {
+                Ok(job) => job,
+                Err(e) => {
+                    warn!("Failed to create job object: {}", e);
+                    return None;
+                }
+            };
+            let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+            limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            if let Err(e) = SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &limits as *const _ as *const std::ffi::c_void,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            ) {
+                // Still useful for killing the tree on stop
+                warn!("Failed to set kill-on-close on job object: {}", e);
+            }
+            if let Err(e) = AssignProcessToJobObject(job, process) {
+                warn!("Failed to assign process to job object: {}", e);
+                let _ = CloseHandle(job);
+                return None;
+            }
+            Some(job.0 as isize)
+        }
+    }
+
+    /// Lets `child`, spawned suspended, run. A new process has a single
+    /// thread, found among the system's.
+    pub fn resume(child: &Child) {
+        let Some(pid) = child.id() else {
+            return;
+        };
+        unsafe {
+            let snapshot = match CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    warn!("Failed to resume process {}: {}", pid, e);
+                    return;
+                }
+            };
+            let mut entry = THREADENTRY32 {
+                dwSize: std::mem::size_of::<THREADENTRY32>() as u32,
+                ..Default::default()
+            };
+            let mut more = Thread32First(snapshot, &mut entry).is_ok();
+            while more {
+                if entry.th32OwnerProcessID == pid {
+                    match OpenThread(THREAD_SUSPEND_RESUME, false, entry.th32ThreadID) {
+                        Ok(thread) => {
+                            ResumeThread(thread);
+                            let _ = CloseHandle(thread);
+                        }
+                        Err(e) => warn!("Failed to resume process {}: {}", pid, e),
+                    }
+                }
+                more = Thread32Next(snapshot, &mut entry).is_ok();
+            }
+            let _ = CloseHandle(snapshot);
+        }
+    }
+
+    pub fn terminate(job: isize) {
+        unsafe {
+            let _ = TerminateJobObject(handle(job), 1);
+        }
+    }
+
+    pub fn close(job: isize) {
+        unsafe {
+            let _ = CloseHandle(handle(job));
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows_console {
+    use std::sync::Mutex;
+    use windows::Win32::System::Console::{
+        AttachConsole, FreeConsole, GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT,
+    };
+
+    pub const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+    /// Attaching to a console is process-wide; one signal at a time.
+    static CONSOLE: Mutex<()> = Mutex::new(());
+
+    /// Sends CTRL_BREAK to process group `group`. Only a process sharing
+    /// its console can, so OpenTunnel attaches to the client's for the
+    /// time it takes.
+    pub fn ctrl_break(group: u32) {
+        let _console = CONSOLE.lock().unwrap();
+        unsafe {
+            let _ = FreeConsole();
+            if AttachConsole(group).is_err() {
+                return;
+            }
+            let _ = GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, group);
+            let _ = FreeConsole();
+        }
+    }
+}
+
+/// Stops `child` and its tree: asks them to exit, closing the SSH session
+/// cleanly, and kills whatever is left after `grace`.
+pub async fn stop(child: &mut Child, tree: &ProcessTree, grace: Duration) {
+    tree.terminate(child);
+    // Clients reading stdin take its end as the request to quit
+    drop(child.stdin.take());
+    if tokio::time::timeout(grace, tree.wait(child)).await.is_err() {
+        tree.kill(child);
+        let _ = child.kill().await;
+    }
+}
@@ -0,0 +1,95 @@
+//! Named profiles ("work", "homelab"), each a config of its own with its
+//! own tunnels and settings. The default profile is the config in the
+//! config directory, the others live in `profiles/<name>/`, and the active
+//! one is remembered in `profile.txt`.
+
+use crate::config;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const DEFAULT: &str = "default";
+
+/// Active profile, once read from `profile.txt`.
+static ACTIVE: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Profile {
+    pub name: String,
+    pub active: bool,
+}
+
+fn active_file() -> PathBuf {
+    config::config_dir().join("profile.txt")
+}
+
+/// Directory holding profile `name`'s config and backups.
+pub fn dir(name: &str) -> PathBuf {
+    if name == DEFAULT {
+        config::config_dir()
+    } else {
+        config::config_dir().join("profiles").join(name)
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid profile name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The active profile; the default one if the remembered one is gone.
+pub fn active() -> String {
+    ACTIVE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| {
+            fs::read_to_string(active_file())
+                .ok()
+                .map(|name| name.trim().to_string())
+                .filter(|name| validate_name(name).is_ok() && dir(name).is_dir())
+                .unwrap_or_else(|| DEFAULT.to_string())
+        })
+        .clone()
+}
+
+/// Makes `name` the active profile, from now on and after a restart.
+pub fn set_active(name: &str) -> Result<(), String> {
+    fs::create_dir_all(dir(name)).map_err(|e| format!("Failed to create profile dir: {}", e))?;
+    fs::write(active_file(), name).map_err(|e| format!("Failed to save active profile: {}", e))?;
+    *ACTIVE.lock().unwrap() = Some(name.to_string());
+    Ok(())
+}
+
+/// The default profile, then the others by name.
+pub fn list() -> Vec<Profile> {
+    let active = active();
+    let mut names: Vec<String> = fs::read_dir(config::config_dir().join("profiles"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().join("config.json").is_file())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| name != DEFAULT && validate_name(name).is_ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names.insert(0, DEFAULT.to_string());
+    names
+        .into_iter()
+        .map(|name| Profile {
+            active: name == active,
+            name,
+        })
+        .collect()
+}
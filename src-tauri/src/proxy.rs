@@ -0,0 +1,321 @@
+//! SSH connections through an HTTP CONNECT or SOCKS5 proxy, for networks
+//! that only let traffic out through one. The SSH client runs this
+//! executable as its proxy command, `opentunnel --proxy-connect <protocol>
+//! <proxy> <target>`, which connects through the proxy and relays
+//! stdin/stdout. The proxy password comes through `PASSWORD_ENV`, never the
+//! command line.
+
+use crate::config::{self, ProxyProtocol, ProxyTransport, TunnelConfig};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+use tokio::process::Command;
+
+pub const HELPER_FLAG: &str = "--proxy-connect";
+/// Environment variable carrying the proxy password to the helper.
+pub const PASSWORD_ENV: &str = "OPENTUNNEL_PROXY_PASSWORD";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Proxy command arguments (program first) reaching `tunnel`'s server
+/// through `proxy`.
+pub fn command_args(proxy: &ProxyTransport, tunnel: &TunnelConfig) -> Vec<String> {
+    let exe = std::env::current_exe()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "opentunnel".to_string());
+    let address = format!("{}:{}", config::bracket_host(&proxy.host), proxy.port);
+    let address = match proxy.username.as_deref().filter(|u| !u.is_empty()) {
+        Some(user) => format!("{}@{}", user, address),
+        None => address,
+    };
+    vec![
+        exe,
+        HELPER_FLAG.to_string(),
+        proxy.protocol.as_str().to_string(),
+        address,
+        format!("{}:{}", config::bracket_host(&tunnel.host), tunnel.port),
+    ]
+}
+
+/// Hands the proxy password of `tunnel`, if any, to the helper `command`
+/// will start.
+pub fn apply_env(command: &mut Command, tunnel: &TunnelConfig) {
+    if let Some(config::Transport::Proxy(proxy)) = &tunnel.transport {
+        if let Some(password) = proxy.password.as_deref().filter(|p| !p.is_empty()) {
+            command.env(PASSWORD_ENV, password);
+        }
+    }
+}
+
+/// Becomes the proxy helper (and never returns) when started with
+/// `HELPER_FLAG`.
+pub fn run_if_helper() {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, flag, protocol, proxy, target] = &args[..] else {
+        return;
+    };
+    if flag != HELPER_FLAG {
+        return;
+    }
+
+    let result = parse_args(protocol, proxy, target).and_then(|(proxy, host, port)| {
+        let stream = connect(&proxy, &host, port)?;
+        relay(stream);
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("FATAL ERROR: {}", e);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+fn parse_args(
+    protocol: &str,
+    proxy: &str,
+    target: &str,
+) -> Result<(ProxyTransport, String, u16), String> {
+    let protocol = match protocol {
+        "http" => ProxyProtocol::Http,
+        "socks5" => ProxyProtocol::Socks5,
+        _ => return Err(format!("Unknown proxy protocol '{}'", protocol)),
+    };
+    let (username, address) = match proxy.rsplit_once('@') {
+        Some((user, address)) => (Some(user.to_string()), address),
+        None => (None, proxy),
+    };
+    let (host, port) =
+        config::split_host_port(address).ok_or_else(|| format!("Invalid proxy '{}'", proxy))?;
+    let (target_host, target_port) =
+        config::split_host_port(target).ok_or_else(|| format!("Invalid target '{}'", target))?;
+    let proxy = ProxyTransport {
+        protocol,
+        host,
+        port,
+        username,
+        password: std::env::var(PASSWORD_ENV).ok(),
+        password_ref: None,
+    };
+    Ok((proxy, target_host, target_port))
+}
+
+/// Opens a connection to `host:port` through `proxy`.
+pub fn connect(proxy: &ProxyTransport, host: &str, port: u16) -> Result<TcpStream, String> {
+    let address = (config::unbracket_host(&proxy.host), proxy.port);
+    let stream = std::net::ToSocketAddrs::to_socket_addrs(&address)
+        .map_err(|e| format!("Can't resolve proxy {}: {}", proxy.host, e))?
+        .find_map(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).ok())
+        .ok_or_else(|| format!("Can't connect to proxy {}:{}", proxy.host, proxy.port))?;
+    let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+    match proxy.protocol {
+        ProxyProtocol::Http => http_connect(&stream, proxy, host, port)?,
+        ProxyProtocol::Socks5 => socks5_connect(&stream, proxy, host, port)?,
+    }
+    let _ = stream.set_read_timeout(None);
+    Ok(stream)
+}
+
+fn http_connect(
+    mut stream: &TcpStream,
+    proxy: &ProxyTransport,
+    host: &str,
+    port: u16,
+) -> Result<(), String> {
+    let target = format!("{}:{}", config::bracket_host(host), port);
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some(user) = proxy.username.as_deref() {
+        let credentials = format!("{}:{}", user, proxy.password.as_deref().unwrap_or(""));
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64(credentials.as_bytes())
+        ));
+    }
+    request.push_str("\r\n");
+    let io = |e: std::io::Error| format!("Proxy error: {}", e);
+    stream.write_all(request.as_bytes()).map_err(io)?;
+
+    // Read the response headers a byte at a time, so nothing the server
+    // sends after them is lost
+    let mut reader = BufReader::with_capacity(1, stream);
+    let mut status = String::new();
+    reader.read_line(&mut status).map_err(io)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(io)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!(
+            "Proxy refused CONNECT to {}: {}",
+            target,
+            status.trim()
+        )),
+    }
+}
+
+/// Checks that a SOCKS5 proxy (such as a dynamic tunnel) on `host:port`
+/// completes a handshake and, given a `target`, connects to it.
+pub fn probe_socks5(host: &str, port: u16, target: Option<(&str, u16)>) -> Result<(), String> {
+    let proxy = ProxyTransport {
+        protocol: ProxyProtocol::Socks5,
+        host: host.to_string(),
+        port,
+        username: None,
+        password: None,
+        password_ref: None,
+    };
+    match target {
+        Some((target_host, target_port)) => connect(&proxy, target_host, target_port).map(drop),
+        None => {
+            let address = (config::unbracket_host(host), port);
+            let stream = std::net::ToSocketAddrs::to_socket_addrs(&address)
+                .map_err(|e| format!("Can't resolve {}: {}", host, e))?
+                .find_map(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).ok())
+                .ok_or_else(|| format!("Can't connect to {}:{}", host, port))?;
+            let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+            socks5_greet(&stream, &proxy)
+        }
+    }
+}
+
+/// Greets a SOCKS5 proxy and logs in if it asks to.
+fn socks5_greet(mut stream: &TcpStream, proxy: &ProxyTransport) -> Result<(), String> {
+    let io = |e: std::io::Error| format!("Proxy error: {}", e);
+    let user = proxy.username.as_deref().unwrap_or("");
+    let password = proxy.password.as_deref().unwrap_or("");
+
+    // Greeting: no authentication, or username/password (RFC 1929)
+    let greeting: &[u8] = if user.is_empty() {
+        &[5, 1, 0]
+    } else {
+        &[5, 2, 0, 2]
+    };
+    stream.write_all(greeting).map_err(io)?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).map_err(io)?;
+    match choice {
+        [5, 0] => {}
+        [5, 2] => {
+            let len = |field: &str, value: &str| {
+                u8::try_from(value.len())
+                    .map_err(|_| format!("SOCKS proxy {} is longer than 255 bytes", field))
+            };
+            let mut auth = vec![1, len("username", user)?];
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(len("password", password)?);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth).map_err(io)?;
+            let mut reply = [0u8; 2];
+            stream.read_exact(&mut reply).map_err(io)?;
+            if reply[1] != 0 {
+                return Err("SOCKS proxy rejected the credentials".to_string());
+            }
+        }
+        _ => return Err("SOCKS proxy accepts none of our authentication methods".to_string()),
+    }
+    Ok(())
+}
+
+fn socks5_connect(
+    mut stream: &TcpStream,
+    proxy: &ProxyTransport,
+    host: &str,
+    port: u16,
+) -> Result<(), String> {
+    let io = |e: std::io::Error| format!("Proxy error: {}", e);
+    socks5_greet(stream, proxy)?;
+
+    let mut request = vec![5, 1, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| format!("Host name {} is too long for a SOCKS proxy", host))?;
+            request.push(3);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).map_err(io)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).map_err(io)?;
+    if reply[1] != 0 {
+        return Err(format!(
+            "SOCKS proxy couldn't connect to {}:{} (error {})",
+            host, port, reply[1]
+        ));
+    }
+    // Skip the bound address and port
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        _ => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).map_err(io)?;
+            len[0] as usize
+        }
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).map_err(io)
+}
+
+/// Copies stdin to the connection and the connection to stdout until
+/// either side closes.
+fn relay(stream: TcpStream) {
+    let Ok(mut upstream) = stream.try_clone() else {
+        return;
+    };
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut std::io::stdin().lock(), &mut upstream);
+        let _ = upstream.shutdown(std::net::Shutdown::Write);
+    });
+    // stdout is line buffered; SSH needs every chunk flushed
+    let mut downstream = stream;
+    let mut stdout = std::io::stdout().lock();
+    let mut buf = [0u8; 16 * 1024];
+    while let Ok(n) = downstream.read(&mut buf) {
+        if n == 0
+            || stdout
+                .write_all(&buf[..n])
+                .and_then(|_| stdout.flush())
+                .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests;
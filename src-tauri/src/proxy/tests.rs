@@ -0,0 +1,106 @@
+use super::*;
+use std::net::TcpListener;
+use std::thread::JoinHandle;
+
+/// A proxy on a free local port that sends `reply` to the first client
+/// and returns what the client sent until it hung up.
+fn fake_proxy(reply: &[u8]) -> (u16, JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let reply = reply.to_vec();
+    let server = std::thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.write_all(&reply).unwrap();
+        let mut received = Vec::new();
+        let _ = socket.read_to_end(&mut received);
+        received
+    });
+    (port, server)
+}
+
+fn proxy(protocol: ProxyProtocol, port: u16, user: Option<&str>) -> ProxyTransport {
+    ProxyTransport {
+        protocol,
+        host: "127.0.0.1".to_string(),
+        port,
+        username: user.map(str::to_string),
+        password: user.map(|_| "secret".to_string()),
+        password_ref: None,
+    }
+}
+
+#[test]
+fn base64_pads_to_whole_quads() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+}
+
+#[test]
+fn http_connect_sends_credentials_and_reads_the_status() {
+    let (port, server) = fake_proxy(b"HTTP/1.1 200 Connection established\r\nVia: test\r\n\r\n");
+    let stream = connect(&proxy(ProxyProtocol::Http, port, Some("me")), "::1", 22).unwrap();
+    drop(stream);
+    let request = String::from_utf8(server.join().unwrap()).unwrap();
+    assert!(request.starts_with("CONNECT [::1]:22 HTTP/1.1\r\n"));
+    assert!(request.contains(&format!(
+        "Proxy-Authorization: Basic {}\r\n",
+        base64(b"me:secret")
+    )));
+
+    let (port, _server) = fake_proxy(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n");
+    let refused = connect(&proxy(ProxyProtocol::Http, port, None), "example.com", 22);
+    assert!(refused.unwrap_err().contains("407"));
+}
+
+#[test]
+fn socks5_reply_is_read_up_to_the_bound_address() {
+    // Password login accepted, then a success with a domain name bound
+    // address, which has its length first
+    let mut reply = vec![5, 2, 1, 0, 5, 0, 0, 3, 4];
+    reply.extend_from_slice(b"host");
+    reply.extend_from_slice(&[0, 80]);
+    let (port, server) = fake_proxy(&reply);
+    let stream = connect(
+        &proxy(ProxyProtocol::Socks5, port, Some("me")),
+        "example.com",
+        22,
+    );
+    drop(stream.unwrap());
+    let sent = server.join().unwrap();
+    let mut expected = vec![5, 2, 0, 2, 1, 2];
+    expected.extend_from_slice(b"me");
+    expected.push(6);
+    expected.extend_from_slice(b"secret");
+    expected.extend_from_slice(&[5, 1, 0, 3, 11]);
+    expected.extend_from_slice(b"example.com");
+    expected.extend_from_slice(&[0, 22]);
+    assert_eq!(sent, expected);
+
+    // Connection refused by the target
+    let (port, _server) = fake_proxy(&[5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]);
+    let refused = connect(&proxy(ProxyProtocol::Socks5, port, None), "10.0.0.1", 22);
+    assert!(refused.unwrap_err().contains("error 5"));
+
+    let (port, _server) = fake_proxy(&[5, 0xff]);
+    assert!(connect(&proxy(ProxyProtocol::Socks5, port, None), "10.0.0.1", 22).is_err());
+}
+
+#[test]
+fn socks5_fields_over_255_bytes_are_refused() {
+    let long = "a".repeat(256);
+    let (port, _server) = fake_proxy(&[5, 0]);
+    let e = connect(&proxy(ProxyProtocol::Socks5, port, None), &long, 22).unwrap_err();
+    assert!(e.contains("too long"), "{}", e);
+
+    let (port, _server) = fake_proxy(&[5, 2]);
+    let e = connect(
+        &proxy(ProxyProtocol::Socks5, port, Some(&long)),
+        "example.com",
+        22,
+    )
+    .unwrap_err();
+    assert!(e.contains("longer than 255 bytes"), "{}", e);
+}
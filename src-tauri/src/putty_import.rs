@@ -1,7 +1,28 @@
-#[cfg(windows)]
-use crate::config::{AuthMethod, TunnelConfig, TunnelType};
-#[cfg(windows)]
-use uuid::Uuid;
+#[cfg(any(windows, test))]
+use crate::config::{self, AuthMethod, TunnelConfig, TunnelType};
+
+/// A saved PuTTY session, as far as importing it goes.
+#[cfg(any(windows, test))]
+struct Session {
+    /// Registry key name, URL-encoded ("My%20Server").
+    name: String,
+    host: String,
+    port: u32,
+    username: String,
+    key_path: String,
+    /// PuTTY's `PortForwardings` value.
+    port_forwardings: String,
+}
+
+/// One of a session's port forwardings.
+#[cfg(any(windows, test))]
+struct Forward {
+    tunnel_type: TunnelType,
+    bind_address: Option<String>,
+    local_port: u16,
+    remote_host: String,
+    remote_port: u16,
+}
 
 #[cfg(windows)]
 pub fn import_sessions() -> Result<Vec<TunnelConfig>, String> {
@@ -21,100 +42,140 @@ pub fn import_sessions() -> Result<Vec<TunnelConfig>, String> {
             Err(_) => continue,
         };
 
-        let host: String = session_key.get_value("HostName").unwrap_or_default();
-        let port: u32 = session_key.get_value("PortNumber").unwrap_or(22);
-        let username: String = session_key.get_value("UserName").unwrap_or_default();
-        let key_path: String = session_key.get_value("PublicKeyFile").unwrap_or_default();
-        let port_fwds: String = session_key.get_value("PortForwardings").unwrap_or_default();
-
-        if host.is_empty() || port_fwds.is_empty() {
-            continue;
-        }
-
-        // Parse PuTTY port forwarding format: "L8080=localhost:80,R9090=remote:90,D1080="
-        for fwd in port_fwds.split(',') {
-            let fwd = fwd.trim();
-            if fwd.is_empty() {
-                continue;
-            }
-
-            let (tunnel_type, rest) = match fwd.chars().next() {
-                Some('L') => (TunnelType::Local, &fwd[1..]),
-                Some('R') => (TunnelType::Remote, &fwd[1..]),
-                Some('D') => (TunnelType::Dynamic, &fwd[1..]),
-                _ => continue,
-            };
+        let session = Session {
+            host: session_key.get_value("HostName").unwrap_or_default(),
+            port: session_key.get_value("PortNumber").unwrap_or(22),
+            username: session_key.get_value("UserName").unwrap_or_default(),
+            key_path: session_key.get_value("PublicKeyFile").unwrap_or_default(),
+            port_forwardings: session_key.get_value("PortForwardings").unwrap_or_default(),
+            name: session_name,
+        };
+        tunnels.extend(session_tunnels(&session));
+    }
 
-            let parts: Vec<&str> = rest.splitn(2, '=').collect();
-            if parts.is_empty() {
-                continue;
-            }
+    Ok(tunnels)
+}
 
-            let local_port: u16 = parts[0].parse().unwrap_or(0);
-            if local_port == 0 {
-                continue;
-            }
+/// A tunnel for each of the session's port forwardings.
+#[cfg(any(windows, test))]
+fn session_tunnels(session: &Session) -> Vec<TunnelConfig> {
+    let Ok(port) = u16::try_from(session.port) else {
+        return Vec::new();
+    };
+    if session.host.is_empty() {
+        return Vec::new();
+    }
+    let decoded_name = urlencoding_decode(&session.name);
 
-            let (remote_host, remote_port) = if tunnel_type == TunnelType::Dynamic {
-                ("127.0.0.1".to_string(), 0u16)
-            } else if parts.len() > 1 {
-                let dest_parts: Vec<&str> = parts[1].rsplitn(2, ':').collect();
-                if dest_parts.len() == 2 {
-                    (
-                        dest_parts[1].to_string(),
-                        dest_parts[0].parse().unwrap_or(0),
-                    )
-                } else {
-                    continue;
-                }
-            } else {
-                continue;
-            };
-
-            let decoded_name = urlencoding_decode(&session_name);
-
-            tunnels.push(TunnelConfig {
-                id: Uuid::new_v4().to_string(),
-                name: format!("{} ({}:{})", decoded_name, remote_host, remote_port),
-                host: host.clone(),
-                port: port as u16,
-                username: username.clone(),
-                auth_method: if key_path.is_empty() {
+    session
+        .port_forwardings
+        .split(',')
+        .filter_map(parse_forward)
+        .map(|fwd| {
+            let name = format!(
+                "{} ({}:{})",
+                decoded_name,
+                config::bracket_host(&fwd.remote_host),
+                fwd.remote_port
+            );
+            TunnelConfig {
+                port,
+                auth_method: if session.key_path.is_empty() {
                     AuthMethod::Password
                 } else {
                     AuthMethod::Key
                 },
-                key_path: if key_path.is_empty() {
+                key_path: if session.key_path.is_empty() {
                     None
                 } else {
-                    Some(key_path.clone())
+                    Some(session.key_path.clone())
                 },
-                tunnel_type,
-                local_port,
-                remote_host,
-                remote_port,
-                auto_connect: false,
-                enabled: true,
-            });
-        }
+                // Keep the session's proxy, host keys and ciphers
+                putty_session: Some(decoded_name.clone()),
+                tunnel_type: fwd.tunnel_type,
+                local_port: fwd.local_port,
+                bind_address: fwd.bind_address,
+                remote_host: fwd.remote_host,
+                remote_port: fwd.remote_port,
+                ..TunnelConfig::new(name, session.host.clone(), session.username.clone())
+            }
+        })
+        .collect()
+}
+
+/// Parses one entry of PuTTY's port forwarding list: "L8080=localhost:80",
+/// "R9090=remote:90" or "D1080=", with an optional 4/6 address family
+/// prefix and source address ("6L[::1]:8080=[::1]:80").
+#[cfg(any(windows, test))]
+fn parse_forward(fwd: &str) -> Option<Forward> {
+    let fwd = fwd.trim().trim_start_matches(['4', '6']);
+    let (tunnel_type, rest) = match fwd.chars().next()? {
+        'L' => (TunnelType::Local, &fwd[1..]),
+        'R' => (TunnelType::Remote, &fwd[1..]),
+        'D' => (TunnelType::Dynamic, &fwd[1..]),
+        _ => return None,
+    };
+
+    let (source, destination) = match rest.split_once('=') {
+        Some((source, destination)) => (source, Some(destination)),
+        None => (rest, None),
+    };
+
+    // The port comes last in the source, after any address
+    let (bind_address, local_port) = match source.rsplit_once(':') {
+        Some((address, port)) => (
+            Some(config::unbracket_host(address).to_string()),
+            port.parse().ok()?,
+        ),
+        None => (None, source.parse().ok()?),
+    };
+    if local_port == 0 {
+        return None;
     }
 
-    Ok(tunnels)
+    let (remote_host, remote_port) = if tunnel_type == TunnelType::Dynamic {
+        ("127.0.0.1".to_string(), 0)
+    } else {
+        let (address, port) = destination?.rsplit_once(':')?;
+        (
+            config::unbracket_host(address).to_string(),
+            port.parse().ok()?,
+        )
+    };
+
+    Some(Forward {
+        tunnel_type,
+        bind_address,
+        local_port,
+        remote_host,
+        remote_port,
+    })
 }
 
-#[cfg(windows)]
+/// Decodes a session name as PuTTY stores it, `%XX` escaping its bytes.
+#[cfg(any(windows, test))]
 fn urlencoding_decode(s: &str) -> String {
-    let mut result = String::new();
-    let mut chars = s.chars();
-    while let Some(c) = chars.next() {
-        if c == '%' {
-            let hex: String = chars.by_ref().take(2).collect();
-            if let Ok(byte) = u8::from_str_radix(&hex, 16) {
-                result.push(byte as char);
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = (b == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(b);
+                rest = tail;
             }
-        } else {
-            result.push(c);
         }
     }
-    result
+    String::from_utf8_lossy(&bytes).into_owned()
 }
+
+#[cfg(test)]
+mod tests;
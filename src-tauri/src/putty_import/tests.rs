@@ -0,0 +1,80 @@
+use super::*;
+
+fn session(port_forwardings: &str) -> Session {
+    Session {
+        name: "My%20Server".to_string(),
+        host: "bastion.invalid".to_string(),
+        port: 2222,
+        username: "tester".to_string(),
+        key_path: String::new(),
+        port_forwardings: port_forwardings.to_string(),
+    }
+}
+
+#[test]
+fn each_forward_of_a_session_becomes_a_tunnel() {
+    let tunnels = session_tunnels(&session("L8080=localhost:80,R9090=remote:90,D1080=,X1=a:1"));
+    let forwards: Vec<_> = tunnels
+        .iter()
+        .map(|t| {
+            (
+                t.tunnel_type.clone(),
+                t.local_port,
+                t.remote_host.as_str(),
+                t.remote_port,
+            )
+        })
+        .collect();
+    assert_eq!(
+        forwards,
+        [
+            (TunnelType::Local, 8080, "localhost", 80),
+            (TunnelType::Remote, 9090, "remote", 90),
+            (TunnelType::Dynamic, 1080, "127.0.0.1", 0),
+        ]
+    );
+
+    let tunnel = &tunnels[0];
+    assert_eq!(tunnel.name, "My Server (localhost:80)");
+    assert_eq!(
+        (tunnel.host.as_str(), tunnel.port),
+        ("bastion.invalid", 2222)
+    );
+    assert_eq!(tunnel.auth_method, AuthMethod::Password);
+    assert_eq!(tunnel.putty_session.as_deref(), Some("My Server"));
+    assert!(!tunnel.id.is_empty() && tunnel.id != tunnels[1].id);
+}
+
+#[test]
+fn ipv6_forwards_keep_their_bind_address_and_target() {
+    let tunnels = session_tunnels(&session(
+        "6L[::1]:8080=[2001:db8::5]:80,4L127.0.0.2:5432=db:5432",
+    ));
+    assert_eq!(tunnels.len(), 2);
+    assert_eq!(tunnels[0].bind_address.as_deref(), Some("::1"));
+    assert_eq!(tunnels[0].local_port, 8080);
+    assert_eq!(
+        (tunnels[0].remote_host.as_str(), tunnels[0].remote_port),
+        ("2001:db8::5", 80)
+    );
+    assert_eq!(tunnels[0].name, "My Server ([2001:db8::5]:80)");
+    assert_eq!(tunnels[1].bind_address.as_deref(), Some("127.0.0.2"));
+}
+
+#[test]
+fn broken_forwards_and_sessions_are_skipped() {
+    assert!(session_tunnels(&session("L0=a:1,Lx=a:1,L8080,L8080=nowhere,")).is_empty());
+
+    let mut no_host = session("L8080=localhost:80");
+    no_host.host.clear();
+    assert!(session_tunnels(&no_host).is_empty());
+    let mut bad_port = session("L8080=localhost:80");
+    bad_port.port = 70000;
+    assert!(session_tunnels(&bad_port).is_empty());
+}
+
+#[test]
+fn session_names_are_percent_decoded() {
+    assert_eq!(urlencoding_decode("db%20%C3%A9t%C3%A9"), "db été");
+    assert_eq!(urlencoding_decode("100%"), "100%");
+}
@@ -0,0 +1,113 @@
+use log::warn;
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// How long a client connection is held open waiting for the tunnel to come
+/// back before it's closed.
+const HOLD: Duration = Duration::from_secs(30);
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Owns a tunnel's local port across reconnects. The SSH client listens on
+/// a private loopback port instead, and connections arriving while it's
+/// being restarted are held until it's back rather than refused.
+pub struct Relay {
+    /// Port the current client listens on; 0 before the first start.
+    upstream: Arc<AtomicU16>,
+    activity: Arc<Activity>,
+    accept: JoinHandle<()>,
+}
+
+/// Connections through the relay, to tell when the tunnel sits unused.
+struct Activity {
+    open: AtomicUsize,
+    /// When the last connection closed, or the relay was bound.
+    last: Mutex<Instant>,
+}
+
+impl Relay {
+    pub async fn bind(host: &str, port: u16) -> Result<Self, String> {
+        let listener = TcpListener::bind((host, port))
+            .await
+            .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+        let upstream = Arc::new(AtomicU16::new(0));
+        let activity = Arc::new(Activity {
+            open: AtomicUsize::new(0),
+            last: Mutex::new(Instant::now()),
+        });
+        let accept = tokio::spawn(accept_loop(listener, upstream.clone(), activity.clone()));
+        Ok(Self {
+            upstream,
+            activity,
+            accept,
+        })
+    }
+
+    /// Points new connections at the client listening on `port`.
+    pub fn set_upstream(&self, port: u16) {
+        self.upstream.store(port, Ordering::Relaxed);
+    }
+
+    /// How long no connection has been open.
+    pub fn idle_for(&self) -> Duration {
+        if self.activity.open.load(Ordering::Relaxed) > 0 {
+            return Duration::ZERO;
+        }
+        self.activity.last.lock().unwrap().elapsed()
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+async fn accept_loop(listener: TcpListener, upstream: Arc<AtomicU16>, activity: Arc<Activity>) {
+    loop {
+        match listener.accept().await {
+            Ok((client, _)) => {
+                let upstream = upstream.clone();
+                let activity = activity.clone();
+                activity.open.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    forward(client, upstream).await;
+                    *activity.last.lock().unwrap() = Instant::now();
+                    activity.open.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+            Err(e) => {
+                warn!("Relay accept failed: {}", e);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+async fn forward(mut client: TcpStream, upstream: Arc<AtomicU16>) {
+    let deadline = Instant::now() + HOLD;
+    let mut server = loop {
+        let port = upstream.load(Ordering::Relaxed);
+        if port != 0 {
+            if let Ok(server) = TcpStream::connect(("127.0.0.1", port)).await {
+                break server;
+            }
+        }
+        if Instant::now() >= deadline {
+            return;
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    };
+    let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+}
+
+/// A loopback port that's free right now, for the client to listen on.
+pub fn free_port() -> Result<u16, String> {
+    std::net::TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|l| l.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|e| format!("Failed to reserve a relay port: {}", e))
+}
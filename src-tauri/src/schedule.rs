@@ -0,0 +1,216 @@
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// How far back a cron schedule looks for its last start or stop.
+const CRON_LOOKBACK_MINUTES: i64 = 32 * 24 * 60;
+
+/// When a tunnel should be up, in local time. Outside its schedule a tunnel
+/// is stopped and not reconnected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Schedule {
+    /// Up during any of the windows.
+    Weekly { windows: Vec<WeeklyWindow> },
+    /// Up from each `start` firing until the next `stop` firing. Both are
+    /// five-field cron expressions (minute hour day-of-month month
+    /// day-of-week).
+    Cron { start: String, stop: String },
+}
+
+/// `start`–`end` (`HH:MM`) on each of `days` (`mon`…`sun`). A window ending
+/// at or before its start runs past midnight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeeklyWindow {
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Schedule::Weekly { windows } => {
+                for window in windows {
+                    window.parse()?;
+                }
+                Ok(())
+            }
+            Schedule::Cron { start, stop } => {
+                CronExpr::parse(start)?;
+                CronExpr::parse(stop)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the tunnel should be up at `now`. Invalid schedules are
+    /// never active.
+    pub fn is_active(&self, now: NaiveDateTime) -> bool {
+        match self {
+            Schedule::Weekly { windows } => windows
+                .iter()
+                .filter_map(|w| w.parse().ok())
+                .any(|w| w.contains(now)),
+            Schedule::Cron { start, stop } => {
+                let (Ok(start), Ok(stop)) = (CronExpr::parse(start), CronExpr::parse(stop)) else {
+                    return false;
+                };
+                // Walk back to whichever fired last
+                let mut t = now
+                    .with_second(0)
+                    .and_then(|t| t.with_nanosecond(0))
+                    .unwrap_or(now);
+                for _ in 0..CRON_LOOKBACK_MINUTES {
+                    if stop.matches(t) {
+                        return false;
+                    }
+                    if start.matches(t) {
+                        return true;
+                    }
+                    t -= Duration::minutes(1);
+                }
+                false
+            }
+        }
+    }
+}
+
+struct ParsedWindow {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl WeeklyWindow {
+    fn parse(&self) -> Result<ParsedWindow, String> {
+        let days = self
+            .days
+            .iter()
+            .map(|d| {
+                d.parse::<Weekday>()
+                    .map_err(|_| format!("Invalid day '{}' in schedule", d))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let time = |s: &str| {
+            NaiveTime::parse_from_str(s, "%H:%M")
+                .map_err(|_| format!("Invalid time '{}' in schedule (expected HH:MM)", s))
+        };
+        Ok(ParsedWindow {
+            days,
+            start: time(&self.start)?,
+            end: time(&self.end)?,
+        })
+    }
+}
+
+impl ParsedWindow {
+    fn contains(&self, now: NaiveDateTime) -> bool {
+        let time = now.time();
+        let today = now.weekday();
+        let yesterday = today.pred();
+        if self.end > self.start {
+            self.days.contains(&today) && time >= self.start && time < self.end
+        } else {
+            // Overnight: the evening part today, or the morning part of
+            // yesterday's window
+            (self.days.contains(&today) && time >= self.start)
+                || (self.days.contains(&yesterday) && time < self.end)
+        }
+    }
+}
+
+/// A parsed five-field cron expression; each field is a bit set of the
+/// values it matches.
+struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day-of-month and day-of-week were both restricted, in which case
+    /// either may match (as in classic cron).
+    either_day: bool,
+}
+
+impl CronExpr {
+    fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("Cron expression '{}' must have 5 fields", expr));
+        };
+        let field = |spec: &str, min: u32, max: u32| {
+            parse_field(spec, min, max)
+                .map_err(|e| format!("Invalid cron expression '{}': {}", expr, e))
+        };
+        let mut weekdays = field(weekday, 0, 7)?;
+        // 7 is Sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            weekdays,
+            either_day: day != "*" && weekday != "*",
+        })
+    }
+
+    fn matches(&self, t: NaiveDateTime) -> bool {
+        let has = |set: u64, value: u32| set & (1 << value) != 0;
+        let day = has(self.days, t.day());
+        let weekday = has(self.weekdays, t.weekday().num_days_from_sunday());
+        let day_matches = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        has(self.minutes, t.minute())
+            && has(self.hours, t.hour())
+            && has(self.months, t.month())
+            && day_matches
+    }
+}
+
+/// `*`, `5`, `1-5`, `*/15`, `0-30/10` and comma-separated lists of those.
+fn parse_field(spec: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut set = 0u64;
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("bad step '{}'", step))?,
+            ),
+            None => (part, 1),
+        };
+        let (low, high) = if range == "*" {
+            (min, max)
+        } else if let Some((low, high)) = range.split_once('-') {
+            (parse_value(low, min, max)?, parse_value(high, min, max)?)
+        } else {
+            let value = parse_value(range, min, max)?;
+            (value, value)
+        };
+        if low > high {
+            return Err(format!("bad range '{}'", range));
+        }
+        for value in (low..=high).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn parse_value(s: &str, min: u32, max: u32) -> Result<u32, String> {
+    s.parse::<u32>()
+        .ok()
+        .filter(|v| (min..=max).contains(v))
+        .ok_or_else(|| format!("'{}' is not between {} and {}", s, min, max))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,78 @@
+use super::*;
+use chrono::NaiveDate;
+
+/// 2024-01-01 was a Monday.
+fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2024, 1, day)
+        .unwrap()
+        .and_hms_opt(hour, minute, 0)
+        .unwrap()
+}
+
+fn weekly(days: &[&str], start: &str, end: &str) -> Schedule {
+    Schedule::Weekly {
+        windows: vec![WeeklyWindow {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+        }],
+    }
+}
+
+#[test]
+fn office_hours_window() {
+    let schedule = weekly(&["mon", "tue", "wed", "thu", "fri"], "09:00", "18:00");
+    assert!(schedule.validate().is_ok());
+    assert!(schedule.is_active(at(1, 9, 0)));
+    assert!(schedule.is_active(at(5, 17, 59)));
+    assert!(!schedule.is_active(at(1, 18, 0)));
+    assert!(!schedule.is_active(at(1, 8, 59)));
+    // Saturday
+    assert!(!schedule.is_active(at(6, 12, 0)));
+}
+
+#[test]
+fn overnight_window_spills_into_next_day() {
+    let schedule = weekly(&["fri"], "22:00", "02:00");
+    assert!(schedule.is_active(at(5, 23, 0)));
+    assert!(schedule.is_active(at(6, 1, 30)));
+    assert!(!schedule.is_active(at(6, 2, 0)));
+    assert!(!schedule.is_active(at(4, 1, 0)));
+}
+
+#[test]
+fn cron_window_runs_from_start_to_stop() {
+    let schedule = Schedule::Cron {
+        start: "30 8 * * 1-5".to_string(),
+        stop: "0 19 * * 1-5".to_string(),
+    };
+    assert!(schedule.validate().is_ok());
+    assert!(schedule.is_active(at(1, 8, 30)));
+    assert!(schedule.is_active(at(1, 18, 59)));
+    assert!(!schedule.is_active(at(1, 19, 0)));
+    // Stopped Friday evening, not restarted over the weekend
+    assert!(!schedule.is_active(at(7, 12, 0)));
+    assert!(schedule.is_active(at(8, 9, 0)));
+}
+
+#[test]
+fn cron_fields_support_lists_ranges_and_steps() {
+    let expr = CronExpr::parse("*/15 9-17 * * 1,3,5").unwrap();
+    assert!(expr.matches(at(1, 9, 45)));
+    assert!(!expr.matches(at(1, 9, 50)));
+    assert!(!expr.matches(at(2, 9, 45)));
+    assert!(CronExpr::parse("0 7 * * 7").unwrap().matches(at(7, 7, 0)));
+}
+
+#[test]
+fn invalid_schedules_are_rejected() {
+    assert!(weekly(&["someday"], "09:00", "18:00").validate().is_err());
+    assert!(weekly(&["mon"], "9am", "18:00").validate().is_err());
+    let cron = |start: &str| Schedule::Cron {
+        start: start.to_string(),
+        stop: "0 18 * * *".to_string(),
+    };
+    assert!(cron("0 9 * *").validate().is_err());
+    assert!(cron("60 9 * * *").validate().is_err());
+    assert!(cron("*/0 9 * * *").validate().is_err());
+}
@@ -0,0 +1,165 @@
+//! Passwords kept in the OS credential store (Windows Credential Manager,
+//! macOS Keychain, Secret Service on Linux) instead of config.json, which
+//! only holds the id of each one's entry (`passwordRef`). Configs are
+//! filled in from the store when loaded, so the rest of the app sees the
+//! passwords as before.
+
+use crate::config::{AppConfig, AuthMethod, Transport, TunnelConfig};
+use keyring::Entry;
+use log::warn;
+use serde_json::Value;
+
+/// Service name of every entry OpenTunnel stores.
+const SERVICE: &str = "OpenTunnel";
+
+fn password_id(tunnel_id: &str) -> String {
+    format!("{}/password", tunnel_id)
+}
+
+fn proxy_id(tunnel_id: &str) -> String {
+    format!("{}/proxy", tunnel_id)
+}
+
+fn entry(id: &str) -> Result<Entry, String> {
+    Entry::new(SERVICE, id).map_err(|e| format!("Credential manager error: {}", e))
+}
+
+fn fetch(id: &str) -> Result<Option<String>, String> {
+    match entry(id)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!(
+            "Failed to read '{}' from the credential manager: {}",
+            id, e
+        )),
+    }
+}
+
+/// Stores `secret` as `id`, unless it's already there.
+fn store(id: &str, secret: &str) -> Result<(), String> {
+    if fetch(id)?.as_deref() == Some(secret) {
+        return Ok(());
+    }
+    entry(id)?.set_password(secret).map_err(|e| {
+        format!(
+            "Failed to store a password in the credential manager: {}",
+            e
+        )
+    })
+}
+
+fn delete(id: &str) {
+    if let Ok(entry) = entry(id) {
+        match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(e) => warn!(
+                "Failed to delete '{}' from the credential manager: {}",
+                id, e
+            ),
+        }
+    }
+}
+
+/// Moves the passwords of `config`, as about to be written to disk, into
+/// the store, leaving references to them. One the store can't take stays
+/// in the clear, and the first such failure is returned.
+pub fn stash(config: &mut AppConfig) -> Option<String> {
+    let mut failed = None;
+    // The reference to set and the password to keep in the clear
+    let mut stash_one = |id: String, password: String| match store(&id, &password) {
+        Ok(()) => (Some(id), None),
+        Err(e) => {
+            failed.get_or_insert(e);
+            (None, Some(password))
+        }
+    };
+    for tunnel in &mut config.tunnels {
+        match tunnel.password.take().filter(|p| !p.is_empty()) {
+            Some(password) => {
+                (tunnel.password_ref, tunnel.password) =
+                    stash_one(password_id(&tunnel.id), password);
+            }
+            // An empty password may just have failed to load; only one
+            // that's no longer used goes
+            None if tunnel.auth_method != AuthMethod::Password => {
+                if let Some(id) = tunnel.password_ref.take() {
+                    delete(&id);
+                }
+            }
+            None => {}
+        }
+        if let Some(Transport::Proxy(ref mut proxy)) = tunnel.transport {
+            if let Some(password) = proxy.password.take().filter(|p| !p.is_empty()) {
+                (proxy.password_ref, proxy.password) = stash_one(proxy_id(&tunnel.id), password);
+            }
+        }
+    }
+    failed
+}
+
+/// Drops the passwords in the clear from `config`, the JSON of a config
+/// file kept aside such as a backup, leaving references to the store,
+/// which has the current ones. Returns whether there were any.
+pub fn scrub(config: &mut Value) -> bool {
+    let Some(tunnels) = config.get_mut("tunnels").and_then(Value::as_array_mut) else {
+        return false;
+    };
+    let mut scrubbed = false;
+    for tunnel in tunnels {
+        let id = tunnel.get("id").and_then(Value::as_str).map(str::to_string);
+        let Some(fields) = tunnel.as_object_mut() else {
+            continue;
+        };
+        if fields.remove("password").is_some() {
+            scrubbed = true;
+            if let Some(ref id) = id {
+                fields.insert("passwordRef".to_string(), password_id(id).into());
+            }
+        }
+        if let Some(proxy) = fields.get_mut("transport").and_then(Value::as_object_mut) {
+            if proxy.remove("password").is_some() {
+                scrubbed = true;
+                if let Some(ref id) = id {
+                    proxy.insert("passwordRef".to_string(), proxy_id(id).into());
+                }
+            }
+        }
+    }
+    scrubbed
+}
+
+/// Fills in the passwords `config` references from the store.
+pub fn fill(config: &mut AppConfig) {
+    let load = |id: &str| match fetch(id) {
+        Ok(secret) => secret,
+        Err(e) => {
+            warn!("{}", e);
+            None
+        }
+    };
+    for tunnel in &mut config.tunnels {
+        if let Some(ref id) = tunnel.password_ref {
+            tunnel.password = tunnel.password.take().or_else(|| load(id));
+        }
+        if let Some(Transport::Proxy(ref mut proxy)) = tunnel.transport {
+            if let Some(ref id) = proxy.password_ref {
+                proxy.password = proxy.password.take().or_else(|| load(id));
+            }
+        }
+    }
+}
+
+/// Deletes the stored passwords of a deleted tunnel.
+pub fn forget(tunnel: &TunnelConfig) {
+    delete(&password_id(&tunnel.id));
+    delete(&proxy_id(&tunnel.id));
+}
+
+/// Whether `config` still has passwords in the clear, from before they
+/// were kept in the store.
+pub fn any_in_clear(config: &AppConfig) -> bool {
+    config.tunnels.iter().any(|t| {
+        (t.password.is_some() && t.password_ref.is_none())
+            || matches!(&t.transport, Some(Transport::Proxy(p)) if p.password.is_some() && p.password_ref.is_none())
+    })
+}
@@ -0,0 +1,54 @@
+use crate::instances;
+use crate::logs::LogBus;
+use crate::monitor::{self, Monitor};
+use crate::tunnel::{self, TunnelManager};
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tauri::Manager;
+use tokio::task::JoinSet;
+
+/// How long tunnels get to stop before they are killed outright.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Marks the app as shutting down. Returns `false` if a shutdown is already
+/// under way, i.e. this exit request is the one we issue once it's done.
+pub fn begin() -> bool {
+    !SHUTTING_DOWN.swap(true, Ordering::SeqCst)
+}
+
+/// Stops the monitor and every tunnel, then flushes pending logs and
+/// releases the instance lease.
+pub async fn run(app: &tauri::AppHandle) {
+    info!("Shutting down");
+    let monitor = app.state::<Monitor>();
+    monitor.lock().await.running = false;
+    monitor::save(&monitor).await;
+
+    let manager = app.state::<TunnelManager>().inner().clone();
+    let mut stops = JoinSet::new();
+    for id in manager.ids() {
+        let manager = manager.clone();
+        let app = app.clone();
+        stops.spawn(async move {
+            let _ = tunnel::stop_tunnel(&manager, &id, &app).await;
+        });
+    }
+
+    let all_stopped = async { while stops.join_next().await.is_some() {} };
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, all_stopped)
+        .await
+        .is_err()
+    {
+        // Dropping the handles makes the supervisors kill their process
+        warn!("Tunnels didn't stop in time, killing them");
+        for id in manager.ids() {
+            manager.remove(&id);
+        }
+    }
+
+    app.state::<LogBus>().flush(app);
+    instances::release();
+}
@@ -0,0 +1,204 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Runtime};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TunnelStatus {
+    Stopped,
+    Starting,
+    /// The client is up as far as can be told from outside: its local port
+    /// accepts connections or, for remote forwards, which have none, it
+    /// has stayed up for a 2s settle delay (`STARTUP_SETTLE`). Health
+    /// checks find out whether the forward actually works.
+    Running,
+    Reconnecting,
+    Error,
+}
+
+impl TunnelStatus {
+    /// Lifecycle of a tunnel:
+    ///
+    /// ```text
+    /// Stopped → Starting → Running → Error → Reconnecting → Starting …
+    /// ```
+    ///
+    /// Starting may fail straight to Error, and any state can be stopped.
+    /// Reaching Running ends a reconnect, see [`TunnelState::transition`].
+    pub fn can_transition_to(self, next: TunnelStatus) -> bool {
+        use TunnelStatus::*;
+        matches!(
+            (self, next),
+            (_, Stopped)
+                | (Stopped, Starting)
+                | (Starting, Running)
+                | (Starting, Error)
+                | (Running, Error)
+                | (Error, Starting)
+                | (Error, Reconnecting)
+                | (Reconnecting, Reconnecting)
+                | (Reconnecting, Starting)
+                | (Reconnecting, Error)
+        )
+    }
+}
+
+/// Why a tunnel failed, as far as its error message tells.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    /// The server rejected the credentials.
+    Auth,
+    /// The server's host key is unknown or doesn't match.
+    HostKey,
+    Refused,
+    Dns,
+    Timeout,
+    /// Connected, but the port forward couldn't be set up.
+    Forward,
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether reconnecting could fix it without the user stepping in.
+    pub fn retryable(self) -> bool {
+        !matches!(self, ErrorKind::Auth | ErrorKind::HostKey)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelState {
+    pub id: String,
+    pub status: TunnelStatus,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    #[serde(rename = "lastErrorKind", default)]
+    pub last_error_kind: Option<ErrorKind>,
+    #[serde(rename = "startedAt")]
+    pub started_at: Option<String>,
+    /// Reconnect attempt under way, or about to be.
+    #[serde(rename = "reconnectCount")]
+    pub reconnect_count: u32,
+    /// When the next reconnect attempt is due, while one is scheduled.
+    #[serde(rename = "nextRetryAt", default)]
+    pub next_retry_at: Option<String>,
+    /// Port the tunnel listens on locally, once started.
+    #[serde(rename = "localPort", default)]
+    pub local_port: Option<u16>,
+    /// Client process, while the tunnel is tracked.
+    #[serde(default)]
+    pub pid: Option<u32>,
+    /// Seconds since the tunnel started, while it's running.
+    #[serde(rename = "uptimeSec", default)]
+    pub uptime_sec: Option<u64>,
+    /// `address:port` the tunnel accepts connections on, for tunnels that
+    /// listen locally.
+    #[serde(rename = "localEndpoint", default)]
+    pub local_endpoint: Option<String>,
+    /// `user@host:port` of the SSH server, for SSH tunnels.
+    #[serde(rename = "sshTarget", default)]
+    pub ssh_target: Option<String>,
+}
+
+/// A status change, emitted as `tunnel-transition`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Transition {
+    pub id: String,
+    pub from: TunnelStatus,
+    pub to: TunnelStatus,
+    pub reason: Option<String>,
+    pub at: String,
+}
+
+impl TunnelState {
+    /// State of a tunnel that isn't tracked by the manager (yet).
+    pub fn untracked(id: &str, status: TunnelStatus, last_error: Option<String>) -> Self {
+        Self {
+            id: id.to_string(),
+            status,
+            last_error,
+            last_error_kind: None,
+            started_at: None,
+            reconnect_count: 0,
+            next_retry_at: None,
+            local_port: None,
+            pid: None,
+            uptime_sec: None,
+            local_endpoint: None,
+            ssh_target: None,
+        }
+    }
+
+    /// A copy with `uptime_sec` brought up to date.
+    pub fn snapshot(&self) -> Self {
+        let mut state = self.clone();
+        state.uptime_sec = self
+            .started_at
+            .as_deref()
+            .filter(|_| self.status == TunnelStatus::Running)
+            .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+            .map(|at| (Utc::now() - at.with_timezone(&Utc)).num_seconds().max(0) as u64);
+        state
+    }
+
+    /// Moves to `to` if the lifecycle allows it. The reason becomes
+    /// `last_error` when entering Error; its kind is up to the caller.
+    /// Running resets `reconnect_count`, the tunnel being back.
+    pub fn transition(
+        &mut self,
+        to: TunnelStatus,
+        reason: Option<String>,
+    ) -> Result<Transition, String> {
+        let from = self.status;
+        if !from.can_transition_to(to) {
+            return Err(format!(
+                "Invalid transition {:?} -> {:?} for tunnel {}",
+                from, to, self.id
+            ));
+        }
+
+        self.status = to;
+        if to != TunnelStatus::Reconnecting {
+            self.next_retry_at = None;
+        }
+        match to {
+            TunnelStatus::Error => self.last_error = reason.clone(),
+            TunnelStatus::Starting => {
+                self.last_error = None;
+                self.last_error_kind = None;
+            }
+            TunnelStatus::Running => {
+                self.last_error = None;
+                self.last_error_kind = None;
+                self.reconnect_count = 0;
+            }
+            _ => {}
+        }
+        Ok(Transition {
+            id: self.id.clone(),
+            from,
+            to,
+            reason,
+            at: Utc::now().to_rfc3339(),
+        })
+    }
+}
+
+/// Tells the frontend about one tunnel's new state. Clients resync the full
+/// list through `get_tunnel_states`.
+pub fn emit_state<R: Runtime>(app_handle: &AppHandle<R>, state: &TunnelState) {
+    let _ = app_handle.emit("tunnel-status", state);
+}
+
+/// Emits a transition and the state it produced.
+pub fn emit_transition<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    transition: &Transition,
+    state: &TunnelState,
+) {
+    let _ = app_handle.emit("tunnel-transition", transition);
+    emit_state(app_handle, state);
+}
+
+#[cfg(test)]
+mod tests;
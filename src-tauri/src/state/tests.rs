@@ -0,0 +1,56 @@
+use super::*;
+use TunnelStatus::*;
+
+const ALL: [TunnelStatus; 5] = [Stopped, Starting, Running, Reconnecting, Error];
+
+#[test]
+fn lifecycle_allows_only_listed_transitions() {
+    let allowed = [
+        (Stopped, Starting),
+        (Starting, Running),
+        (Starting, Error),
+        (Running, Error),
+        (Error, Starting),
+        (Error, Reconnecting),
+        (Reconnecting, Reconnecting),
+        (Reconnecting, Starting),
+        (Reconnecting, Error),
+    ];
+    for from in ALL {
+        for to in ALL {
+            let expected = to == Stopped || allowed.contains(&(from, to));
+            assert_eq!(
+                from.can_transition_to(to),
+                expected,
+                "{:?} -> {:?}",
+                from,
+                to
+            );
+        }
+    }
+}
+
+#[test]
+fn transitions_keep_error_and_attempt_until_back_up() {
+    let mut state = TunnelState::untracked("db", Running, None);
+    state.reconnect_count = 2;
+
+    let transition = state
+        .transition(Error, Some("Connection refused".to_string()))
+        .unwrap();
+    assert_eq!((transition.from, transition.to), (Running, Error));
+    assert_eq!(state.last_error.as_deref(), Some("Connection refused"));
+
+    state.transition(Reconnecting, None).unwrap();
+    state.next_retry_at = Some(Utc::now().to_rfc3339());
+    state.transition(Starting, None).unwrap();
+    assert_eq!(state.last_error, None);
+    assert_eq!(state.next_retry_at, None);
+    assert_eq!(state.reconnect_count, 2);
+
+    assert!(state.transition(Reconnecting, None).is_err());
+    assert_eq!(state.status, Starting);
+
+    state.transition(Running, None).unwrap();
+    assert_eq!(state.reconnect_count, 0);
+}
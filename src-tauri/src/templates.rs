@@ -0,0 +1,107 @@
+//! Tunnel templates: presets for common services (RDP, databases, VNC,
+//! web) plus the user's own, kept in the config's `templates`, that new
+//! tunnels start from with their ports and name filled in.
+
+use crate::config::{AppConfig, TunnelConfig, TunnelType};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelTemplate {
+    pub id: String,
+    /// Service name; tunnels made from it are named after it.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub tunnel_type: TunnelType,
+    /// Port forwarded locally; 0 picks a free one at start.
+    #[serde(rename = "localPort", default)]
+    pub local_port: u16,
+    #[serde(rename = "remoteHost", default = "default_remote_host")]
+    pub remote_host: String,
+    #[serde(rename = "remotePort", default)]
+    pub remote_port: u16,
+    /// One of the presets rather than the user's.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub builtin: bool,
+}
+
+impl TunnelTemplate {
+    /// Checks that tunnels made from it can be started: a name, and the
+    /// ports its type forwards.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("A template needs a name".to_string());
+        }
+        match self.tunnel_type {
+            TunnelType::Local if self.remote_host.trim().is_empty() || self.remote_port == 0 => {
+                Err("A local forward needs a remote host and port".to_string())
+            }
+            TunnelType::Remote if self.local_port == 0 => {
+                Err("A remote forward needs the local port it forwards to".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn default_remote_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+/// Presets: id, name, type, local and remote port. Local ports are moved
+/// off the service's own where the machine may well be running it.
+const PRESETS: &[(&str, &str, TunnelType, u16, u16)] = &[
+    ("rdp", "Remote Desktop", TunnelType::Local, 13389, 3389),
+    ("ssh", "SSH", TunnelType::Local, 2222, 22),
+    ("mysql", "MySQL", TunnelType::Local, 13306, 3306),
+    ("postgres", "PostgreSQL", TunnelType::Local, 15432, 5432),
+    ("mssql", "SQL Server", TunnelType::Local, 11433, 1433),
+    ("mongodb", "MongoDB", TunnelType::Local, 27018, 27017),
+    ("redis", "Redis", TunnelType::Local, 16379, 6379),
+    ("vnc", "VNC", TunnelType::Local, 15900, 5900),
+    ("http", "HTTP", TunnelType::Local, 8080, 8080),
+    ("https", "HTTPS", TunnelType::Local, 8443, 443),
+    ("socks", "SOCKS Proxy", TunnelType::Dynamic, 1080, 0),
+];
+
+/// The presets, then the user's templates.
+pub fn all(config: &AppConfig) -> Vec<TunnelTemplate> {
+    PRESETS
+        .iter()
+        .map(
+            |(id, name, tunnel_type, local_port, remote_port)| TunnelTemplate {
+                id: id.to_string(),
+                name: name.to_string(),
+                tunnel_type: tunnel_type.clone(),
+                local_port: *local_port,
+                remote_host: default_remote_host(),
+                remote_port: *remote_port,
+                builtin: true,
+            },
+        )
+        .chain(config.templates.iter().cloned())
+        .collect()
+}
+
+pub fn is_preset(id: &str) -> bool {
+    PRESETS.iter().any(|(preset, ..)| *preset == id)
+}
+
+/// A new tunnel from `template`, named "<service> on <host>" and logging
+/// in like `server` if given. It has no id until it's added.
+pub fn instantiate(template: &TunnelTemplate, server: Option<&TunnelConfig>) -> TunnelConfig {
+    let mut tunnel = match server {
+        Some(server) => {
+            TunnelConfig::on_server_of(format!("{} on {}", template.name, server.host), server)
+        }
+        None => TunnelConfig::new(template.name.clone(), String::new(), String::new()),
+    };
+    tunnel.id = String::new();
+    tunnel.tunnel_type = template.tunnel_type.clone();
+    tunnel.local_port = template.local_port;
+    tunnel.remote_host = template.remote_host.clone();
+    tunnel.remote_port = template.remote_port;
+    tunnel
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,52 @@
+use super::*;
+
+fn preset(id: &str) -> TunnelTemplate {
+    all(&AppConfig::default())
+        .into_iter()
+        .find(|t| t.id == id)
+        .unwrap()
+}
+
+#[test]
+fn templates_fill_in_ports_name_and_login() {
+    let mut server = TunnelConfig::new(
+        "db server".to_string(),
+        "bastion.invalid".to_string(),
+        "tester".to_string(),
+    );
+    server.port = 2200;
+    server.jump_hosts = vec!["edge.invalid".to_string()];
+    server.extra_args = vec!["-4".to_string()];
+    server.hosts_alias = Some("db.local".to_string());
+
+    let tunnel = instantiate(&preset("postgres"), Some(&server));
+    assert!(tunnel.id.is_empty());
+    assert_eq!(tunnel.name, "PostgreSQL on bastion.invalid");
+    assert_eq!((tunnel.port, tunnel.remote_port), (2200, 5432));
+    assert_eq!(tunnel.username, server.username);
+    assert_eq!(tunnel.jump_hosts, server.jump_hosts);
+    assert_eq!(tunnel.extra_args, server.extra_args);
+    // Not a way of reaching the server
+    assert_eq!(tunnel.hosts_alias, None);
+    assert!(is_preset("postgres"));
+}
+
+#[test]
+fn templates_need_the_ports_their_type_forwards() {
+    assert!(all(&AppConfig::default())
+        .iter()
+        .all(|t| t.validate().is_ok()));
+
+    let mut template = preset("postgres");
+    template.remote_port = 0;
+    assert!(template.validate().is_err());
+
+    template.tunnel_type = TunnelType::Remote;
+    template.local_port = 0;
+    assert!(template.validate().is_err());
+    template.local_port = 5432;
+    assert!(template.validate().is_ok());
+
+    template.name = " ".to_string();
+    assert!(template.validate().is_err());
+}
@@ -0,0 +1,166 @@
+use crate::commands;
+use crate::config::load_config;
+use crate::health::{self, AppHealth, HealthStatus};
+use crate::tunnel::{self, TunnelManager, TunnelState, TunnelStatus};
+use log::warn;
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{AppHandle, Listener, Manager, Wry};
+
+const TRAY_ID: &str = "main";
+
+fn is_active(status: &TunnelStatus) -> bool {
+    matches!(
+        status,
+        TunnelStatus::Running | TunnelStatus::Starting | TunnelStatus::Reconnecting
+    )
+}
+
+/// Tray (Windows/Linux) or menu bar (macOS) menu: one toggle per tunnel,
+/// plus window and bulk controls.
+fn build_menu(app: &AppHandle, states: &[TunnelState]) -> tauri::Result<Menu<Wry>> {
+    let cfg = load_config();
+    let menu = Menu::new(app)?;
+
+    menu.append(&MenuItem::with_id(
+        app,
+        "show",
+        "Show OpenTunnel",
+        true,
+        None::<&str>,
+    )?)?;
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+
+    for t in &cfg.tunnels {
+        let active = states.iter().any(|s| s.id == t.id && is_active(&s.status));
+        let label = format!("{} {}", if active { "●" } else { "○" }, t.name);
+        menu.append(&MenuItem::with_id(
+            app,
+            format!("toggle:{}", t.id),
+            label,
+            t.enabled,
+            None::<&str>,
+        )?)?;
+    }
+
+    if !cfg.tunnels.is_empty() {
+        menu.append(&PredefinedMenuItem::separator(app)?)?;
+    }
+    menu.append(&MenuItem::with_id(
+        app,
+        "start-all",
+        "Start all",
+        true,
+        None::<&str>,
+    )?)?;
+    menu.append(&MenuItem::with_id(
+        app,
+        "stop-all",
+        "Stop all",
+        true,
+        None::<&str>,
+    )?)?;
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+    menu.append(&MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?)?;
+
+    Ok(menu)
+}
+
+fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    let id = event.id().as_ref().to_string();
+    let app = app.clone();
+
+    match id.as_str() {
+        "show" => {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.unminimize();
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+        }
+        "start-all" => {
+            tauri::async_runtime::spawn(async move {
+                let _ = commands::start_all_tunnels(app.state(), app.clone()).await;
+            });
+        }
+        "stop-all" => {
+            tauri::async_runtime::spawn(async move {
+                let _ = commands::stop_all_tunnels(app.state(), app.clone()).await;
+            });
+        }
+        "quit" => app.exit(0),
+        _ => {
+            let Some(tunnel_id) = id.strip_prefix("toggle:").map(str::to_string) else {
+                return;
+            };
+            tauri::async_runtime::spawn(async move {
+                let manager = app.state::<TunnelManager>();
+                let active = tunnel::get_all_states(&manager)
+                    .iter()
+                    .any(|s| s.id == tunnel_id && is_active(&s.status));
+                let result = if active {
+                    commands::stop_tunnel_cmd(tunnel_id, app.state(), app.clone()).await
+                } else {
+                    commands::start_tunnel_cmd(tunnel_id, app.state(), app.clone()).await
+                };
+                if let Err(e) = result {
+                    warn!("Tray action failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+fn refresh(app: &AppHandle, states: &[TunnelState]) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    match build_menu(app, states) {
+        Ok(menu) => {
+            let _ = tray.set_menu(Some(menu));
+        }
+        Err(e) => warn!("Failed to rebuild tray menu: {}", e),
+    }
+}
+
+fn tooltip(health: &AppHealth) -> String {
+    match health.status {
+        HealthStatus::AllHealthy => "OpenTunnel".to_string(),
+        HealthStatus::Degraded => format!(
+            "OpenTunnel — {} of {} tunnels down",
+            health.failing,
+            health.failing + health.running
+        ),
+        HealthStatus::AllDown => "OpenTunnel — all tunnels down".to_string(),
+    }
+}
+
+/// Creates the tray icon and keeps its menu in sync with `tunnel-status`,
+/// and its tooltip with `app-health`.
+pub fn init(app: &AppHandle) -> tauri::Result<()> {
+    let menu = build_menu(app, &[])?;
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .menu(&menu)
+        .tooltip("OpenTunnel")
+        .on_menu_event(on_menu_event);
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+
+    let handle = app.clone();
+    app.listen("tunnel-status", move |_event| {
+        let manager = handle.state::<TunnelManager>();
+        refresh(&handle, &tunnel::get_all_states(&manager));
+    });
+
+    let handle = app.clone();
+    app.listen("app-health", move |_event| {
+        let health = health::current(&handle.state::<TunnelManager>());
+        if let Some(tray) = handle.tray_by_id(TRAY_ID) {
+            let _ = tray.set_tooltip(Some(tooltip(&health)));
+        }
+    });
+
+    Ok(())
+}
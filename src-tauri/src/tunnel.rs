@@ -1,36 +1,34 @@
-use crate::config::{AuthMethod, TunnelConfig, TunnelType};
+use crate::actions;
+use crate::backend::{self, HostKeyNotice, OutputKind, TunnelBackend};
+use crate::config::{self, HealthCheckKind, TunnelConfig, TunnelType};
+use crate::health;
+use crate::hosts;
+use crate::instances;
+use crate::logs;
+use crate::mux;
+use crate::netwatch;
+use crate::notify;
+use crate::orphans;
+use crate::password;
+use crate::probe;
+use crate::process::ProcessTree;
+use crate::proxy;
+use crate::relay::{self, Relay};
+use crate::state::emit_transition;
+pub use crate::state::{emit_state, ErrorKind, TunnelState, TunnelStatus};
+use crate::wol;
 use chrono::Utc;
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::process::Stdio;
-use std::sync::Arc;
-use tauri::Emitter;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::{Child, Command};
-use tokio::sync::Mutex;
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum TunnelStatus {
-    Stopped,
-    Starting,
-    Running,
-    Reconnecting,
-    Error,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TunnelState {
-    pub id: String,
-    pub status: TunnelStatus,
-    #[serde(rename = "lastError")]
-    pub last_error: Option<String>,
-    #[serde(rename = "startedAt")]
-    pub started_at: Option<String>,
-    #[serde(rename = "reconnectCount")]
-    pub reconnect_count: u32,
-}
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex as StdMutex, RwLock};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, Lines};
+use tokio::net::TcpStream;
+use tokio::process::Child;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
@@ -41,193 +39,899 @@ pub struct LogEntry {
     pub tunnel_name: String,
     pub level: String,
     pub message: String,
+    /// Client output the line came from, `stdout` or `stderr`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+}
+
+/// Round trip of a tunnel's end-to-end probe, emitted as `tunnel-latency`
+/// after each health check.
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelLatency {
+    #[serde(rename = "tunnelId")]
+    pub tunnel_id: String,
+    pub ms: u64,
+    #[serde(rename = "averageMs")]
+    pub average_ms: u64,
+    /// Last few round trips, oldest first.
+    pub recent: Vec<u64>,
+}
+
+/// A host key the client refused because it isn't trusted yet, emitted as
+/// `host-key-unknown` so the user can accept it.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostKeyPrompt {
+    #[serde(rename = "tunnelId")]
+    pub tunnel_id: String,
+    #[serde(rename = "tunnelName")]
+    pub tunnel_name: String,
+    pub host: String,
+    pub port: u16,
+    pub fingerprint: String,
+}
+
+fn host_key_prompt(tunnel: &TunnelConfig, fingerprint: String) -> HostKeyPrompt {
+    HostKeyPrompt {
+        tunnel_id: tunnel.id.clone(),
+        tunnel_name: tunnel.name.clone(),
+        host: tunnel.host.clone(),
+        port: tunnel.port,
+        fingerprint,
+    }
+}
+
+/// Lines of output kept per stream to make sense of a host key prompt.
+const HOST_KEY_CONTEXT: usize = 12;
+
+/// How long a freshly spawned remote-forward client must stay up before it
+/// counts as connected; there's no local port to check.
+const STARTUP_SETTLE: Duration = Duration::from_secs(2);
+
+/// How long a client gets to open its local port.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the local port is checked while starting.
+const LISTEN_PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long an exited client's output is given to drain.
+const OUTPUT_DRAIN: Duration = Duration::from_secs(1);
+
+/// How often a running tunnel's health check runs, unless it sets its own.
+const HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+/// Longest a health check may take before it counts as failed, as it does
+/// against a hung session.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Consecutive failed health checks after which a client that's still
+/// running is taken for hung and restarted, unless the tunnel sets its own.
+const HUNG_AFTER_CHECKS: u32 = 3;
+/// Probe round trips kept for `tunnel-latency`.
+const LATENCY_WINDOW: usize = 10;
+
+/// Messages a tunnel's supervisor task accepts.
+enum Control {
+    Stop(oneshot::Sender<()>),
 }
 
-pub struct TunnelProcess {
-    pub child: Child,
-    pub state: TunnelState,
+/// Manager-side view of a supervised tunnel. The supervisor task owns the
+/// child process; everything else only sees the shared state.
+pub struct TunnelHandle {
     pub config: TunnelConfig,
+    state: Arc<StdMutex<TunnelState>>,
+    control: mpsc::Sender<Control>,
 }
 
-pub type TunnelManager = Arc<Mutex<HashMap<String, TunnelProcess>>>;
+impl TunnelHandle {
+    pub fn state(&self) -> TunnelState {
+        self.state.lock().unwrap().snapshot()
+    }
 
-pub fn new_manager() -> TunnelManager {
-    Arc::new(Mutex::new(HashMap::new()))
+    /// Asks the supervisor to kill the process and waits until it has.
+    async fn stop(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.control.send(Control::Stop(done_tx)).await.is_ok() {
+            let _ = done_rx.await;
+        }
+    }
 }
 
-pub fn build_plink_args(tunnel: &TunnelConfig, plink_path: &str) -> (String, Vec<String>) {
-    let mut args = vec![
-        "-N".to_string(),        // no shell
-        "-batch".to_string(),    // non-interactive
-        "-ssh".to_string(),      // force SSH
+/// Registry of supervised tunnels. The map lock is only held for short,
+/// synchronous lookups, never across process management or event emits:
+/// callers take a snapshot (`state`, `states`) and emit after releasing it.
+#[derive(Clone)]
+pub struct TunnelManager {
+    tunnels: Arc<RwLock<HashMap<String, TunnelHandle>>>,
+    exited_tx: mpsc::UnboundedSender<String>,
+    exited_rx: Arc<StdMutex<mpsc::UnboundedReceiver<String>>>,
+    /// Persistent local listeners, kept across reconnects until the tunnel
+    /// is stopped.
+    relays: Arc<StdMutex<HashMap<String, Relay>>>,
+    /// When each tunnel was last asked to start.
+    start_requests: Arc<StdMutex<HashMap<String, Instant>>>,
+}
+
+impl TunnelManager {
+    pub fn ids(&self) -> Vec<String> {
+        self.tunnels.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn configs(&self) -> Vec<TunnelConfig> {
+        self.tunnels
+            .read()
+            .unwrap()
+            .values()
+            .map(|h| h.config.clone())
+            .collect()
+    }
+
+    pub fn states(&self) -> Vec<TunnelState> {
+        self.tunnels
+            .read()
+            .unwrap()
+            .values()
+            .map(|h| h.state())
+            .collect()
+    }
+
+    pub fn update_state(&self, tunnel_id: &str, f: impl FnOnce(&mut TunnelState)) {
+        if let Some(handle) = self.tunnels.read().unwrap().get(tunnel_id) {
+            f(&mut handle.state.lock().unwrap());
+        }
+    }
+
+    /// Moves a tracked tunnel to `to` and emits the change.
+    pub fn transition<R: Runtime>(
+        &self,
+        app_handle: &AppHandle<R>,
+        tunnel_id: &str,
+        to: TunnelStatus,
+        reason: Option<String>,
+    ) {
+        let state = self
+            .tunnels
+            .read()
+            .unwrap()
+            .get(tunnel_id)
+            .map(|h| h.state.clone());
+        if let Some(state) = state {
+            apply_transition(app_handle, &state, to, reason);
+        }
+    }
+
+    /// Drops a tunnel from the registry; its supervisor kills the process
+    /// once the control channel closes.
+    pub fn remove(&self, tunnel_id: &str) -> Option<TunnelHandle> {
+        self.tunnels.write().unwrap().remove(tunnel_id)
+    }
+
+    /// How long ago `tunnel_id` was last asked to start.
+    pub fn since_start(&self, tunnel_id: &str) -> Option<Duration> {
+        let requests = self.start_requests.lock().unwrap();
+        requests.get(tunnel_id).map(Instant::elapsed)
+    }
+
+    fn insert(&self, handle: TunnelHandle) {
+        let id = handle.config.id.clone();
+        self.tunnels.write().unwrap().insert(id, handle);
+    }
+
+    /// Binds (or reuses) the tunnel's persistent listener and points it at
+    /// a fresh private port, which the client should listen on instead.
+    async fn relay_to_new_port(&self, tunnel: &TunnelConfig) -> Result<u16, String> {
+        let bound = self.relays.lock().unwrap().contains_key(&tunnel.id);
+        if !bound {
+            let relay = Relay::bind(bind_host(tunnel), tunnel.local_port).await?;
+            self.relays.lock().unwrap().insert(tunnel.id.clone(), relay);
+        }
+        let port = relay::free_port()?;
+        if let Some(relay) = self.relays.lock().unwrap().get(&tunnel.id) {
+            relay.set_upstream(port);
+        }
+        Ok(port)
+    }
+
+    /// How long nothing has been connected to the tunnel's local port, for
+    /// tunnels behind a relay.
+    pub fn idle_for(&self, tunnel_id: &str) -> Option<Duration> {
+        self.relays
+            .lock()
+            .unwrap()
+            .get(tunnel_id)
+            .map(Relay::idle_for)
+    }
+
+    /// Frees what a tunnel holds across reconnects: its persistent listener
+    /// and its use of a shared connection.
+    pub fn release(&self, tunnel_id: &str) {
+        self.relays.lock().unwrap().remove(tunnel_id);
+        mux::release(tunnel_id);
+    }
+}
+
+/// Applies a transition to shared state and emits it once the lock is
+/// released. Invalid transitions are logged and ignored.
+fn apply_transition<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    state: &StdMutex<TunnelState>,
+    to: TunnelStatus,
+    reason: Option<String>,
+) {
+    let (transition, snapshot) = {
+        let mut state = state.lock().unwrap();
+        if to == TunnelStatus::Error {
+            state.last_error_kind = reason.as_deref().map(classify_error);
+        }
+        match state.transition(to, reason) {
+            Ok(t) => (t, state.snapshot()),
+            Err(e) => {
+                warn!("{}", e);
+                return;
+            }
+        }
+    };
+    emit_transition(app_handle, &transition, &snapshot);
+}
+
+/// Sorts a failure by its message, from the client's output or the
+/// supervisor, so the monitor can tell which are worth retrying.
+pub fn classify_error(message: &str) -> ErrorKind {
+    const KINDS: &[(ErrorKind, &[&str])] = &[
+        (
+            ErrorKind::Forward,
+            &[
+                "port forward failed",
+                "address already in use",
+                "cannot listen to port",
+            ],
+        ),
+        (
+            ErrorKind::HostKey,
+            &["host key", "host identification has changed"],
+        ),
+        (
+            ErrorKind::Auth,
+            &[
+                "permission denied",
+                "access denied",
+                "authentication failed",
+                "no supported authentication methods",
+                "too many authentication failures",
+                "server refused our key",
+            ],
+        ),
+        (
+            ErrorKind::Dns,
+            &[
+                "could not resolve",
+                "host does not exist",
+                "name or service not known",
+                "nodename nor servname",
+            ],
+        ),
+        (ErrorKind::Refused, &["connection refused"]),
+        (ErrorKind::Timeout, &["timed out"]),
     ];
+    let message = message.to_lowercase();
+    KINDS
+        .iter()
+        .find(|(_, patterns)| patterns.iter().any(|p| message.contains(p)))
+        .map_or(ErrorKind::Other, |(kind, _)| *kind)
+}
 
-    // Port
-    if tunnel.port != 22 {
-        args.push("-P".to_string());
-        args.push(tunnel.port.to_string());
+pub fn new_manager() -> TunnelManager {
+    let (exited_tx, exited_rx) = mpsc::unbounded_channel();
+    TunnelManager {
+        tunnels: Arc::new(RwLock::new(HashMap::new())),
+        exited_tx,
+        exited_rx: Arc::new(StdMutex::new(exited_rx)),
+        relays: Arc::default(),
+        start_requests: Arc::default(),
     }
+}
+
+/// A spawned client and the task logging its stdout and stderr, which
+/// yields the last error it printed and reports the forward failing.
+struct ClientProcess {
+    child: Child,
+    output: JoinHandle<Option<String>>,
+    forward_failed: oneshot::Receiver<String>,
+}
+
+/// A starting tunnel came up: it's running, and its on-connect action
+/// runs, whoever started it (the user, auto-connect, a reconnect).
+async fn connected<R: Runtime>(
+    tunnel: &TunnelConfig,
+    state: &Arc<StdMutex<TunnelState>>,
+    app_handle: &AppHandle<R>,
+) {
+    password::forget(&tunnel.id).await;
+    apply_transition(app_handle, state, TunnelStatus::Running, None);
+    actions::run_connect_action(app_handle, tunnel);
+}
+
+/// Owns the child process: promotes the tunnel to Running once the client
+/// opens its local port (or, for remote forwards, has settled), then waits
+/// for it to exit or fail its health check (moving to Error) or for a stop
+/// request. A client that never opens its port, or reports its forward
+/// failed, is stopped.
+async fn supervise<R: Runtime>(
+    tunnel: TunnelConfig,
+    backend: Arc<dyn TunnelBackend>,
+    client: ClientProcess,
+    state: Arc<StdMutex<TunnelState>>,
+    mut control: mpsc::Receiver<Control>,
+    exited: mpsc::UnboundedSender<String>,
+    app_handle: AppHandle<R>,
+) {
+    let ClientProcess {
+        mut child,
+        mut output,
+        mut forward_failed,
+    } = client;
+    let mut forward_pending = true;
+    let tree = ProcessTree::attach(&child);
+    // Running once the local port accepts connections; remote forwards
+    // only have the settle delay to go by
+    let listens = tunnel.tunnel_type != TunnelType::Remote;
+    let settle = tokio::time::sleep(STARTUP_SETTLE);
+    tokio::pin!(settle);
+    let startup = tokio::time::sleep(STARTUP_TIMEOUT);
+    tokio::pin!(startup);
+    let mut probe = tokio::time::interval(LISTEN_PROBE_INTERVAL);
+    let mut settled = false;
+    let check = tunnel.health_check.clone().unwrap_or_default();
+    let hung_after = check.failure_threshold.unwrap_or(HUNG_AFTER_CHECKS);
+    let mut health = tokio::time::interval(
+        check
+            .interval_sec
+            .map_or(HEALTH_INTERVAL, Duration::from_secs),
+    );
+    health.tick().await;
+    let mut network = netwatch::Watch::new();
+    let mut latency = LatencyWindow::default();
+    let mut failed_checks = 0;
 
-    // Auth
-    match &tunnel.auth_method {
-        AuthMethod::Key => {
-            if let Some(ref key) = tunnel.key_path {
-                args.push("-i".to_string());
-                args.push(key.clone());
+    let reason = loop {
+        tokio::select! {
+            request = control.recv() => {
+                // Stop requested, or the handle was dropped
+                backend.stop(&mut child, &tree).await;
+                orphans::forget(&tunnel.id).await;
+                password::forget(&tunnel.id).await;
+                if let Some(Control::Stop(done)) = request {
+                    let _ = done.send(());
+                }
+                return;
+            }
+            status = tree.wait(&mut child) => {
+                break match status {
+                    Ok(exit) => {
+                        warn!("Tunnel '{}' exited with status: {:?}", tunnel.name, exit);
+                        // The client's last complaint says more than its
+                        // exit code
+                        let last_error = tokio::time::timeout(OUTPUT_DRAIN, &mut output)
+                            .await
+                            .ok()
+                            .and_then(Result::ok)
+                            .flatten();
+                        last_error
+                            .unwrap_or_else(|| format!("Process exited with code: {:?}", exit.code()))
+                    }
+                    Err(e) => {
+                        error!("Error checking tunnel '{}': {}", tunnel.name, e);
+                        format!("Health check error: {}", e)
+                    }
+                };
+            }
+            // Connected but without its forward, which may leave the port
+            // to whatever else holds it
+            failed = &mut forward_failed, if forward_pending => {
+                forward_pending = false;
+                if let Ok(line) = failed {
+                    warn!("Tunnel '{}' couldn't set up its forward", tunnel.name);
+                    backend.stop(&mut child, &tree).await;
+                    break format!("Port forward failed: {}", line);
+                }
+            }
+            _ = &mut settle, if !settled && !listens => {
+                settled = true;
+                connected(&tunnel, &state, &app_handle).await;
+            }
+            _ = probe.tick(), if !settled && listens => {
+                if is_listening(&tunnel).await {
+                    settled = true;
+                    connected(&tunnel, &state, &app_handle).await;
+                }
+            }
+            _ = &mut startup, if !settled => {
+                warn!("Tunnel '{}' never opened its port", tunnel.name);
+                backend.stop(&mut child, &tree).await;
+                break format!("Timed out waiting for port {} to open", tunnel.local_port);
+            }
+            // The connection may not have survived the switch
+            _ = network.changed(), if settled => health.reset_after(netwatch::SETTLE),
+            _ = health.tick(), if settled && check.kind != HealthCheckKind::Process => {
+                // A client that lost its forward won't get it back
+                if listens && !forward_alive(&tunnel).await {
+                    warn!("Tunnel '{}' lost its forward", tunnel.name);
+                    backend.stop(&mut child, &tree).await;
+                    let e = format!("port {} no longer accepts connections", tunnel.local_port);
+                    health::record(&tunnel.id, Err(&e));
+                    break format!("Health check failed: {}", e);
+                }
+                let checked = tokio::time::timeout(HEALTH_TIMEOUT, check_health(&tunnel, check.kind, backend.as_ref()))
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".to_string()));
+                health::record(&tunnel.id, checked.as_ref().copied().map_err(String::as_str));
+                match checked {
+                    Ok(rtt) => {
+                        failed_checks = 0;
+                        if let Some(rtt) = rtt {
+                            latency.record(&tunnel, rtt, &app_handle);
+                        }
+                    }
+                    // Probes fail now and then; failing in a row while the
+                    // client runs means its session is hung
+                    Err(e) => {
+                        failed_checks += 1;
+                        warn!(
+                            "Tunnel '{}' failed its health check ({} of {}): {}",
+                            tunnel.name, failed_checks, hung_after, e
+                        );
+                        if failed_checks >= hung_after {
+                            backend.stop(&mut child, &tree).await;
+                            break format!("Health check failed: {}", e);
+                        }
+                    }
+                }
             }
         }
-        AuthMethod::Password => {
-            // plink will prompt — but in batch mode this will fail
-            // User should use key-based auth for unattended tunnels
+    };
+
+    orphans::forget(&tunnel.id).await;
+    password::forget(&tunnel.id).await;
+    apply_transition(&app_handle, &state, TunnelStatus::Error, Some(reason));
+    let _ = exited.send(tunnel.id);
+}
+
+/// Address to reach `tunnel`'s local port at; wildcard binds through
+/// loopback.
+fn connect_host(tunnel: &TunnelConfig) -> &str {
+    match tunnel.bind_address.as_deref() {
+        Some("") | Some("0.0.0.0") | Some("*") | Some("::") | Some("[::]") | None => "127.0.0.1",
+        Some(address) => config::unbracket_host(address),
+    }
+}
+
+/// Whether the client accepts connections on its local port yet.
+async fn is_listening(tunnel: &TunnelConfig) -> bool {
+    let connect = TcpStream::connect((connect_host(tunnel), tunnel.local_port));
+    matches!(
+        tokio::time::timeout(LISTEN_PROBE_INTERVAL, connect).await,
+        Ok(Ok(_))
+    )
+}
+
+/// Whether a running tunnel's local port still accepts connections,
+/// giving a busy client a few tries.
+async fn forward_alive(tunnel: &TunnelConfig) -> bool {
+    for _ in 0..3 {
+        if is_listening(tunnel).await {
+            return true;
         }
     }
+    false
+}
 
-    // Tunnel forwarding
-    match tunnel.tunnel_type {
-        TunnelType::Local => {
-            args.push("-L".to_string());
-            args.push(format!(
-                "{}:{}:{}",
-                tunnel.local_port, tunnel.remote_host, tunnel.remote_port
-            ));
+/// What `kind` checks on a running tunnel besides its process being alive
+/// and its local port open, returning the round trip of its end-to-end
+/// probe, if it has one.
+async fn check_health(
+    tunnel: &TunnelConfig,
+    kind: HealthCheckKind,
+    backend: &dyn TunnelBackend,
+) -> Result<Option<Duration>, String> {
+    let mut latency = None;
+    if tunnel.tunnel_type != TunnelType::Remote && kind >= HealthCheckKind::Socks {
+        latency = check_forward(tunnel).await?;
+        if kind == HealthCheckKind::EndToEnd {
+            if let Some(rtt) = probe::check(tunnel, connect_host(tunnel)).await? {
+                latency = Some(rtt);
+            }
         }
-        TunnelType::Remote => {
-            args.push("-R".to_string());
-            args.push(format!(
-                "{}:{}:{}",
-                tunnel.remote_port, tunnel.remote_host, tunnel.local_port
-            ));
+    }
+    backend.health(tunnel).await?;
+    Ok(latency)
+}
+
+/// Checks that a dynamic tunnel's local port speaks SOCKS (reaching its
+/// probe target, if set, whose round trip is returned).
+async fn check_forward(tunnel: &TunnelConfig) -> Result<Option<Duration>, String> {
+    if tunnel.tunnel_type != TunnelType::Dynamic {
+        return Ok(None);
+    }
+    let host = connect_host(tunnel).to_string();
+    let port = tunnel.local_port;
+    let target = tunnel
+        .socks_probe
+        .as_deref()
+        .and_then(config::split_host_port);
+    let timed = target.is_some();
+    let started = Instant::now();
+    tokio::task::spawn_blocking(move || {
+        let target = target.as_ref().map(|(h, p)| (h.as_str(), *p));
+        proxy::probe_socks5(&host, port, target)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("SOCKS proxy isn't working: {}", e))?;
+    Ok(timed.then(|| started.elapsed()))
+}
+
+/// Recent probe round trips of a tunnel, reported as `tunnel-latency`.
+#[derive(Default)]
+struct LatencyWindow {
+    recent: VecDeque<u64>,
+    /// Whether the average is over the tunnel's alert threshold.
+    degraded: bool,
+}
+
+impl LatencyWindow {
+    /// Records a round trip, emits the window and notifies once when the
+    /// average crosses the tunnel's threshold.
+    fn record<R: Runtime>(
+        &mut self,
+        tunnel: &TunnelConfig,
+        rtt: Duration,
+        app_handle: &AppHandle<R>,
+    ) {
+        let ms = rtt.as_millis() as u64;
+        if self.recent.len() == LATENCY_WINDOW {
+            self.recent.pop_front();
         }
-        TunnelType::Dynamic => {
-            args.push("-D".to_string());
-            args.push(tunnel.local_port.to_string());
+        self.recent.push_back(ms);
+        let average = self.recent.iter().sum::<u64>() / self.recent.len() as u64;
+        let _ = app_handle.emit(
+            "tunnel-latency",
+            TunnelLatency {
+                tunnel_id: tunnel.id.clone(),
+                ms,
+                average_ms: average,
+                recent: self.recent.iter().copied().collect(),
+            },
+        );
+
+        let Some(threshold) = tunnel.latency_alert_ms else {
+            return;
+        };
+        let degraded = average > threshold;
+        if degraded && !self.degraded {
+            warn!(
+                "Tunnel '{}' latency is {} ms (alert at {} ms)",
+                tunnel.name, average, threshold
+            );
+            notify::send(
+                app_handle,
+                &tunnel.id,
+                "error",
+                format!("Tunnel '{}' is slow: {} ms", tunnel.name, average),
+            );
         }
+        self.degraded = degraded;
     }
+}
 
-    // user@host
-    args.push(format!("{}@{}", tunnel.username, tunnel.host));
+/// Address `tunnel` listens on locally.
+fn bind_host(tunnel: &TunnelConfig) -> &str {
+    match tunnel.bind_address.as_deref() {
+        Some("") | None => "127.0.0.1",
+        Some("*") => "0.0.0.0",
+        Some(address) => config::unbracket_host(address),
+    }
+}
 
-    (plink_path.to_string(), args)
+/// Why `tunnel`'s local port is taken, checked before the client starts
+/// rather than left for it to fail on: another tunnel here claims it, or
+/// something else already listens on it.
+fn port_conflict(manager: &TunnelManager, tunnel: &TunnelConfig) -> Option<String> {
+    let port = instances::claimed_port(tunnel)?;
+    if let Some(other) = manager
+        .configs()
+        .into_iter()
+        .find(|t| t.id != tunnel.id && instances::claimed_port(t) == Some(port))
+    {
+        return Some(format!(
+            "Port {} is already in use by '{}'",
+            port, other.name
+        ));
+    }
+    // A persistent listener kept across reconnects holds the port itself
+    if manager.relays.lock().unwrap().contains_key(&tunnel.id) {
+        return None;
+    }
+    match std::net::TcpListener::bind((bind_host(tunnel), port)) {
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => Some(format!(
+            "Port {} is already in use by another program",
+            port
+        )),
+        _ => None,
+    }
 }
 
-pub async fn start_tunnel(
+/// Starts `tunnel`, returning it as started: with a local port of 0 it
+/// listens on a free port picked here.
+pub async fn start_tunnel<R: Runtime>(
     manager: &TunnelManager,
     tunnel: &TunnelConfig,
     plink_path: &str,
-    app_handle: tauri::AppHandle,
-) -> Result<(), String> {
-    let (cmd, args) = build_plink_args(tunnel, plink_path);
+    app_handle: AppHandle<R>,
+) -> Result<TunnelConfig, String> {
+    let mut tunnel = tunnel.clone();
+    if tunnel.keepalive_sec.is_none() {
+        tunnel.keepalive_sec = Some(config::load_config_async().await.settings.keepalive_sec);
+    }
+    tunnel.key_path = tunnel.key_path.as_deref().map(config::expand_path);
+    let backend = backend::select(&tunnel, &config::expand_path(plink_path));
+    start_with_backend(manager, &tunnel, backend, app_handle).await
+}
+
+/// `start_tunnel` with an explicit backend.
+pub async fn start_with_backend<R: Runtime>(
+    manager: &TunnelManager,
+    tunnel: &TunnelConfig,
+    backend: Arc<dyn TunnelBackend>,
+    app_handle: AppHandle<R>,
+) -> Result<TunnelConfig, String> {
+    manager
+        .start_requests
+        .lock()
+        .unwrap()
+        .insert(tunnel.id.clone(), Instant::now());
+    let mut tunnel = tunnel.clone();
+    if instances::claimed_port(&tunnel) == Some(0) {
+        tunnel.local_port = relay::free_port()?;
+    }
+    let tunnel = &tunnel;
 
-    info!("Starting tunnel '{}': {} {}", tunnel.name, cmd, args.join(" "));
+    if let Some(reason) = instances::find_conflict(tunnel).await {
+        return Err(reason);
+    }
+    if let Some(reason) = port_conflict(manager, tunnel) {
+        return Err(reason);
+    }
 
-    let mut child = Command::new(&cmd)
-        .args(&args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .stdin(Stdio::null())
-        .kill_on_drop(true)
-        .spawn()
-        .map_err(|e| format!("Failed to start plink: {}. Is '{}' in PATH?", e, cmd))?;
+    if let Some(ref wake) = tunnel.wake_on_lan {
+        if let Err(e) = wol::wake_host(wake, &tunnel.host, tunnel.port).await {
+            warn!("Wake-on-LAN for '{}' failed: {}", tunnel.name, e);
+        }
+    }
+
+    // With a persistent listener, or to tell when the tunnel is idle, the
+    // client forwards from a private port behind the relay
+    let mut client = tunnel.clone();
+    if (tunnel.persistent_listener || tunnel.idle_disconnect_min.is_some_and(|m| m > 0))
+        && tunnel.tunnel_type != TunnelType::Remote
+    {
+        client.local_port = manager.relay_to_new_port(tunnel).await?;
+    }
+
+    let spawned = spawn_client(&client, backend.as_ref()).await;
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            password::forget(&tunnel.id).await;
+            // The persistent listener stays for the next attempt
+            mux::release(&tunnel.id);
+            return Err(e);
+        }
+    };
+    if let Some(pid) = child.id() {
+        orphans::record(&tunnel.id, &tunnel.id, pid).await;
+    }
 
     let state = TunnelState {
         id: tunnel.id.clone(),
-        status: TunnelStatus::Running,
+        status: TunnelStatus::Starting,
         last_error: None,
+        last_error_kind: None,
         started_at: Some(Utc::now().to_rfc3339()),
         reconnect_count: 0,
+        next_retry_at: None,
+        local_port: instances::claimed_port(tunnel),
+        pid: child.id(),
+        uptime_sec: None,
+        local_endpoint: instances::claimed_port(tunnel)
+            .map(|port| format!("{}:{}", config::bracket_host(bind_host(tunnel)), port)),
+        ssh_target: tunnel.provider.is_none().then(|| {
+            format!(
+                "{}@{}:{}",
+                tunnel.username,
+                config::bracket_host(&tunnel.host),
+                tunnel.port
+            )
+        }),
     };
 
-    // Stream stderr to logs
-    let tunnel_id = tunnel.id.clone();
-    let tunnel_name = tunnel.name.clone();
+    // Stream both outputs to logs, keeping the last error for the exit
+    // reason
+    let logged = tunnel.clone();
     let handle = app_handle.clone();
-    if let Some(stderr) = child.stderr.take() {
-        let reader = BufReader::new(stderr);
-        tokio::spawn(async move {
-            let mut lines = reader.lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                let entry = LogEntry {
-                    timestamp: Utc::now().to_rfc3339(),
-                    tunnel_id: tunnel_id.clone(),
-                    tunnel_name: tunnel_name.clone(),
-                    level: "info".to_string(),
-                    message: line,
-                };
-                let _ = handle.emit("tunnel-log", &entry);
+    let parser = backend.clone();
+    let mut stdout = child.stdout.take().map(|s| BufReader::new(s).lines());
+    let mut stderr = child.stderr.take().map(|s| BufReader::new(s).lines());
+    let (forward_tx, forward_failed) = oneshot::channel();
+    let mut forward_tx = Some(forward_tx);
+    let output = tokio::spawn(async move {
+        let mut last_error = None;
+        let mut recent: [Vec<String>; 2] = Default::default();
+        loop {
+            let (stream, line) = tokio::select! {
+                Ok(Some(line)) = next_line(&mut stderr) => (0, line),
+                Ok(Some(line)) = next_line(&mut stdout) => (1, line),
+                else => break,
+            };
+            match parser.host_key_notice(&logged, &recent[stream], &line) {
+                Some(HostKeyNotice::Unknown(fingerprint)) => {
+                    let _ = handle.emit("host-key-unknown", host_key_prompt(&logged, fingerprint));
+                }
+                Some(HostKeyNotice::Changed(fingerprint)) => {
+                    let _ = handle.emit("host-key-changed", host_key_prompt(&logged, fingerprint));
+                }
+                Some(HostKeyNotice::OtherHost(host)) => {
+                    let entry = LogEntry {
+                        timestamp: Utc::now().to_rfc3339(),
+                        tunnel_id: logged.id.clone(),
+                        tunnel_name: logged.name.clone(),
+                        level: "error".to_string(),
+                        message: format!(
+                            "The host key of {} isn't trusted; connect to it once on its own to check and store it",
+                            host
+                        ),
+                        stream: None,
+                    };
+                    logs::push(&handle, entry);
+                }
+                None => {}
+            }
+            let logged_line = parser.log_line(&logged, &line);
+            let lines = &mut recent[stream];
+            if lines.len() == HOST_KEY_CONTEXT {
+                lines.remove(0);
+            }
+            lines.push(line);
+            let Some((kind, message)) = logged_line else {
+                continue;
+            };
+            if kind != OutputKind::Info {
+                last_error = Some(message.clone());
+            }
+            if kind == OutputKind::ForwardFailed {
+                if let Some(tx) = forward_tx.take() {
+                    let _ = tx.send(message.clone());
+                }
+            }
+            let entry = LogEntry {
+                timestamp: Utc::now().to_rfc3339(),
+                tunnel_id: logged.id.clone(),
+                tunnel_name: logged.name.clone(),
+                level: kind.level().to_string(),
+                message,
+                stream: Some(["stderr", "stdout"][stream].to_string()),
+            };
+            logs::push(&handle, entry);
+        }
+        last_error
+    });
+
+    if let Some(alias) = tunnel.hosts_alias.clone().filter(|a| !a.is_empty()) {
+        let id = tunnel.id.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = hosts::add_alias(&id, &alias) {
+                warn!("{}", e);
             }
         });
     }
 
-    let mut mgr = manager.lock().await;
-    mgr.insert(
-        tunnel.id.clone(),
-        TunnelProcess {
+    // Tracked before it's announced, so whoever reacts to the event finds
+    // it, and before it's supervised, so its first change follows that
+    let starting = state.clone();
+    let state = Arc::new(StdMutex::new(state));
+    let (control_tx, control_rx) = mpsc::channel(4);
+    manager.insert(TunnelHandle {
+        config: tunnel.clone(),
+        state: state.clone(),
+        control: control_tx,
+    });
+    instances::publish_async(manager.configs()).await;
+    emit_state(&app_handle, &starting);
+
+    tokio::spawn(supervise(
+        client,
+        backend,
+        ClientProcess {
             child,
-            state,
-            config: tunnel.clone(),
+            output,
+            forward_failed,
         },
-    );
+        state,
+        control_rx,
+        manager.exited_tx.clone(),
+        app_handle.clone(),
+    ));
 
-    // Emit status update
-    let _ = app_handle.emit("tunnel-status", &get_all_states_inner(&mgr));
+    Ok(tunnel.clone())
+}
 
-    Ok(())
+/// Next line of a client output that's still open.
+async fn next_line<T: AsyncRead + Unpin>(
+    lines: &mut Option<Lines<BufReader<T>>>,
+) -> std::io::Result<Option<String>> {
+    match lines {
+        Some(lines) => lines.next_line().await,
+        None => Ok(None),
+    }
+}
+
+async fn spawn_client(tunnel: &TunnelConfig, backend: &dyn TunnelBackend) -> Result<Child, String> {
+    backend.validate(tunnel)?;
+    password::write(tunnel).await?;
+    backend.prepare(tunnel).await?;
+    let (cmd, args) = backend.command(tunnel);
+
+    info!(
+        "Starting tunnel '{}': {} {}",
+        tunnel.name,
+        cmd,
+        args.join(" ")
+    );
+
+    let mut child = backend.spawn(tunnel)?;
+    // Written on its own so a full pipe can't hold up the start
+    if let (Some(input), Some(mut stdin)) = (backend.input(tunnel), child.stdin.take()) {
+        tokio::spawn(async move {
+            let _ = stdin.write_all(input.as_bytes()).await;
+        });
+    }
+    Ok(child)
 }
 
-pub async fn stop_tunnel(
+pub async fn stop_tunnel<R: Runtime>(
     manager: &TunnelManager,
     tunnel_id: &str,
-    app_handle: &tauri::AppHandle,
+    app_handle: &AppHandle<R>,
 ) -> Result<(), String> {
-    let mut mgr = manager.lock().await;
-    if let Some(process) = mgr.get_mut(tunnel_id) {
-        info!("Stopping tunnel '{}'", process.config.name);
-        let _ = process.child.kill().await;
-        process.state.status = TunnelStatus::Stopped;
-        process.state.last_error = None;
+    if let Some(handle) = manager.remove(tunnel_id) {
+        info!("Stopping tunnel '{}'", handle.config.name);
+        handle.stop().await;
+
+        // Waited for, so it's done before the app exits
+        if handle.config.hosts_alias.is_some() {
+            let id = tunnel_id.to_string();
+            let removed = tokio::task::spawn_blocking(move || hosts::remove_alias(&id)).await;
+            if let Ok(Err(e)) = removed {
+                warn!("{}", e);
+            }
+        }
 
-        let _ = app_handle.emit("tunnel-status", &get_all_states_inner(&mgr));
+        apply_transition(app_handle, &handle.state, TunnelStatus::Stopped, None);
     }
-    mgr.remove(tunnel_id);
+    manager.release(tunnel_id);
+    instances::publish_async(manager.configs()).await;
     Ok(())
 }
 
-pub async fn get_all_states(manager: &TunnelManager) -> Vec<TunnelState> {
-    let mgr = manager.lock().await;
-    get_all_states_inner(&mgr)
+pub fn get_all_states(manager: &TunnelManager) -> Vec<TunnelState> {
+    manager.states()
 }
 
-fn get_all_states_inner(mgr: &HashMap<String, TunnelProcess>) -> Vec<TunnelState> {
-    mgr.values().map(|p| p.state.clone()).collect()
-}
-
-pub async fn check_tunnel_health(manager: &TunnelManager) -> Vec<String> {
+/// Tunnels whose process exited since the last call.
+pub fn check_tunnel_health(manager: &TunnelManager) -> Vec<String> {
     let mut dead_tunnels = Vec::new();
-    let mut mgr = manager.lock().await;
-
-    for (id, process) in mgr.iter_mut() {
-        if process.state.status == TunnelStatus::Running {
-            match process.child.try_wait() {
-                Ok(Some(exit)) => {
-                    warn!(
-                        "Tunnel '{}' exited with status: {:?}",
-                        process.config.name, exit
-                    );
-                    process.state.status = TunnelStatus::Error;
-                    process.state.last_error =
-                        Some(format!("Process exited with code: {:?}", exit.code()));
-                    dead_tunnels.push(id.clone());
-                }
-                Ok(None) => {} // still running
-                Err(e) => {
-                    error!("Error checking tunnel '{}': {}", process.config.name, e);
-                    process.state.status = TunnelStatus::Error;
-                    process.state.last_error = Some(format!("Health check error: {}", e));
-                    dead_tunnels.push(id.clone());
-                }
-            }
-        }
+    let mut exited = manager.exited_rx.lock().unwrap();
+    while let Ok(id) = exited.try_recv() {
+        dead_tunnels.push(id);
     }
-
     dead_tunnels
 }
+
+#[cfg(test)]
+mod tests;
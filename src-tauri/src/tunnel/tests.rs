@@ -0,0 +1,671 @@
+//! Lifecycle tests against a scripted fake plink (or a mock backend) and a
+//! mock Tauri runtime.
+
+use super::*;
+use crate::backend::OutputKind;
+use crate::config::{HealthProbe, HttpProbe};
+use crate::logs::LogBus;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tauri::test::{mock_builder, mock_context, noop_assets, MockRuntime};
+
+/// Stays connected until killed.
+const STAY_UP: Script = Script {
+    unix: "exec sleep 30",
+    windows: "@ping -n 30 127.0.0.1 > nul",
+};
+
+/// Fails like plink does when the host refuses the connection.
+const REFUSED: Script = Script {
+    unix: "echo 'FATAL ERROR: Network error: Connection refused' >&2\nexit 1",
+    windows: "@echo FATAL ERROR: Network error: Connection refused 1>&2\r\n@exit /b 1",
+};
+
+/// Fails with its complaint on stdout rather than stderr.
+const REFUSED_ON_STDOUT: Script = Script {
+    unix: "echo 'FATAL ERROR: Network error: Connection refused'\nexit 1",
+    windows: "@echo FATAL ERROR: Network error: Connection refused\r\n@exit /b 1",
+};
+
+/// Connects but can't bind its forward, and stays up like plink does.
+const PORT_IN_USE: Script = Script {
+    unix: "echo 'Local port 8080 forwarding to 127.0.0.1:80 failed: Network error: Address already in use' >&2\nexec sleep 30",
+    windows: "@echo Local port 8080 forwarding to 127.0.0.1:80 failed: Network error: Address already in use 1>&2\r\n@ping -n 30 127.0.0.1 > nul",
+};
+
+struct Script {
+    unix: &'static str,
+    windows: &'static str,
+}
+
+/// Path of a fake plink, deleted with its directory when dropped.
+struct FakePlink {
+    dir: PathBuf,
+    path: String,
+}
+
+impl std::ops::Deref for FakePlink {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for FakePlink {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Writes `script` as an executable whose name makes it pass for plink, in
+/// a directory of its own; `name` must be unique among the tests.
+fn fake_plink(name: &str, script: &Script) -> FakePlink {
+    let dir = std::env::temp_dir().join(format!("opentunnel-test-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let path: PathBuf = if cfg!(windows) {
+        let path = dir.join(format!("{}.cmd", name));
+        std::fs::write(&path, script.windows).unwrap();
+        path
+    } else {
+        let path = dir.join(format!("{}.sh", name));
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script.unix)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        path
+    };
+    FakePlink {
+        path: path.to_string_lossy().to_string(),
+        dir,
+    }
+}
+
+fn mock_app() -> tauri::App<MockRuntime> {
+    mock_builder()
+        .manage(LogBus::new(100))
+        .build(mock_context(noop_assets()))
+        .unwrap()
+}
+
+fn test_tunnel(name: &str) -> TunnelConfig {
+    let mut tunnel = TunnelConfig::new(
+        name.to_string(),
+        "bastion.invalid".to_string(),
+        "tester".to_string(),
+    );
+    tunnel.local_port = relay::free_port().unwrap();
+    tunnel
+}
+
+/// Holds `tunnel`'s local port open once it has started, standing in for
+/// the client's forward.
+fn listen(tunnel: &TunnelConfig) -> std::net::TcpListener {
+    std::net::TcpListener::bind(("127.0.0.1", tunnel.local_port)).unwrap()
+}
+
+/// Polls the manager until `id` reaches `status`, failing after a few
+/// seconds of wall-clock time.
+async fn wait_for(manager: &TunnelManager, id: &str, status: TunnelStatus) -> TunnelState {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(state) = manager.states().into_iter().find(|s| s.id == id) {
+            if state.status == status {
+                return state;
+            }
+        }
+        assert!(
+            Instant::now() < deadline,
+            "tunnel never reached {:?}",
+            status
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// Scripted backend: runs `script` with no SSH arguments, records which
+/// hooks the supervisor called and reports `health`.
+struct MockBackend {
+    program: FakePlink,
+    health: Result<(), String>,
+    prepared: AtomicUsize,
+    stopped: AtomicUsize,
+}
+
+impl MockBackend {
+    fn new(name: &str, script: &Script) -> Self {
+        Self {
+            program: fake_plink(name, script),
+            health: Ok(()),
+            prepared: AtomicUsize::new(0),
+            stopped: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl TunnelBackend for MockBackend {
+    fn command(&self, _tunnel: &TunnelConfig) -> (String, Vec<String>) {
+        (self.program.to_string(), Vec::new())
+    }
+
+    async fn prepare(&self, _tunnel: &TunnelConfig) -> Result<(), String> {
+        self.prepared.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn stop(&self, child: &mut Child, tree: &ProcessTree) {
+        self.stopped.fetch_add(1, Ordering::SeqCst);
+        tree.kill(child);
+        let _ = child.kill().await;
+    }
+
+    async fn health(&self, _tunnel: &TunnelConfig) -> Result<(), String> {
+        self.health.clone()
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn start_settles_into_running_and_stop_removes() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("up");
+    let plink = fake_plink("plink-up", &STAY_UP);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+    let _listener = listen(&tunnel);
+    assert_eq!(manager.states()[0].status, TunnelStatus::Starting);
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Running).await;
+    assert!(state.pid.is_some());
+    assert!(state.uptime_sec.is_some());
+    assert_eq!(
+        state.local_endpoint,
+        Some(format!("127.0.0.1:{}", tunnel.local_port))
+    );
+    assert_eq!(
+        state.ssh_target.as_deref(),
+        Some("tester@bastion.invalid:22")
+    );
+
+    stop_tunnel(&manager, &tunnel.id, app.handle())
+        .await
+        .unwrap();
+    assert!(manager.states().is_empty());
+    assert!(check_tunnel_health(&manager).is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn exit_moves_to_error_and_is_reported_dead() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("refused");
+    let plink = fake_plink("plink-refused", &REFUSED);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert_eq!(
+        state.last_error.as_deref(),
+        Some("FATAL ERROR: Network error: Connection refused")
+    );
+    assert_eq!(state.last_error_kind, Some(ErrorKind::Refused));
+    assert_eq!(check_tunnel_health(&manager), vec![tunnel.id.clone()]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn errors_on_stdout_are_the_exit_reason_too() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("stdout");
+    let plink = fake_plink("plink-stdout", &REFUSED_ON_STDOUT);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert_eq!(
+        state.last_error.as_deref(),
+        Some("FATAL ERROR: Network error: Connection refused")
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn forward_failure_stops_a_connected_client() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("in-use");
+    let plink = fake_plink("plink-in-use", &PORT_IN_USE);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+    // Someone else takes the port after the check, so it looks open
+    let _listener = listen(&tunnel);
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert!(state
+        .last_error
+        .unwrap()
+        .starts_with("Port forward failed: Local port 8080 forwarding"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn reconnect_after_failure_replaces_dead_process() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("flaky");
+
+    let refused = fake_plink("plink-flaky-down", &REFUSED);
+    start_tunnel(&manager, &tunnel, &refused, app.handle().clone())
+        .await
+        .unwrap();
+    wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+
+    // What the monitor does for a dead auto-connect tunnel
+    assert_eq!(check_tunnel_health(&manager), vec![tunnel.id.clone()]);
+    manager.transition(app.handle(), &tunnel.id, TunnelStatus::Reconnecting, None);
+    wait_for(&manager, &tunnel.id, TunnelStatus::Reconnecting).await;
+    manager.remove(&tunnel.id);
+
+    let up = fake_plink("plink-flaky-up", &STAY_UP);
+    start_tunnel(&manager, &tunnel, &up, app.handle().clone())
+        .await
+        .unwrap();
+    let _listener = listen(&tunnel);
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Running).await;
+    assert_eq!(state.last_error, None);
+
+    stop_tunnel(&manager, &tunnel.id, app.handle())
+        .await
+        .unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_health_check_kills_tunnel() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("unhealthy");
+    let backend = Arc::new(MockBackend {
+        health: Err("probe failed".to_string()),
+        ..MockBackend::new("mock-unhealthy", &STAY_UP)
+    });
+
+    start_with_backend(&manager, &tunnel, backend, app.handle().clone())
+        .await
+        .unwrap();
+    let _listener = listen(&tunnel);
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert_eq!(
+        state.last_error.as_deref(),
+        Some("Health check failed: probe failed")
+    );
+    assert_eq!(check_tunnel_health(&manager), vec![tunnel.id.clone()]);
+    let history = crate::health::history(&tunnel.id);
+    assert_eq!(history.len(), 3);
+    assert!(history.iter().all(|s| !s.ok));
+}
+
+#[tokio::test(start_paused = true)]
+async fn process_only_check_ignores_failing_health() {
+    let app = mock_app();
+    let manager = new_manager();
+    let mut tunnel = test_tunnel("process-only");
+    tunnel.health_check = Some(crate::config::HealthCheck {
+        kind: crate::config::HealthCheckKind::Process,
+        interval_sec: Some(1),
+        failure_threshold: Some(1),
+    });
+    let backend = Arc::new(MockBackend {
+        health: Err("probe failed".to_string()),
+        ..MockBackend::new("mock-process-only", &STAY_UP)
+    });
+
+    start_with_backend(&manager, &tunnel, backend, app.handle().clone())
+        .await
+        .unwrap();
+    let _listener = listen(&tunnel);
+    wait_for(&manager, &tunnel.id, TunnelStatus::Running).await;
+    // Long enough for checks every second to have failed it many times
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert!(check_tunnel_health(&manager).is_empty());
+    stop_tunnel(&manager, &tunnel.id, app.handle())
+        .await
+        .unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn lost_forward_fails_a_live_client() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("lost");
+    let plink = fake_plink("plink-lost", &STAY_UP);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+    let listener = listen(&tunnel);
+    wait_for(&manager, &tunnel.id, TunnelStatus::Running).await;
+    drop(listener);
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert_eq!(
+        state.last_error,
+        Some(format!(
+            "Health check failed: port {} no longer accepts connections",
+            tunnel.local_port
+        ))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn dynamic_tunnel_that_doesnt_speak_socks_fails() {
+    let app = mock_app();
+    let manager = new_manager();
+    let mut tunnel = test_tunnel("socks");
+    tunnel.tunnel_type = TunnelType::Dynamic;
+    let plink = fake_plink("plink-socks", &STAY_UP);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+    // Hangs up on every connection instead of answering the handshake
+    let listener = listen(&tunnel);
+    std::thread::spawn(move || for _ in listener.incoming() {});
+    wait_for(&manager, &tunnel.id, TunnelStatus::Running).await;
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert!(state
+        .last_error
+        .unwrap()
+        .starts_with("Health check failed: SOCKS proxy isn't working"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn http_probe_fails_a_tunnel_whose_service_is_down() {
+    let app = mock_app();
+    let manager = new_manager();
+    let mut tunnel = test_tunnel("probed");
+    tunnel.remote_port = 80;
+    tunnel.health_probe = Some(HealthProbe::Http(HttpProbe {
+        path: "/health".to_string(),
+        expect_status: 200,
+    }));
+    let plink = fake_plink("plink-probed", &STAY_UP);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+    // The service behind the forward is unavailable
+    let listener = listen(&tunnel);
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = [0u8; 512];
+            if std::io::Read::read(&mut stream, &mut request).unwrap_or(0) > 0 {
+                let _ = std::io::Write::write_all(
+                    &mut stream,
+                    b"HTTP/1.1 503 Service Unavailable\r\n\r\n",
+                );
+            }
+        }
+    });
+    wait_for(&manager, &tunnel.id, TunnelStatus::Running).await;
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert_eq!(
+        state.last_error.as_deref(),
+        Some("Health check failed: 127.0.0.1:80/health answered 503 instead of 200")
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn backend_is_prepared_before_start_and_stopped_once() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("mock");
+    let backend = Arc::new(MockBackend::new("mock-up", &STAY_UP));
+
+    start_with_backend(&manager, &tunnel, backend.clone(), app.handle().clone())
+        .await
+        .unwrap();
+    let _listener = listen(&tunnel);
+    assert_eq!(backend.prepared.load(Ordering::SeqCst), 1);
+    wait_for(&manager, &tunnel.id, TunnelStatus::Running).await;
+
+    stop_tunnel(&manager, &tunnel.id, app.handle())
+        .await
+        .unwrap();
+    assert_eq!(backend.stopped.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn taken_local_port_is_refused_before_spawning() {
+    let app = mock_app();
+    let manager = new_manager();
+    let first = test_tunnel("first");
+    let mut second = test_tunnel("second");
+    second.local_port = first.local_port;
+    let plink = fake_plink("plink-taken", &STAY_UP);
+
+    start_tunnel(&manager, &first, &plink, app.handle().clone())
+        .await
+        .unwrap();
+    let err = start_tunnel(&manager, &second, &plink, app.handle().clone())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        format!("Port {} is already in use by 'first'", first.local_port)
+    );
+
+    stop_tunnel(&manager, &first.id, app.handle())
+        .await
+        .unwrap();
+    let _listener = listen(&second);
+    let err = start_tunnel(&manager, &second, &plink, app.handle().clone())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        format!(
+            "Port {} is already in use by another program",
+            second.local_port
+        )
+    );
+    assert!(manager.states().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn port_zero_listens_on_a_free_port() {
+    let app = mock_app();
+    let manager = new_manager();
+    let mut tunnel = test_tunnel("auto");
+    tunnel.local_port = 0;
+    let plink = fake_plink("plink-auto", &STAY_UP);
+
+    let started = start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+    assert_ne!(started.local_port, 0);
+    let _listener = listen(&started);
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Running).await;
+    assert_eq!(state.local_port, Some(started.local_port));
+}
+
+#[tokio::test(start_paused = true)]
+async fn client_that_never_opens_its_port_times_out() {
+    let app = mock_app();
+    let manager = new_manager();
+    let tunnel = test_tunnel("silent");
+    let plink = fake_plink("plink-silent", &STAY_UP);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+
+    let state = wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert_eq!(
+        state.last_error,
+        Some(format!(
+            "Timed out waiting for port {} to open",
+            tunnel.local_port
+        ))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn persistent_listener_outlives_process_until_stopped() {
+    let app = mock_app();
+    let manager = new_manager();
+    let mut tunnel = test_tunnel("held");
+    tunnel.persistent_listener = true;
+    let plink = fake_plink("plink-held", &REFUSED);
+
+    start_tunnel(&manager, &tunnel, &plink, app.handle().clone())
+        .await
+        .unwrap();
+    wait_for(&manager, &tunnel.id, TunnelStatus::Error).await;
+    assert!(std::net::TcpListener::bind(("127.0.0.1", tunnel.local_port)).is_err());
+
+    stop_tunnel(&manager, &tunnel.id, app.handle())
+        .await
+        .unwrap();
+    // The aborted accept task drops its listener when next scheduled
+    tokio::task::yield_now().await;
+    assert!(std::net::TcpListener::bind(("127.0.0.1", tunnel.local_port)).is_ok());
+}
+
+#[test]
+fn failures_are_sorted_by_cause() {
+    let cases = [
+        (
+            "FATAL ERROR: Network error: Connection refused",
+            ErrorKind::Refused,
+        ),
+        (
+            "FATAL ERROR: No supported authentication methods available",
+            ErrorKind::Auth,
+        ),
+        (
+            "tester@bastion: Permission denied (publickey).",
+            ErrorKind::Auth,
+        ),
+        ("Host key verification failed.", ErrorKind::HostKey),
+        (
+            "ssh: Could not resolve hostname bastion.invalid",
+            ErrorKind::Dns,
+        ),
+        ("FATAL ERROR: Host does not exist", ErrorKind::Dns),
+        (
+            "FATAL ERROR: Network error: Connection timed out",
+            ErrorKind::Timeout,
+        ),
+        (
+            "Port forward failed: Local port 8080 forwarding to 127.0.0.1:80 failed",
+            ErrorKind::Forward,
+        ),
+        ("Process exited with code: Some(1)", ErrorKind::Other),
+    ];
+    for (message, kind) in cases {
+        assert_eq!(classify_error(message), kind, "{}", message);
+    }
+    assert!(!ErrorKind::Auth.retryable());
+    assert!(ErrorKind::Timeout.retryable());
+}
+
+#[test]
+fn plink_fatal_errors_are_classified() {
+    let backend = backend::select(&test_tunnel("parse"), "plink");
+    assert_eq!(
+        backend.parse_output("FATAL ERROR: Network error: Connection refused"),
+        OutputKind::Error
+    );
+    assert_eq!(
+        backend.parse_output("Using username \"tester\"."),
+        OutputKind::Info
+    );
+}
+
+#[test]
+fn jump_hosts_chain_through_each_hop() {
+    let mut tunnel = test_tunnel("jump");
+    tunnel.jump_hosts = vec![
+        "edge.invalid".to_string(),
+        "ops@inner.invalid:2222".to_string(),
+    ];
+
+    let (_, args) = backend::select(&tunnel, "plink").command(&tunnel);
+    let proxy = &args[args.iter().position(|a| a == "-proxycmd").unwrap() + 1];
+    assert_eq!(
+        proxy,
+        "plink -batch -ssh -P 2222 \
+         -proxycmd \"plink -batch -ssh -P 22 -nc inner.invalid:2222 tester@edge.invalid\" \
+         -nc %host:%port ops@inner.invalid"
+    );
+
+    let (_, args) = backend::select(&tunnel, "ssh").command(&tunnel);
+    let jump = &args[args.iter().position(|a| a == "-J").unwrap() + 1];
+    assert_eq!(jump, "tester@edge.invalid:22,ops@inner.invalid:2222");
+}
+
+#[test]
+fn ipv6_hosts_are_bracketed_in_forwards() {
+    let mut tunnel = test_tunnel("ipv6");
+    tunnel.remote_host = "fd00::10".to_string();
+    tunnel.bind_address = Some("::1".to_string());
+    tunnel.jump_hosts = vec!["[2001:db8::1]:2222".to_string()];
+
+    let (_, args) = backend::select(&tunnel, "ssh").command(&tunnel);
+    let spec = &args[args.iter().position(|a| a == "-L").unwrap() + 1];
+    assert_eq!(
+        spec,
+        &format!(
+            "[::1]:{}:[fd00::10]:{}",
+            tunnel.local_port, tunnel.remote_port
+        )
+    );
+    let jump = &args[args.iter().position(|a| a == "-J").unwrap() + 1];
+    assert_eq!(jump, "tester@[2001:db8::1]:2222");
+}
+
+#[test]
+fn proxy_transport_connects_through_the_helper() {
+    let mut tunnel = test_tunnel("proxied");
+    tunnel.transport = Some(crate::config::Transport::Proxy(
+        crate::config::ProxyTransport {
+            protocol: crate::config::ProxyProtocol::Http,
+            host: "proxy.invalid".to_string(),
+            port: 3128,
+            username: Some("alice".to_string()),
+            password: Some("secret".to_string()),
+            password_ref: None,
+        },
+    ));
+
+    let (_, args) = backend::select(&tunnel, "ssh").command(&tunnel);
+    let proxy = args
+        .iter()
+        .find(|a| a.starts_with("ProxyCommand="))
+        .unwrap();
+    assert!(proxy.ends_with(&format!(
+        " --proxy-connect http alice@proxy.invalid:3128 {}:22",
+        tunnel.host
+    )));
+    assert!(!args.iter().any(|a| a.contains("secret")));
+}
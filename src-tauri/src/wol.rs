@@ -0,0 +1,84 @@
+use crate::config::WakeOnLan;
+use log::{info, warn};
+use std::time::Duration;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{sleep, timeout, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const RESEND_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn parse_mac(mac: &str) -> Result<[u8; 6], String> {
+    let hex: String = mac
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .collect();
+    if hex.len() != 12 {
+        return Err(format!("Invalid MAC address '{}'", mac));
+    }
+
+    let mut bytes = [0u8; 6];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("Invalid MAC address '{}'", mac))?;
+    }
+    Ok(bytes)
+}
+
+/// Magic packet: 6 × 0xFF followed by the MAC repeated 16 times.
+fn magic_packet(mac: [u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0xFFu8; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac);
+    }
+    packet
+}
+
+pub async fn send_magic_packet(wol: &WakeOnLan) -> Result<(), String> {
+    let mac = parse_mac(&wol.mac)?;
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|e| format!("Failed to open WoL socket: {}", e))?;
+    socket
+        .set_broadcast(true)
+        .map_err(|e| format!("Failed to enable broadcast: {}", e))?;
+    socket
+        .send_to(&magic_packet(mac), (wol.broadcast.as_str(), wol.port))
+        .await
+        .map_err(|e| format!("Failed to send magic packet: {}", e))?;
+    Ok(())
+}
+
+async fn is_reachable(host: &str, port: u16) -> bool {
+    matches!(
+        timeout(PROBE_TIMEOUT, TcpStream::connect((host, port))).await,
+        Ok(Ok(_))
+    )
+}
+
+/// Wakes `host` if its SSH port doesn't answer, re-sending the magic packet
+/// until it does or `wait_sec` runs out. Returns whether the host came up.
+pub async fn wake_host(wol: &WakeOnLan, host: &str, port: u16) -> Result<bool, String> {
+    if is_reachable(host, port).await {
+        return Ok(true);
+    }
+
+    info!(
+        "Host {} unreachable, sending Wake-on-LAN to {}",
+        host, wol.mac
+    );
+    let deadline = Instant::now() + Duration::from_secs(wol.wait_sec);
+
+    loop {
+        send_magic_packet(wol).await?;
+        sleep(RESEND_INTERVAL).await;
+
+        if is_reachable(host, port).await {
+            info!("Host {} is awake", host);
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            warn!("Host {} did not wake up within {}s", host, wol.wait_sec);
+            return Ok(false);
+        }
+    }
+}
@@ -5,14 +5,28 @@ const { listen } = window.__TAURI__.event;
 
 let config = { tunnels: [], settings: {} };
 let tunnelStates = new Map();
+let foreignClaims = new Map();
+// Last `tunnel-latency` event of each running tunnel
+let latencies = new Map();
+let healthHistories = new Map();
+// Built-in presets, then the user's templates
+let templates = [];
+// "tunnelId fingerprint" of host keys already asked about
+let hostKeyPrompts = new Set();
+let monitorPaused = false;
+// Ids of the tunnels `list_tunnels` matched to the filter box, or null to
+// show them all
+let filterIds = null;
 let logs = [];
-const MAX_LOGS = 1000;
+const DEFAULT_MAX_LOGS = 1000;
 
 // ── Init ──
 
 document.addEventListener('DOMContentLoaded', async () => {
     await loadConfig();
     renderTunnels();
+    loadProfiles();
+    loadTemplates();
     setupEventListeners();
     setupTauriListeners();
     setupKeyboard();
@@ -26,31 +40,162 @@ async function loadConfig() {
     }
 }
 
+// ── Profiles ──
+
+async function loadProfiles() {
+    try {
+        const profiles = await invoke('list_profiles');
+        document.getElementById('profile-select').innerHTML = profiles
+            .map(p => `<option value="${escapeHtml(p.name)}"${p.active ? ' selected' : ''}>${escapeHtml(p.name)}</option>`)
+            .join('') + '<option value="">New profile…</option>';
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Failed to list profiles: ${e}`);
+    }
+}
+
+async function switchProfile() {
+    let name = document.getElementById('profile-select').value;
+    if (!name) {
+        name = (prompt('Name of the new profile (letters, digits, - and _):') || '').trim();
+    }
+    const running = [...tunnelStates.values()].some(s => s.status !== 'error');
+    if (!name || (running && !confirm(`Switching to "${name}" stops every running tunnel. Continue?`))) {
+        await loadProfiles();
+        return;
+    }
+    try {
+        config = await invoke('switch_profile', { name, connect: true });
+        latencies.clear();
+        healthHistories.clear();
+        await applyFilter();
+        addLog('system', 'OpenTunnel', 'success', `Switched to profile "${name}"`);
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Failed to switch profile: ${e}`);
+    }
+    await loadProfiles();
+}
+
 // ── Tauri Event Listeners ──
 
 async function setupTauriListeners() {
+    // One event per changed tunnel
     await listen('tunnel-status', (event) => {
-        const states = event.payload;
-        tunnelStates.clear();
-        for (const s of states) {
+        const s = event.payload;
+        if (s.status === 'stopped') {
+            tunnelStates.delete(s.id);
+            latencies.delete(s.id);
+            healthHistories.delete(s.id);
+        } else {
             tunnelStates.set(s.id, s);
         }
+        if (parseFilter()?.status) {
+            applyFilter();
+        } else {
+            renderTunnels();
+        }
+        refreshForeignClaims();
+    });
+
+    // config.json was edited outside the app
+    await listen('config-changed', async (event) => {
+        config = event.payload;
+        await applyFilter();
+        addLog('system', 'OpenTunnel', 'info', 'Config reloaded after an outside change');
+    });
+
+    await listen('tunnel-transition', (event) => {
+        const t = event.payload;
+        if (!t.reason) return;
+        const tunnel = config.tunnels.find(x => x.id === t.id);
+        const level = t.to === 'error' ? 'error' : 'info';
+        addLog(t.id, tunnel ? tunnel.name : t.id, level, `${t.from} → ${t.to}: ${t.reason}`);
+    });
+
+    // Log lines arrive in batches
+    await listen('tunnel-latency', (event) => {
+        latencies.set(event.payload.tunnelId, event.payload);
         renderTunnels();
     });
 
     await listen('tunnel-log', (event) => {
-        const entry = event.payload;
-        addLog(entry.tunnelId, entry.tunnelName, entry.level, entry.message);
+        for (const entry of event.payload) {
+            addLog(entry.tunnelId, entry.tunnelName, entry.level, entry.message, entry.stream);
+        }
     });
 
+    // A client refused a host key it hasn't seen; ask once per key
+    await listen('host-key-unknown', async (event) => {
+        const p = event.payload;
+        const key = `${p.tunnelId} ${p.fingerprint}`;
+        if (hostKeyPrompts.has(key)) return;
+        hostKeyPrompts.add(key);
+        addLog(p.tunnelId, p.tunnelName, 'warn', `Unknown host key ${p.fingerprint} for ${p.host}:${p.port}`);
+
+        const accepted = confirm(
+            `The host key of ${p.host}:${p.port} (tunnel "${p.tunnelName}") isn't trusted yet.\n\n`
+            + `Fingerprint: ${p.fingerprint}\n\n`
+            + 'Only accept it if it matches the key you expect. Trust this key and connect?'
+        );
+        if (!accepted) return;
+        try {
+            await invoke('accept_host_key', { id: p.tunnelId, fingerprint: p.fingerprint });
+            const tunnel = config.tunnels.find(t => t.id === p.tunnelId);
+            if (tunnel) tunnel.hostKeyFingerprint = p.fingerprint;
+            addLog(p.tunnelId, p.tunnelName, 'info', `Host key ${p.fingerprint} trusted`);
+        } catch (e) {
+            addLog(p.tunnelId, p.tunnelName, 'error', `Failed to accept host key: ${e}`);
+        }
+    });
+
+    // A server's key no longer matches the trusted one; never offer to
+    // accept it from here
+    await listen('host-key-changed', (event) => {
+        const p = event.payload;
+        const key = `${p.tunnelId} ${p.fingerprint}`;
+        if (hostKeyPrompts.has(key)) return;
+        hostKeyPrompts.add(key);
+        addLog(p.tunnelId, p.tunnelName, 'error', `Host key of ${p.host}:${p.port} changed to ${p.fingerprint}`);
+        alert(
+            `WARNING: the host key of ${p.host}:${p.port} (tunnel "${p.tunnelName}") has changed.\n\n`
+            + `New fingerprint: ${p.fingerprint}\n\n`
+            + 'Someone may be intercepting the connection. Check the key with the server administrator, '
+            + 'then update it in PuTTY before connecting again.'
+        );
+    });
+
+    await listen('monitor-paused', (event) => {
+        renderMonitorPaused(event.payload.paused);
+        addLog('system', 'OpenTunnel', 'info', `Auto-reconnect ${event.payload.paused ? 'paused' : 'resumed'}`);
+    });
+
+    await listen('app-health', (event) => renderAppHealth(event.payload));
+
     await listen('notification', (event) => {
         const n = event.payload;
         addLog('system', 'OpenTunnel', n.type, n.body);
     });
 
-    // Fetch initial states
+    try {
+        renderMonitorPaused(await invoke('is_monitor_paused'));
+        renderAppHealth(await invoke('get_app_health'));
+    } catch (_) {}
+
+    // Tunnels held by other OpenTunnel instances
+    await refreshForeignClaims();
+    setInterval(refreshForeignClaims, 10000);
+    setInterval(refreshHealthHistories, 10000);
+
+    // Fetch initial states, and resync whenever the window comes back
+    await resyncStates();
+    document.addEventListener('visibilitychange', () => {
+        if (!document.hidden) resyncStates();
+    });
+}
+
+async function resyncStates() {
     try {
         const states = await invoke('get_tunnel_states');
+        tunnelStates.clear();
         for (const s of states) {
             tunnelStates.set(s.id, s);
         }
@@ -58,8 +203,78 @@ async function setupTauriListeners() {
     } catch (_) {}
 }
 
+function renderAppHealth(health) {
+    const el = document.getElementById('app-health');
+    el.className = `app-health health-${health.status}`;
+    el.title = health.status === 'all-healthy'
+        ? 'All tunnels healthy'
+        : `${health.failing} of ${health.failing + health.running} tunnels down`;
+}
+
+function renderMonitorPaused(paused) {
+    monitorPaused = paused;
+    const btn = document.getElementById('btn-pause');
+    btn.textContent = paused ? 'Resume' : 'Pause';
+    btn.title = paused ? 'Resume auto-reconnect' : 'Pause auto-reconnect';
+    btn.classList.toggle('btn-warning', paused);
+    btn.classList.toggle('btn-secondary', !paused);
+}
+
+async function refreshForeignClaims() {
+    try {
+        const claims = await invoke('get_foreign_claims');
+        foreignClaims.clear();
+        for (const c of claims) {
+            if (c.tunnelId) foreignClaims.set(c.tunnelId, c);
+        }
+        renderTunnels();
+    } catch (_) {}
+}
+
+/** Recent health checks of the running tunnels, for their sparklines. */
+async function refreshHealthHistories() {
+    try {
+        for (const [id, state] of tunnelStates) {
+            if (state.status !== 'running') continue;
+            healthHistories.set(id, await invoke('get_health_history', { id }));
+        }
+        renderTunnels();
+    } catch (_) {}
+}
+
+/** A bar per recent health check: tall and green when it passed. */
+function sparkline(history) {
+    if (!history?.length) return '';
+    const failed = history.filter(s => !s.ok).length;
+    const bars = history.slice(-30).map(s => {
+        const tip = s.ok ? (s.latencyMs != null ? `${s.latencyMs} ms` : 'OK') : s.error;
+        return `<span class="spark-${s.ok ? 'ok' : 'fail'}" title="${escapeHtml(tip || '')}"></span>`;
+    }).join('');
+    const title = `${history.length - failed} of ${history.length} recent checks passed`;
+    return ` <span class="sparkline" title="${title}">${bars}</span>`;
+}
+
 // ── Render ──
 
+/** The machine or resource a provider tunnel ends on. */
+function providerTarget(provider) {
+    switch (provider.kind) {
+        case 'ssm': return provider.instanceId;
+        case 'bastion': return provider.vm;
+        case 'kubernetes': return provider.resource;
+        case 'docker': return provider.container;
+        case 'cloudflared': return provider.hostname;
+        default: return '';
+    }
+}
+
+/** Where a tunnel connects through, for the list. */
+function tunnelVia(t) {
+    if (t.provider?.kind === 'docker') return `${t.provider.container} on ${t.username}@${t.host}`;
+    if (t.provider) return `${t.provider.kind}:${providerTarget(t.provider)}`;
+    return `${t.username}@${t.host}`;
+}
+
 function renderTunnels() {
     const list = document.getElementById('tunnel-list');
     const empty = document.getElementById('empty-state');
@@ -72,46 +287,201 @@ function renderTunnels() {
     }
 
     empty.style.display = 'none';
-    list.innerHTML = config.tunnels.map(t => {
+    const card = t => {
         const state = tunnelStates.get(t.id);
         const status = state?.status || 'stopped';
         const typeLabel = t.type === 'local' ? 'L' : t.type === 'remote' ? 'R' : 'D';
-        const detail = t.type === 'dynamic'
-            ? `${typeLabel} :${t.localPort} via ${t.username}@${t.host}`
-            : `${typeLabel} :${t.localPort} -> ${t.remoteHost}:${t.remotePort} via ${t.username}@${t.host}`;
-
         const isRunning = status === 'running' || status === 'starting' || status === 'reconnecting';
+        // Port 0 means one picked at start
+        const localPort = isRunning && state?.localPort ? state.localPort : (t.localPort || 'auto');
+        const detail = t.type === 'dynamic' || t.provider?.kind === 'cloudflared'
+            ? `${typeLabel} :${localPort} via ${tunnelVia(t)}`
+            : `${typeLabel} :${localPort} -> ${t.remoteHost}:${t.remotePort} via ${tunnelVia(t)}`;
+
         const toggleBtn = isRunning
             ? `<button class="btn btn-sm btn-danger" onclick="stopTunnel('${t.id}')" title="Stop">&#9632;</button>`
             : `<button class="btn btn-sm btn-success" onclick="startTunnel('${t.id}')" title="Start">&#9654;</button>`;
 
-        const reconnectInfo = state?.reconnectCount > 0
+        const reconnectInfo = status === 'reconnecting' && state?.nextRetryAt
+            ? ` <span style="color:var(--warning)">(<span class="retry-eta" data-at="${state.nextRetryAt}">${retryEta(state.nextRetryAt)}</span>, attempt ${state.reconnectCount})</span>`
+            : state?.reconnectCount > 0
             ? ` <span style="color:var(--warning)">(retry #${state.reconnectCount})</span>`
             : '';
 
+        const latency = isRunning ? latencies.get(t.id) : null;
+        const alert = t.latencyAlertMs && latency?.averageMs > t.latencyAlertMs;
+        const latencyInfo = latency
+            ? ` <span style="color:var(--${alert ? 'warning' : 'text-muted'})" title="Last: ${latency.recent.join(', ')} ms">${latency.averageMs} ms</span>`
+            : '';
+
+        const claim = !state ? foreignClaims.get(t.id) : null;
+        const foreignInfo = claim
+            ? ` <span style="color:var(--warning)" title="Port ${claim.port}">(in use by ${escapeHtml(claim.owner)})</span>`
+            : '';
+
+        const retryNowBtn = status === 'reconnecting' && state?.nextRetryAt
+            ? `<button class="btn-icon" onclick="reconnectNow('${t.id}')" title="Reconnect now">&#8635;</button>`
+            : '';
+
+        // Auto-reconnect gave up on it
+        const rearmBtn = status === 'error' && (state?.reconnectCount > 0 || state?.lastErrorKind)
+            ? `<button class="btn-icon" onclick="rearmTunnel('${t.id}')" title="Retry auto-reconnect">&#8635;</button>`
+            : '';
+
         const errorInfo = state?.lastError
             ? ` <span style="color:var(--danger)" title="${state.lastError}">&#9888;</span>`
             : '';
 
         return `
-            <div class="tunnel-card" data-id="${t.id}">
-                <div class="tunnel-status status-${status}" title="${status}"></div>
+            <div class="tunnel-card" data-id="${t.id}" draggable="true" ondragstart="dragTunnel(event, '${t.id}')" ondragover="dragOverTunnel(event)" ondrop="dropTunnel(event, '${t.id}')"${t.color ? ` style="border-left: 3px solid ${t.color}"` : ''}>
+                <div class="tunnel-status status-${status}" title="${escapeHtml(statusTitle(status, state))}"></div>
                 <div class="tunnel-info">
-                    <div class="tunnel-name">${escapeHtml(t.name)}${reconnectInfo}${errorInfo}</div>
-                    <div class="tunnel-detail">${escapeHtml(detail)}</div>
+                    <div class="tunnel-name">${escapeHtml(t.name)}${t.notes ? ` <span class="tunnel-notes" title="${escapeHtml(t.notes).replace(/"/g, '&quot;')}">&#128221;</span>` : ''}${(t.tags || []).map(tag => ` <span class="tunnel-tag">${escapeHtml(tag)}</span>`).join('')}${reconnectInfo}${latencyInfo}${foreignInfo}${errorInfo}</div>
+                    <div class="tunnel-detail">${escapeHtml(detail)}${status === 'running' ? sparkline(healthHistories.get(t.id)) : ''}</div>
                 </div>
                 <div class="tunnel-actions">
                     ${toggleBtn}
+                    ${retryNowBtn}
+                    ${rearmBtn}
+                    <button class="btn-icon" onclick="diagnoseTunnel('${t.id}')" title="Check SSH agents">&#128269;</button>
                     <button class="btn-icon" onclick="editTunnel('${t.id}')" title="Edit">&#9998;</button>
                     <button class="btn-icon" onclick="deleteTunnel('${t.id}')" title="Delete">&#128465;</button>
                 </div>
             </div>
         `;
+    };
+
+    const shown = filterIds ? config.tunnels.filter(t => filterIds.has(t.id)) : config.tunnels;
+    // Ungrouped tunnels first, then each group (and the folders it's in)
+    // in path order
+    const groups = [...new Set(shown.flatMap(t => {
+        const parts = t.group ? t.group.split('/') : [];
+        return parts.map((_, i) => parts.slice(0, i + 1).join('/'));
+    }))].sort();
+    const ungrouped = shown.filter(t => !t.group).map(card).join('');
+    shownGroups = groups;
+    list.innerHTML = ungrouped + groups.map((path, i) => {
+        const meta = (config.groups || []).find(g => g.path === path) || {};
+        // A folded parent hides its subfolders too
+        const hidden = groups.some(p => p !== path && path.startsWith(p + '/') && groupCollapsed(p));
+        if (hidden) return '';
+        const tunnels = shown.filter(t => t.group === path);
+        const running = tunnels.filter(t => tunnelStates.get(t.id)?.status === 'running').length;
+        const depth = path.split('/').length - 1;
+        return `
+            <div class="tunnel-group" style="margin-left:${depth * 12}px" onclick="toggleGroup(${i})" title="${escapeHtml(meta.description || '')}">
+                <span class="group-arrow">${meta.collapsed ? '&#9656;' : '&#9662;'}</span>
+                ${escapeHtml(path.split('/').pop())}
+                <span class="group-count">${running}/${tunnels.length}</span>
+            </div>
+            ${meta.collapsed ? '' : tunnels.map(card).join('')}
+        `;
     }).join('');
 }
 
+// The filter box as a `list_tunnels` filter: `tag:` and `status:` words,
+// anything else matched against hosts. Null when empty.
+function parseFilter() {
+    const words = document.getElementById('tunnel-filter').value.trim().split(/\s+/).filter(Boolean);
+    if (words.length === 0) return null;
+    const filter = {};
+    const host = [];
+    for (const word of words) {
+        const [key, value] = word.split(/:(.*)/);
+        if ((key === 'tag' || key === 'status') && value) {
+            filter[key] = value.toLowerCase();
+        } else {
+            host.push(word);
+        }
+    }
+    if (host.length) filter.host = host.join(' ');
+    return filter;
+}
+
+async function applyFilter() {
+    const filter = parseFilter();
+    try {
+        filterIds = filter
+            ? new Set((await invoke('list_tunnels', { filter })).map(t => t.id))
+            : null;
+    } catch (e) {
+        // An unknown status
+        filterIds = new Set();
+    }
+    renderTunnels();
+}
+
+// Tunnel being dragged to a new place in the list
+let draggedId = null;
+
+window.dragTunnel = function (event, id) {
+    draggedId = id;
+    event.dataTransfer.effectAllowed = 'move';
+};
+
+window.dragOverTunnel = function (event) {
+    if (draggedId) event.preventDefault();
+};
+
+// Moves the dragged tunnel before the one it's dropped on
+window.dropTunnel = async function (event, id) {
+    event.preventDefault();
+    const dragged = config.tunnels.find(t => t.id === draggedId);
+    draggedId = null;
+    if (!dragged || dragged.id === id) return;
+    const previous = config.tunnels;
+    const rest = previous.filter(t => t !== dragged);
+    rest.splice(rest.findIndex(t => t.id === id), 0, dragged);
+    config.tunnels = rest;
+    renderTunnels();
+    try {
+        await invoke('reorder_tunnels', { ids: rest.map(t => t.id) });
+    } catch (e) {
+        config.tunnels = previous;
+        renderTunnels();
+        addLog('system', 'OpenTunnel', 'error', `Failed to reorder: ${e}`);
+    }
+};
+
+function groupCollapsed(path) {
+    return (config.groups || []).find(g => g.path === path)?.collapsed || false;
+}
+
+// Group paths in the order the list shows them, so headers can refer to
+// theirs by index
+let shownGroups = [];
+
+window.toggleGroup = async function (i) {
+    const path = shownGroups[i];
+    const group = { ...((config.groups || []).find(g => g.path === path) || { path }) };
+    group.collapsed = !group.collapsed;
+    try {
+        await invoke('save_group', { group });
+        config.groups = [...(config.groups || []).filter(g => g.path !== path), group];
+        renderTunnels();
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Failed to save group: ${e}`);
+    }
+};
+
 // ── Tunnel Actions ──
 
+window.rearmTunnel = async function(id) {
+    try {
+        await invoke('reset_reconnect_attempts', { id });
+    } catch (e) {
+        addLog(id, getTunnelName(id), 'error', `Failed to re-arm auto-reconnect: ${e}`);
+    }
+};
+
+window.reconnectNow = async function(id) {
+    try {
+        await invoke('reconnect_now', { id });
+    } catch (e) {
+        addLog(id, getTunnelName(id), 'error', `Failed to reconnect now: ${e}`);
+    }
+};
+
 window.startTunnel = async function(id) {
     try {
         await invoke('start_tunnel_cmd', { id });
@@ -130,6 +500,17 @@ window.stopTunnel = async function(id) {
     }
 };
 
+window.diagnoseTunnel = async function(id) {
+    try {
+        const report = await invoke('diagnose_agents', { id });
+        for (const msg of report.messages) {
+            addLog(id, getTunnelName(id), report.keyLoaded === false ? 'error' : 'info', msg);
+        }
+    } catch (e) {
+        addLog(id, getTunnelName(id), 'error', `Diagnostics failed: ${e}`);
+    }
+};
+
 window.editTunnel = function(id) {
     const tunnel = config.tunnels.find(t => t.id === id);
     if (!tunnel) return;
@@ -157,27 +538,107 @@ window.deleteTunnel = async function(id) {
 function openTunnelModal(tunnel = null) {
     const modal = document.getElementById('modal-tunnel');
     const title = document.getElementById('modal-title');
+    document.getElementById('tunnel-groups').innerHTML = [...new Set(config.tunnels.map(t => t.group).filter(Boolean))]
+        .map(g => `<option value="${escapeHtml(g)}">`)
+        .join('');
+    const others = config.tunnels.filter(t => t.id !== tunnel?.id);
+    document.getElementById('tunnel-depends-on').innerHTML = '<option value="">None</option>' + others
+        .map(t => `<option value="${t.id}">${escapeHtml(t.name)}</option>`)
+        .join('');
+
+    // Templates only start new tunnels
+    document.getElementById('template-row').style.display = tunnel?.id ? 'none' : '';
+    document.getElementById('tunnel-template').innerHTML = '<option value="">None</option>' + templates
+        .map(t => `<option value="${escapeHtml(t.id)}">${escapeHtml(t.name)}${t.builtin ? '' : ' (yours)'}</option>`)
+        .join('');
+    document.getElementById('tunnel-template-server').innerHTML = '<option value="">None</option>' + config.tunnels
+        .map(t => `<option value="${t.id}">${escapeHtml(t.name)} (${escapeHtml(t.host)})</option>`)
+        .join('');
+    document.getElementById('btn-delete-template').style.display = 'none';
 
     if (tunnel) {
-        title.textContent = 'Edit Tunnel';
+        title.textContent = tunnel.id ? 'Edit Tunnel' : 'Add Tunnel';
         document.getElementById('tunnel-id').value = tunnel.id;
         document.getElementById('tunnel-name').value = tunnel.name;
+        document.getElementById('tunnel-group').value = tunnel.group || '';
+        document.getElementById('tunnel-tags').value = (tunnel.tags || []).join(', ');
+        const color = document.getElementById('tunnel-color');
+        // One set by hand in config.json
+        if (tunnel.color && ![...color.options].some(o => o.value === tunnel.color)) {
+            color.add(new Option(tunnel.color, tunnel.color));
+        }
+        color.value = tunnel.color || '';
+        document.getElementById('tunnel-notes').value = tunnel.notes || '';
         document.getElementById('tunnel-host').value = tunnel.host;
         document.getElementById('tunnel-port').value = tunnel.port;
         document.getElementById('tunnel-username').value = tunnel.username;
         document.getElementById('tunnel-auth').value = tunnel.authMethod;
         document.getElementById('tunnel-keypath').value = tunnel.keyPath || '';
+        document.getElementById('tunnel-password').value = tunnel.password || '';
+        document.getElementById('tunnel-pageant').checked = tunnel.usePageant || false;
+        document.getElementById('tunnel-sshclient').value = tunnel.sshClient || '';
+        document.getElementById('tunnel-puttysession').value = tunnel.puttySession || '';
+        document.getElementById('tunnel-jumphosts').value = (tunnel.jumpHosts || []).join(', ');
+        document.getElementById('tunnel-hostkey').value = tunnel.hostKeyFingerprint || '';
+        document.getElementById('tunnel-wol-mac').value = tunnel.wakeOnLan?.mac || '';
+        document.getElementById('tunnel-wol-broadcast').value = tunnel.wakeOnLan?.broadcast || '';
+        document.getElementById('tunnel-wol-wait').value = tunnel.wakeOnLan?.waitSec || '';
+        loadNetworkCondition('tunnel-net', tunnel.autoConnectOn);
+        document.getElementById('tunnel-socksprobe').value = tunnel.socksProbe || '';
+        document.getElementById('tunnel-check-kind').value = tunnel.healthCheck?.kind || 'end-to-end';
+        document.getElementById('tunnel-check-interval').value = tunnel.healthCheck?.intervalSec || '';
+        document.getElementById('tunnel-check-threshold').value = tunnel.healthCheck?.failureThreshold || '';
+        document.getElementById('tunnel-probe').value = tunnel.healthProbe?.kind || '';
+        document.getElementById('tunnel-probe-path').value = tunnel.healthProbe?.path || '';
+        document.getElementById('tunnel-probe-status').value = tunnel.healthProbe?.expectStatus || '';
+        document.getElementById('tunnel-latency-alert').value = tunnel.latencyAlertMs || '';
+        document.getElementById('tunnel-idle').value = tunnel.idleDisconnectMin || '';
+        document.getElementById('tunnel-max-session').value = tunnel.maxSessionHours || '';
+        document.getElementById('tunnel-depends-on').value = tunnel.dependsOn || '';
+        document.getElementById('tunnel-extraargs').value = (tunnel.extraArgs || [])
+            .map(a => (/\s/.test(a) ? `"${a}"` : a))
+            .join(' ');
+        document.getElementById('tunnel-keepalive').value = tunnel.keepaliveSec ?? '';
         document.getElementById('tunnel-type').value = tunnel.type;
         document.getElementById('tunnel-localport').value = tunnel.localPort;
         document.getElementById('tunnel-remotehost').value = tunnel.remoteHost;
         document.getElementById('tunnel-remoteport').value = tunnel.remotePort;
         document.getElementById('tunnel-autoconnect').checked = tunnel.autoConnect;
+        document.getElementById('tunnel-persistent').checked = tunnel.persistentListener || false;
+        document.getElementById('tunnel-share').checked = tunnel.shareConnection || false;
+        document.getElementById('tunnel-compression').checked = tunnel.compression || false;
+        loadScheduleForm(tunnel.schedule);
+        document.getElementById('tunnel-onconnect').value = tunnel.onConnect?.target || '';
+        document.getElementById('tunnel-hostsalias').value = tunnel.hostsAlias || '';
+        const provider = tunnel.provider || {};
+        document.getElementById('tunnel-provider').value = provider.kind || 'ssh';
+        document.getElementById('tunnel-ssm-instance').value = provider.instanceId || '';
+        document.getElementById('tunnel-ssm-profile').value = provider.profile || '';
+        document.getElementById('tunnel-ssm-region').value = provider.region || '';
+        document.getElementById('tunnel-bastion-subscription').value = provider.subscription || '';
+        document.getElementById('tunnel-bastion-rg').value = provider.resourceGroup || '';
+        document.getElementById('tunnel-bastion-name').value = provider.bastion || '';
+        document.getElementById('tunnel-bastion-vm').value = provider.vm || '';
+        document.getElementById('tunnel-kube-context').value = provider.context || '';
+        document.getElementById('tunnel-kube-namespace').value = provider.namespace || '';
+        document.getElementById('tunnel-kube-resource').value = provider.resource || '';
+        document.getElementById('tunnel-docker-container').value = provider.container || '';
+        document.getElementById('tunnel-cloudflared-hostname').value = provider.hostname || '';
+        document.getElementById('tunnel-transport').value = tunnel.transport?.kind || 'direct';
+        document.getElementById('tunnel-ws-url').value = tunnel.transport?.url || '';
+        const proxy = tunnel.transport?.kind === 'proxy' ? tunnel.transport : {};
+        document.getElementById('tunnel-proxy-protocol').value = proxy.protocol || 'http';
+        document.getElementById('tunnel-proxy-host').value = proxy.host || '';
+        document.getElementById('tunnel-proxy-port').value = proxy.port || '';
+        document.getElementById('tunnel-proxy-username').value = proxy.username || '';
+        document.getElementById('tunnel-proxy-password').value = proxy.password || '';
     } else {
         title.textContent = 'Add Tunnel';
         document.getElementById('tunnel-form').reset();
         document.getElementById('tunnel-id').value = '';
         document.getElementById('tunnel-port').value = 22;
         document.getElementById('tunnel-remotehost').value = '127.0.0.1';
+        loadScheduleForm(null);
     }
 
     updateFormVisibility();
@@ -185,25 +646,299 @@ function openTunnelModal(tunnel = null) {
     document.getElementById('tunnel-name').focus();
 }
 
+// ── Templates ──
+
+async function loadTemplates() {
+    try {
+        templates = await invoke('list_templates');
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Failed to load templates: ${e}`);
+    }
+}
+
+// Refills the form from the chosen template, logging in like the chosen
+// server
+async function applyTemplate() {
+    const templateId = document.getElementById('tunnel-template').value;
+    const serverId = document.getElementById('tunnel-template-server').value || null;
+    if (!templateId) return;
+    try {
+        const tunnel = await invoke('create_from_template', { templateId, serverId });
+        openTunnelModal(tunnel);
+        document.getElementById('tunnel-template').value = templateId;
+        document.getElementById('tunnel-template-server').value = serverId || '';
+        const template = templates.find(t => t.id === templateId);
+        document.getElementById('btn-delete-template').style.display = template?.builtin ? 'none' : '';
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Failed to apply template: ${e}`);
+    }
+}
+
+async function saveAsTemplate() {
+    const value = id => document.getElementById(id).value.trim();
+    const name = (prompt('Template name:', value('tunnel-name')) || '').trim();
+    if (!name) return;
+    const template = {
+        id: '',
+        name,
+        type: value('tunnel-type'),
+        localPort: parseInt(value('tunnel-localport')) || 0,
+        remoteHost: value('tunnel-remotehost') || '127.0.0.1',
+        remotePort: parseInt(value('tunnel-remoteport')) || 0,
+    };
+    try {
+        await invoke('save_template', { template });
+        await loadTemplates();
+        addLog('system', 'OpenTunnel', 'success', `Template "${name}" saved`);
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Failed to save template: ${e}`);
+    }
+}
+
+async function deleteTemplate() {
+    const select = document.getElementById('tunnel-template');
+    const template = templates.find(t => t.id === select.value);
+    if (!template || template.builtin || !confirm(`Delete template "${template.name}"?`)) return;
+    try {
+        await invoke('delete_template', { id: template.id });
+        await loadTemplates();
+        openTunnelModal();
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Failed to delete template: ${e}`);
+    }
+}
+
 function closeTunnelModal() {
     document.getElementById('modal-tunnel').style.display = 'none';
 }
 
 function updateFormVisibility() {
     const auth = document.getElementById('tunnel-auth').value;
-    const type = document.getElementById('tunnel-type').value;
-
+    const provider = document.getElementById('tunnel-provider').value;
+    // Docker tunnels still log in over SSH
+    const ssh = provider === 'ssh' || provider === 'docker';
+    const typeSelect = document.getElementById('tunnel-type');
+
+    // Providers only forward local ports
+    if (provider !== 'ssh') typeSelect.value = 'local';
+    typeSelect.disabled = provider !== 'ssh';
+    const type = typeSelect.value;
+
+    document.getElementById('ssh-group').style.display = ssh ? '' : 'none';
+    document.getElementById('ssm-group').style.display = provider === 'ssm' ? '' : 'none';
+    document.getElementById('bastion-group').style.display = provider === 'bastion' ? '' : 'none';
+    document.getElementById('kubernetes-group').style.display = provider === 'kubernetes' ? '' : 'none';
+    document.getElementById('docker-group').style.display = provider === 'docker' ? '' : 'none';
+    document.getElementById('cloudflared-group').style.display = provider === 'cloudflared' ? '' : 'none';
+    document.getElementById('tunnel-host').required = ssh;
+    document.getElementById('tunnel-username').required = ssh;
+    document.getElementById('tunnel-ssm-instance').required = provider === 'ssm';
+    for (const field of ['subscription', 'rg', 'name', 'vm']) {
+        document.getElementById(`tunnel-bastion-${field}`).required = provider === 'bastion';
+    }
+    document.getElementById('tunnel-kube-resource').required = provider === 'kubernetes';
+    document.getElementById('tunnel-docker-container').required = provider === 'docker';
+    document.getElementById('tunnel-cloudflared-hostname').required = provider === 'cloudflared';
     document.getElementById('key-path-group').style.display = auth === 'key' ? '' : 'none';
-    document.getElementById('remote-group').style.display = type === 'dynamic' ? 'none' : '';
+    document.getElementById('password-group').style.display = auth === 'password' ? '' : 'none';
+    document.getElementById('pageant-group').style.display = auth === 'key' ? '' : 'none';
+    const websocket = document.getElementById('tunnel-transport').value === 'websocket';
+    document.getElementById('ws-url-group').style.display = websocket ? '' : 'none';
+    document.getElementById('tunnel-ws-url').required = ssh && websocket;
+    const proxied = document.getElementById('tunnel-transport').value === 'proxy';
+    document.querySelectorAll('.proxy-group').forEach(el => el.style.display = proxied ? '' : 'none');
+    document.getElementById('tunnel-proxy-host').required = ssh && proxied;
+    document.getElementById('tunnel-proxy-port').required = ssh && proxied;
+    const schedule = document.getElementById('tunnel-schedule').value;
+    document.querySelectorAll('.schedule-weekly').forEach(el => el.style.display = schedule === 'weekly' ? '' : 'none');
+    document.querySelectorAll('.schedule-cron').forEach(el => el.style.display = schedule === 'cron' ? '' : 'none');
+    document.getElementById('tunnel-cron-start').required = schedule === 'cron';
+    document.getElementById('tunnel-cron-stop').required = schedule === 'cron';
+
+    // The Access application decides the origin
+    const noRemote = type === 'dynamic' || provider === 'cloudflared';
+    document.getElementById('remote-group').style.display = noRemote ? 'none' : '';
+    document.getElementById('socks-group').style.display = type === 'dynamic' ? '' : 'none';
+    document.getElementById('probe-group').style.display = type === 'local' ? '' : 'none';
+    document.getElementById('latency-group').style.display = type === 'remote' ? 'none' : '';
+    document.getElementById('idle-group').style.display = type === 'remote' ? 'none' : '';
+    const httpProbe = document.getElementById('tunnel-probe').value === 'http';
+    document.querySelectorAll('.probe-http').forEach(el => el.style.display = httpProbe ? '' : 'none');
+    // Bastion, port-forward and container tunnels always end on the target itself
+    const fixedHost = provider === 'bastion' || provider === 'kubernetes' || provider === 'docker';
+    document.getElementById('remote-host-group').style.display = fixedHost ? 'none' : '';
+}
+
+/** Health check settings, or null when they're all the defaults. */
+function formHealthCheck() {
+    const kind = document.getElementById('tunnel-check-kind').value;
+    const intervalSec = parseInt(document.getElementById('tunnel-check-interval').value) || null;
+    const failureThreshold = parseInt(document.getElementById('tunnel-check-threshold').value) || null;
+    if (kind === 'end-to-end' && !intervalSec && !failureThreshold) return null;
+    return { kind, intervalSec, failureThreshold };
+}
+
+function formHealthProbe() {
+    const kind = document.getElementById('tunnel-probe').value;
+    if (kind !== 'http') return kind ? { kind } : null;
+    return {
+        kind,
+        path: document.getElementById('tunnel-probe-path').value.trim() || '/',
+        expectStatus: parseInt(document.getElementById('tunnel-probe-status').value) || 200,
+    };
+}
+
+function loadScheduleForm(schedule) {
+    const span = schedule?.kind === 'weekly' ? schedule.windows[0] : null;
+    const days = span?.days || ['mon', 'tue', 'wed', 'thu', 'fri'];
+    document.getElementById('tunnel-schedule').value = schedule?.kind || 'none';
+    document.getElementById('tunnel-schedule-from').value = span?.start || '09:00';
+    document.getElementById('tunnel-schedule-to').value = span?.end || '18:00';
+    document.querySelectorAll('#schedule-days input').forEach(box => box.checked = days.includes(box.value));
+    document.getElementById('tunnel-cron-start').value = schedule?.kind === 'cron' ? schedule.start : '';
+    document.getElementById('tunnel-cron-stop').value = schedule?.kind === 'cron' ? schedule.stop : '';
+}
+
+/** Schedule for the form. Only the first weekly window is editable; others are kept. */
+function formSchedule(existing) {
+    switch (document.getElementById('tunnel-schedule').value) {
+        case 'weekly': {
+            const span = {
+                days: [...document.querySelectorAll('#schedule-days input:checked')].map(box => box.value),
+                start: document.getElementById('tunnel-schedule-from').value,
+                end: document.getElementById('tunnel-schedule-to').value,
+            };
+            const others = existing?.kind === 'weekly' ? existing.windows.slice(1) : [];
+            return { kind: 'weekly', windows: [span, ...others] };
+        }
+        case 'cron':
+            return {
+                kind: 'cron',
+                start: document.getElementById('tunnel-cron-start').value.trim(),
+                stop: document.getElementById('tunnel-cron-stop').value.trim(),
+            };
+        default:
+            return null;
+    }
+}
+
+/** Transport for the form, keeping settings it doesn't edit. */
+function formTransport(existing) {
+    const provider = document.getElementById('tunnel-provider').value;
+    if (provider !== 'ssh' && provider !== 'docker') return null;
+    const value = id => document.getElementById(id).value.trim();
+    if (document.getElementById('tunnel-transport').value === 'proxy') {
+        return {
+            kind: 'proxy',
+            protocol: value('tunnel-proxy-protocol'),
+            host: value('tunnel-proxy-host'),
+            port: parseInt(value('tunnel-proxy-port')) || 0,
+            username: value('tunnel-proxy-username') || null,
+            password: document.getElementById('tunnel-proxy-password').value || null,
+        };
+    }
+    if (document.getElementById('tunnel-transport').value !== 'websocket') return null;
+    return {
+        ...(existing?.kind === 'websocket' ? existing : {}),
+        kind: 'websocket',
+        url: document.getElementById('tunnel-ws-url').value.trim(),
+    };
+}
+
+/** Wake-on-LAN block, or null without a MAC address. */
+function formWakeOnLan(existing) {
+    const value = id => document.getElementById(id).value.trim();
+    if (!value('tunnel-wol-mac')) return null;
+    const wake = { ...existing, mac: value('tunnel-wol-mac') };
+    // Left out, the backend defaults apply
+    if (value('tunnel-wol-broadcast')) wake.broadcast = value('tunnel-wol-broadcast');
+    else delete wake.broadcast;
+    if (parseInt(value('tunnel-wol-wait'))) wake.waitSec = parseInt(value('tunnel-wol-wait'));
+    else delete wake.waitSec;
+    return wake;
+}
+
+/** Fills in the `prefix` network condition fields. */
+function loadNetworkCondition(prefix, condition) {
+    document.getElementById(`${prefix}-ssid`).value = condition?.ssid || '';
+    document.getElementById(`${prefix}-domain`).value = condition?.domain || '';
+    document.getElementById(`${prefix}-subnet`).value = condition?.subnet || '';
+    document.getElementById(`${prefix}-except`).checked = condition?.except || false;
+}
+
+/** Network condition from the `prefix` fields, or null when they're empty. */
+function formNetworkCondition(prefix) {
+    const value = id => document.getElementById(`${prefix}-${id}`).value.trim() || null;
+    const condition = { ssid: value('ssid'), domain: value('domain'), subnet: value('subnet') };
+    if (!condition.ssid && !condition.domain && !condition.subnet) return null;
+    condition.except = document.getElementById(`${prefix}-except`).checked;
+    return condition;
+}
+
+/** Provider block for the form's "Connect via" choice, or null for SSH. */
+function formProvider() {
+    const value = id => document.getElementById(id).value.trim();
+    switch (document.getElementById('tunnel-provider').value) {
+        case 'ssm':
+            return {
+                kind: 'ssm',
+                instanceId: value('tunnel-ssm-instance'),
+                profile: value('tunnel-ssm-profile') || null,
+                region: value('tunnel-ssm-region') || null,
+            };
+        case 'bastion':
+            return {
+                kind: 'bastion',
+                subscription: value('tunnel-bastion-subscription'),
+                resourceGroup: value('tunnel-bastion-rg'),
+                bastion: value('tunnel-bastion-name'),
+                vm: value('tunnel-bastion-vm'),
+            };
+        case 'kubernetes':
+            return {
+                kind: 'kubernetes',
+                context: value('tunnel-kube-context') || null,
+                namespace: value('tunnel-kube-namespace') || null,
+                resource: value('tunnel-kube-resource'),
+            };
+        case 'docker':
+            return {
+                kind: 'docker',
+                container: value('tunnel-docker-container'),
+            };
+        case 'cloudflared':
+            return {
+                kind: 'cloudflared',
+                hostname: value('tunnel-cloudflared-hostname'),
+            };
+        default:
+            return null;
+    }
 }
 
 async function saveTunnel(e) {
     e.preventDefault();
 
     const id = document.getElementById('tunnel-id').value;
+    const existing = config.tunnels.find(t => t.id === id) || {};
+    const onConnect = document.getElementById('tunnel-onconnect').value.trim();
+    const provider = formProvider();
     const tunnel = {
+        // Keep fields the form doesn't edit
+        ...existing,
         id: id || '',
         name: document.getElementById('tunnel-name').value.trim(),
+        group: document.getElementById('tunnel-group').value
+            .split('/')
+            .map(p => p.trim())
+            .filter(Boolean)
+            .join('/') || null,
+        tags: document.getElementById('tunnel-tags').value
+            .split(',')
+            .map(tag => tag.trim())
+            .filter(Boolean),
+        notes: document.getElementById('tunnel-notes').value.trim() || null,
+        color: document.getElementById('tunnel-color').value || null,
         host: document.getElementById('tunnel-host').value.trim(),
         port: parseInt(document.getElementById('tunnel-port').value) || 22,
         username: document.getElementById('tunnel-username').value.trim(),
@@ -211,13 +946,51 @@ async function saveTunnel(e) {
         keyPath: document.getElementById('tunnel-auth').value === 'key'
             ? document.getElementById('tunnel-keypath').value.trim() || null
             : null,
+        usePageant: document.getElementById('tunnel-pageant').checked,
+        password: document.getElementById('tunnel-auth').value === 'password'
+            ? document.getElementById('tunnel-password').value || null
+            : null,
+        sshClient: document.getElementById('tunnel-sshclient').value || null,
+        puttySession: document.getElementById('tunnel-puttysession').value.trim() || null,
+        hostKeyFingerprint: document.getElementById('tunnel-hostkey').value.trim() || null,
+        wakeOnLan: formWakeOnLan(existing.wakeOnLan),
+        socksProbe: document.getElementById('tunnel-socksprobe').value.trim() || null,
+        healthCheck: formHealthCheck(),
+        healthProbe: formHealthProbe(),
+        latencyAlertMs: parseInt(document.getElementById('tunnel-latency-alert').value) || null,
+        idleDisconnectMin: parseInt(document.getElementById('tunnel-idle').value) || null,
+        maxSessionHours: parseInt(document.getElementById('tunnel-max-session').value) || null,
+        dependsOn: document.getElementById('tunnel-depends-on').value || null,
+        jumpHosts: document.getElementById('tunnel-jumphosts').value
+            .split(',')
+            .map(h => h.trim())
+            .filter(Boolean),
+        // Whitespace-separated; double quotes group an argument
+        extraArgs: (document.getElementById('tunnel-extraargs').value.match(/"[^"]*"|\S+/g) || [])
+            .map(a => a.replace(/^"(.*)"$/, '$1')),
+        keepaliveSec: document.getElementById('tunnel-keepalive').value === ''
+            ? null
+            : parseInt(document.getElementById('tunnel-keepalive').value) || 0,
         type: document.getElementById('tunnel-type').value,
         localPort: parseInt(document.getElementById('tunnel-localport').value),
         remoteHost: document.getElementById('tunnel-remotehost').value.trim() || '127.0.0.1',
         remotePort: parseInt(document.getElementById('tunnel-remoteport').value) || 0,
         autoConnect: document.getElementById('tunnel-autoconnect').checked,
-        enabled: true,
+        autoConnectOn: formNetworkCondition('tunnel-net'),
+        persistentListener: document.getElementById('tunnel-persistent').checked,
+        shareConnection: document.getElementById('tunnel-share').checked,
+        compression: document.getElementById('tunnel-compression').checked,
+        enabled: existing.enabled ?? true,
+        onConnect: onConnect ? { ...existing.onConnect, target: onConnect } : null,
+        hostsAlias: document.getElementById('tunnel-hostsalias').value.trim() || null,
+        provider,
+        transport: formTransport(existing.transport),
+        schedule: formSchedule(existing.schedule),
     };
+    if (provider && provider.kind !== 'docker') {
+        // Shown as the host in notifications and `{host}` placeholders
+        tunnel.host = providerTarget(provider);
+    }
 
     try {
         if (id) {
@@ -228,7 +1001,7 @@ async function saveTunnel(e) {
             const saved = await invoke('add_tunnel', { tunnel });
             config.tunnels.push(saved);
         }
-        renderTunnels();
+        await applyFilter();
         closeTunnelModal();
         addLog('system', 'OpenTunnel', 'success', `Tunnel "${tunnel.name}" saved`);
     } catch (e) {
@@ -236,6 +1009,51 @@ async function saveTunnel(e) {
     }
 }
 
+// ── Modal: Expose ──
+
+function openExpose() {
+    const servers = config.tunnels.filter(t => !t.provider);
+    document.getElementById('expose-server').innerHTML = servers
+        .map(t => `<option value="${t.id}">${escapeHtml(t.name)} (${escapeHtml(t.host)})</option>`)
+        .join('');
+    document.getElementById('expose-port').value = '';
+    document.getElementById('modal-expose').style.display = '';
+    document.getElementById('expose-port').focus();
+}
+
+function closeExpose() {
+    document.getElementById('modal-expose').style.display = 'none';
+}
+
+async function exposePort(e) {
+    e.preventDefault();
+
+    const serverId = document.getElementById('expose-server').value;
+    const localPort = parseInt(document.getElementById('expose-port').value);
+    const submit = document.getElementById('btn-expose-submit');
+    submit.disabled = true;
+    submit.textContent = 'Exposing...';
+
+    try {
+        const exposure = await invoke('expose_port', { serverId, localPort });
+        config.tunnels.push(exposure.tunnel);
+        renderTunnels();
+        closeExpose();
+        addLog(exposure.tunnel.id, exposure.tunnel.name, 'success',
+            `Port ${localPort} is public at ${exposure.endpoint}`);
+        try {
+            await navigator.clipboard.writeText(exposure.endpoint);
+        } catch {
+            // Clipboard access is best effort
+        }
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Failed to expose port ${localPort}: ${e}`);
+    } finally {
+        submit.disabled = false;
+        submit.textContent = 'Expose';
+    }
+}
+
 // ── Modal: Settings ──
 
 function openSettings() {
@@ -243,10 +1061,25 @@ function openSettings() {
     document.getElementById('settings-plink').value = s.plinkPath || 'plink.exe';
     document.getElementById('settings-reconnect').value = s.reconnectDelaySec || 5;
     document.getElementById('settings-maxretry').value = s.maxReconnectAttempts || 0;
+    document.getElementById('settings-concurrency').value = s.startConcurrency || 4;
+    document.getElementById('settings-maxlogs').value = s.maxLogLines || DEFAULT_MAX_LOGS;
+    document.getElementById('settings-stopgrace').value = s.stopGraceSec ?? 3;
+    document.getElementById('settings-keepalive').value = s.keepaliveSec ?? 15;
+    document.getElementById('settings-monitor-interval').value = s.monitorIntervalSec ?? 3;
+    document.getElementById('settings-flap-threshold').value = s.flapThreshold ?? 5;
+    document.getElementById('settings-flap-window').value = s.flapWindowMin ?? 10;
+    document.getElementById('settings-flap-hold').value = s.flapHoldMin ?? 15;
+    document.getElementById('settings-notify-after').value = s.notifyAfterFailures ?? 1;
+    document.getElementById('settings-notify-throttle').value = s.notifyThrottleSec ?? 60;
+    document.getElementById('settings-startup-grace').value = s.startupGraceSec ?? 20;
+    document.getElementById('settings-config-backups').value = s.configBackups ?? 10;
     document.getElementById('settings-autostart').checked = s.startWithWindows || false;
+    document.getElementById('settings-encrypt-config').checked = s.encryptConfig || false;
     document.getElementById('settings-minimized').checked = s.startMinimized !== false;
     document.getElementById('settings-notify-disconnect').checked = s.notifyOnDisconnect !== false;
     document.getElementById('settings-notify-reconnect').checked = s.notifyOnReconnect !== false;
+    document.getElementById('settings-hide-dock').checked = s.hideDockIcon || false;
+    loadNetworkCondition('settings-net', s.autoConnectOn);
     document.getElementById('modal-settings').style.display = '';
 }
 
@@ -258,14 +1091,31 @@ async function saveSettings(e) {
     e.preventDefault();
 
     const settings = {
+        // Keep settings the form doesn't edit
+        ...config.settings,
         plinkPath: document.getElementById('settings-plink').value.trim(),
         startWithWindows: document.getElementById('settings-autostart').checked,
+        encryptConfig: document.getElementById('settings-encrypt-config').checked,
         startMinimized: document.getElementById('settings-minimized').checked,
         reconnectDelaySec: parseInt(document.getElementById('settings-reconnect').value) || 5,
         maxReconnectAttempts: parseInt(document.getElementById('settings-maxretry').value) || 0,
+        startConcurrency: parseInt(document.getElementById('settings-concurrency').value) || 4,
+        maxLogLines: parseInt(document.getElementById('settings-maxlogs').value) || DEFAULT_MAX_LOGS,
+        stopGraceSec: parseInt(document.getElementById('settings-stopgrace').value) || 0,
+        keepaliveSec: parseInt(document.getElementById('settings-keepalive').value) || 0,
+        monitorIntervalSec: parseFloat(document.getElementById('settings-monitor-interval').value) || 3,
+        flapThreshold: parseInt(document.getElementById('settings-flap-threshold').value) || 0,
+        flapWindowMin: parseInt(document.getElementById('settings-flap-window').value) || 10,
+        flapHoldMin: parseInt(document.getElementById('settings-flap-hold').value) || 15,
+        notifyAfterFailures: parseInt(document.getElementById('settings-notify-after').value) || 1,
+        notifyThrottleSec: parseInt(document.getElementById('settings-notify-throttle').value) || 0,
+        startupGraceSec: parseInt(document.getElementById('settings-startup-grace').value) || 0,
+        configBackups: parseInt(document.getElementById('settings-config-backups').value) || 0,
         theme: 'dark',
         notifyOnDisconnect: document.getElementById('settings-notify-disconnect').checked,
         notifyOnReconnect: document.getElementById('settings-notify-reconnect').checked,
+        hideDockIcon: document.getElementById('settings-hide-dock').checked,
+        autoConnectOn: formNetworkCondition('settings-net'),
     };
 
     try {
@@ -306,19 +1156,70 @@ async function importPuTTY() {
 
 // ── Logs ──
 
-function addLog(tunnelId, tunnelName, level, message) {
+// Status tooltip with the runtime facts of a tracked tunnel
+/** "retrying in 42s" until `at`, then "retrying now". */
+function retryEta(at) {
+    const secs = Math.ceil((Date.parse(at) - Date.now()) / 1000);
+    return secs > 0 ? `retrying in ${secs}s` : 'retrying now';
+}
+
+// Count down scheduled reconnects without re-rendering the cards
+setInterval(() => {
+    for (const el of document.querySelectorAll('.retry-eta')) {
+        el.textContent = retryEta(el.dataset.at);
+    }
+}, 1000);
+
+function statusTitle(status, state) {
+    const facts = [status];
+    if (state?.localEndpoint) facts.push(`listening on ${state.localEndpoint}`);
+    if (state?.sshTarget) facts.push(`via ${state.sshTarget}`);
+    if (state?.pid) facts.push(`pid ${state.pid}`);
+    if (state?.uptimeSec != null) {
+        const h = Math.floor(state.uptimeSec / 3600);
+        const m = Math.floor(state.uptimeSec % 3600 / 60);
+        facts.push(`up ${h}h${String(m).padStart(2, '0')}`);
+    }
+    return facts.join(' · ');
+}
+
+function addLog(tunnelId, tunnelName, level, message, stream) {
     const now = new Date().toLocaleTimeString('fr-FR', { hour12: false });
-    const entry = { timestamp: now, tunnelId, tunnelName, level, message };
+    const entry = { timestamp: now, tunnelId, tunnelName, level, message, stream };
 
+    const maxLogs = config.settings.maxLogLines || DEFAULT_MAX_LOGS;
     logs.push(entry);
-    if (logs.length > MAX_LOGS) logs.shift();
+    if (logs.length > maxLogs) logs.splice(0, logs.length - maxLogs);
 
     const content = document.getElementById('log-content');
     const cls = level === 'error' ? ' error' : level === 'success' ? ' success' : '';
-    content.innerHTML += `<div class="log-entry${cls}"><span class="timestamp">[${now}]</span> <span class="tunnel-tag">[${escapeHtml(tunnelName)}]</span> ${escapeHtml(message)}</div>`;
+    content.insertAdjacentHTML('beforeend', `<div class="log-entry${cls}"><span class="timestamp">[${now}]</span> <span class="tunnel-tag">[${escapeHtml(tunnelName)}${stream === 'stdout' ? ' stdout' : ''}]</span> ${escapeHtml(message)}</div>`);
+    // Keep the panel as bounded as the log array
+    while (content.childElementCount > maxLogs) {
+        content.firstElementChild.remove();
+    }
     content.scrollTop = content.scrollHeight;
 }
 
+async function exportLogs() {
+    const btn = document.getElementById('btn-export-logs');
+    btn.disabled = true;
+    const unlisten = await listen('log-export-progress', (event) => {
+        const { written, total } = event.payload;
+        btn.textContent = total > 0 ? `${Math.floor((written / total) * 100)}%` : 'Export';
+    });
+    try {
+        const path = await invoke('export_logs', { path: null });
+        addLog('system', 'OpenTunnel', 'success', `Logs exported to ${path}`);
+    } catch (e) {
+        addLog('system', 'OpenTunnel', 'error', `Log export failed: ${e}`);
+    } finally {
+        unlisten();
+        btn.textContent = 'Export';
+        btn.disabled = false;
+    }
+}
+
 function clearLogs() {
     logs = [];
     document.getElementById('log-content').innerHTML = '';
@@ -333,17 +1234,44 @@ function toggleLogs() {
 function setupEventListeners() {
     document.getElementById('btn-add').addEventListener('click', () => openTunnelModal());
     document.getElementById('btn-settings').addEventListener('click', openSettings);
+    document.getElementById('btn-expose').addEventListener('click', openExpose);
+    document.getElementById('btn-expose-close').addEventListener('click', closeExpose);
+    document.getElementById('btn-expose-cancel').addEventListener('click', closeExpose);
+    document.getElementById('expose-form').addEventListener('submit', exposePort);
     document.getElementById('btn-import').addEventListener('click', importPuTTY);
+    document.getElementById('profile-select').addEventListener('change', switchProfile);
+
+    let filterTimer;
+    document.getElementById('tunnel-filter').addEventListener('input', () => {
+        clearTimeout(filterTimer);
+        filterTimer = setTimeout(applyFilter, 200);
+    });
 
     document.getElementById('btn-start-all').addEventListener('click', async () => {
         try {
-            await invoke('start_all_tunnels');
-            addLog('system', 'OpenTunnel', 'success', 'All tunnels started');
+            const results = await invoke('start_all_tunnels');
+            const failed = results.filter(r => !r.ok);
+            for (const r of failed) {
+                addLog(r.tunnelId, r.tunnelName, 'error', `Start failed: ${r.error}`);
+            }
+            if (failed.length === 0) {
+                addLog('system', 'OpenTunnel', 'success', 'All tunnels started');
+            } else {
+                addLog('system', 'OpenTunnel', 'error', `${results.length - failed.length} of ${results.length} tunnels started`);
+            }
         } catch (e) {
             addLog('system', 'OpenTunnel', 'error', `Start all failed: ${e}`);
         }
     });
 
+    document.getElementById('btn-pause').addEventListener('click', async () => {
+        try {
+            await invoke(monitorPaused ? 'resume_monitor' : 'pause_monitor');
+        } catch (e) {
+            addLog('system', 'OpenTunnel', 'error', `Failed to change auto-reconnect: ${e}`);
+        }
+    });
+
     document.getElementById('btn-stop-all').addEventListener('click', async () => {
         try {
             await invoke('stop_all_tunnels');
@@ -359,6 +1287,14 @@ function setupEventListeners() {
     document.getElementById('btn-cancel').addEventListener('click', closeTunnelModal);
     document.getElementById('tunnel-auth').addEventListener('change', updateFormVisibility);
     document.getElementById('tunnel-type').addEventListener('change', updateFormVisibility);
+    document.getElementById('tunnel-template').addEventListener('change', applyTemplate);
+    document.getElementById('tunnel-template-server').addEventListener('change', applyTemplate);
+    document.getElementById('btn-save-template').addEventListener('click', saveAsTemplate);
+    document.getElementById('btn-delete-template').addEventListener('click', deleteTemplate);
+    document.getElementById('tunnel-provider').addEventListener('change', updateFormVisibility);
+    document.getElementById('tunnel-transport').addEventListener('change', updateFormVisibility);
+    document.getElementById('tunnel-schedule').addEventListener('change', updateFormVisibility);
+    document.getElementById('tunnel-probe').addEventListener('change', updateFormVisibility);
 
     // Settings modal
     document.getElementById('settings-form').addEventListener('submit', saveSettings);
@@ -366,6 +1302,7 @@ function setupEventListeners() {
     document.getElementById('btn-settings-cancel').addEventListener('click', closeSettings);
 
     // Logs
+    document.getElementById('btn-export-logs').addEventListener('click', exportLogs);
     document.getElementById('btn-clear-logs').addEventListener('click', clearLogs);
     document.getElementById('btn-toggle-logs').addEventListener('click', toggleLogs);
 